@@ -3,9 +3,14 @@ use std::fs::File;
 use std::io::Read;
 use std::{thread, time};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc;
 use tokio::time::{Duration, timeout};
-use utils::packet::{FlagState, Packet, PacketHeader, deserialize_packet, serialize_packet};
+use utils::advisory::{Advisory, Sense, TrafficAdvisory};
+use utils::flight_plan::FlightPlan;
+use utils::packet::{FlagState, Packet, PacketHeader, forward_packets, serialize_packet};
+use utils::report::Report;
 use utils::vector::Vector3;
+use utils::wake::{WakeAdvisory, WakeCategory};
 
 #[tokio::main]
 async fn main() {
@@ -22,6 +27,21 @@ async fn main() {
         args[7].clone().parse::<f32>().unwrap(),
     );
     let plane_speed = args[8].clone().parse::<f32>().unwrap();
+    // Optional wake category, medium unless given, declared to the server on connect.
+    let category = args.get(9).and_then(|arg| WakeCategory::from_name(arg));
+    let route_args = if category.is_some() { 10 } else { 9 };
+    let category = category.unwrap_or(WakeCategory::MEDIUM);
+    // Optional waypoints to fly through on the way, as x y z triples.
+    let waypoints: Vec<Vector3> = args[route_args.min(args.len())..]
+        .chunks_exact(3)
+        .map(|p| {
+            Vector3::new(
+                p[0].parse::<f32>().unwrap(),
+                p[1].parse::<f32>().unwrap(),
+                p[2].parse::<f32>().unwrap(),
+            )
+        })
+        .collect();
 
     let mut plane_pos = start_pos;
 
@@ -37,7 +57,7 @@ async fn main() {
 
     // Connect to server
     tracing::info!("Connecting to server...");
-    let mut stream = match TcpStream::connect("127.0.0.1:8001").await {
+    let stream = match TcpStream::connect("127.0.0.1:8001").await {
         Ok(listener) => listener,
         Err(_) => {
             tracing::error!("Unable to connect to server...\nExiting now...");
@@ -45,14 +65,24 @@ async fn main() {
         }
     };
     tracing::info!("Connected to server!");
+    // The server sends alerts whenever it has them, so packets are read on their own task.
+    let (reader, mut stream) = stream.into_split();
+    let (sender, mut inbound) = mpsc::channel(16);
+    tokio::spawn(forward_packets(reader, sender));
 
-    // Send empty packet with plane id
-    let mut pkt = Packet::init();
-    pkt.header = PacketHeader {
-        flag: FlagState::COORDINATE,
-        body_size: 0,
-        plane_id: client_id,
-        seq_len: 0,
+    // Send packet with plane id, departure position, wake category and session token
+    tracing::info!("Declaring wake category {category}");
+    let mut body = Report::now(start_pos).to_bytes();
+    body.push(category as u8);
+    body.extend_from_slice(&session_token().to_be_bytes());
+    let pkt = Packet {
+        header: PacketHeader {
+            flag: FlagState::COORDINATE,
+            body_size: body.len() as u16,
+            plane_id: client_id,
+            seq_len: 0,
+        },
+        body,
     };
     // Serialize and send packet
     if let Err(e) = serialize_packet(pkt, &mut stream).await {
@@ -60,19 +90,114 @@ async fn main() {
         return;
     }
 
+    // File the flight plan so the server can probe the route ahead.
+    let plan = FlightPlan::new(start_pos, end_pos, waypoints.clone(), plane_speed);
+    tracing::info!("Filing flight plan {plan}");
+    let body = plan.to_bytes();
+    let pkt = Packet {
+        header: PacketHeader {
+            flag: FlagState::FLIGHT_PLAN,
+            body_size: body.len() as u16,
+            plane_id: client_id,
+            seq_len: 0,
+        },
+        body,
+    };
+    if let Err(e) = serialize_packet(pkt.clone(), &mut stream).await {
+        tracing::error!("Error sending packet: {e}");
+        return;
+    }
+
+    // Wait on the ground until the server releases the departure. The flight plan is filed again
+    // every second while waiting, so the server knows the client is still there.
+    loop {
+        let received = match timeout(Duration::from_secs(1), inbound.recv()).await {
+            Ok(Some(received)) => received,
+            Ok(None) => return,
+            Err(_) => {
+                if let Err(e) = serialize_packet(pkt.clone(), &mut stream).await {
+                    tracing::error!("Error sending packet: {e}");
+                    return;
+                }
+                continue;
+            }
+        };
+        match received {
+            Ok(p) if p.header.flag == FlagState::RELEASE => {
+                tracing::info!("Departure released");
+                break;
+            }
+            Ok(p) if p.header.flag == FlagState::HOLD => {
+                tracing::info!("Departure held");
+            }
+            Ok(p) if p.header.flag == FlagState::SERVER_SHUTDOWN => {
+                tracing::warn!("Server shutting down, staying on the ground");
+                return;
+            }
+            Ok(p) if p.header.flag == FlagState::DISCONNECTED => {
+                tracing::warn!("Disconnected by the operator, staying on the ground");
+                return;
+            }
+            Ok(p) if p.header.flag == FlagState::REJECTED => {
+                tracing::error!("Rejected, another client is flying plane {client_id}");
+                return;
+            }
+            Ok(p) => tracing::info!("Ignoring packet while waiting for departure: {p}"),
+            Err(e) => {
+                tracing::error!("Error waiting for departure release: {e}");
+                return;
+            }
+        }
+    }
+
+    // Resolution advisory currently being followed. Its altitude is held until the server says
+    // the aircraft is clear of conflict.
+    let mut advisory: Option<Advisory> = None;
+    // Altitude the aircraft was flying before its first advisory, returned to once clear. Kept
+    // until the aircraft is back at it, so an advisory interrupting the return is followed by
+    // another return.
+    let mut cleared_altitude: Option<f32> = None;
+    // Whether the advisory being followed is the return to the cleared altitude.
+    let mut returning = false;
+    // Waypoints not reached yet, the next one first.
+    let mut route = waypoints.into_iter();
+    let mut target = route.next().unwrap_or(end_pos);
+
     loop {
         //move aircraft
-        plane_pos = plane_pos.add(plane_pos.displacement_vector(end_pos, plane_speed));
+        match advisory {
+            Some(adv) => {
+                // Keep flying along the route, but let the advisory drive the altitude.
+                let level_target = Vector3::new(target.x, target.y, plane_pos.z);
+                plane_pos = plane_pos.add(plane_pos.displacement_vector(level_target, plane_speed));
+                plane_pos.z = adv.step(plane_pos.z);
+                if returning && plane_pos.z == adv.target_altitude {
+                    tracing::info!("Back at cleared altitude {}, resuming profile", plane_pos.z);
+                    advisory = None;
+                    cleared_altitude = None;
+                    returning = false;
+                }
+            }
+            None => {
+                plane_pos = plane_pos.add(plane_pos.displacement_vector(target, plane_speed));
+            }
+        }
         tracing::info!("{client_id} moved to {plane_pos}");
 
+        // Turn towards the next point of the route once the waypoint is reached.
+        if target != end_pos && Vector3::distance(plane_pos, target) <= plane_speed {
+            tracing::info!("Passed waypoint {target}");
+            target = route.next().unwrap_or(end_pos);
+        }
+
         // if distance to destination is less than A VALUE (idk what) (probably unhardcode this)
         if Vector3::distance(plane_pos, end_pos) <= 1.0 {
             tracing::info!("Landing now, close to destination");
             break;
         }
 
-        // Initialize packet
-        let body = plane_pos.to_bytes();
+        // Initialize packet, stamped with the time the position was measured.
+        let body = Report::now(plane_pos).to_bytes();
         let header = PacketHeader {
             flag: FlagState::COORDINATE,
             plane_id: client_id,
@@ -90,25 +215,65 @@ async fn main() {
         //send data
         tracing::info!("Packet sent...");
 
-        // Check for collision warning, set altitude accordingly.
-        match timeout(Duration::from_secs(1), deserialize_packet(&mut stream)).await {
-            Ok(Ok(p)) => {
-                tracing::info!("Deserialized packet: {p}");
-                if p.header.flag == FlagState::COLLISION {
-                    if let Some(new_altitude) = Vector3::from_bytes(p.body.as_slice()) {
-                        plane_pos.z = new_altitude.z;
-                        tracing::info!("Set altitude to: {}", new_altitude.z);
-                    } else {
-                        tracing::error!("Unable to create Vector3 from bytes...")
+        // Check for collision warnings until the next step, set altitude accordingly.
+        let window = tokio::time::sleep(Duration::from_secs(1));
+        tokio::pin!(window);
+        loop {
+            let p = tokio::select! {
+                _ = &mut window => break,
+                received = inbound.recv() => match received {
+                    Some(Ok(p)) => p,
+                    Some(Err(e)) => {
+                        tracing::error!("Error deserializing COLLISION packet: {e}");
+                        return;
                     }
+                    None => return,
+                },
+            };
+            tracing::info!("Deserialized packet: {p}");
+            if p.header.flag == FlagState::COLLISION {
+                if let Some(new_advisory) = Advisory::from_bytes(p.body.as_slice()) {
+                    tracing::info!("Following advisory: {}", new_advisory);
+                    cleared_altitude.get_or_insert(plane_pos.z);
+                    advisory = Some(new_advisory);
+                    returning = false;
+                } else {
+                    tracing::error!("Unable to create Advisory from bytes...")
                 }
-            }
-            Ok(Err(e)) => {
-                tracing::error!("Error deserializing COLLISION packet: {e}");
+            } else if p.header.flag == FlagState::CLEAR_OF_CONFLICT {
+                // Return to the cleared altitude at the advisory rate, then fly the profile.
+                if let (Some(altitude), Some(adv)) = (cleared_altitude, advisory) {
+                    tracing::info!("Clear of conflict, returning to {}", altitude);
+                    let sense = if altitude >= plane_pos.z {
+                        Sense::CLIMB
+                    } else {
+                        Sense::DESCEND
+                    };
+                    advisory = Some(Advisory::new(sense, altitude, adv.vertical_rate));
+                    returning = true;
+                }
+            } else if p.header.flag == FlagState::TRAFFIC {
+                // Awareness only, keep flying the current profile.
+                if let Some(traffic) = TrafficAdvisory::from_bytes(p.body.as_slice()) {
+                    tracing::warn!("Traffic advisory: {}", traffic);
+                } else {
+                    tracing::error!("Unable to create TrafficAdvisory from bytes...")
+                }
+            } else if p.header.flag == FlagState::WAKE {
+                // Awareness only, the spacing is kept by the arrival sequence.
+                if let Some(wake) = WakeAdvisory::from_bytes(p.body.as_slice()) {
+                    tracing::warn!("Wake turbulence: {}", wake);
+                } else {
+                    tracing::error!("Unable to create WakeAdvisory from bytes...")
+                }
+            } else if p.header.flag == FlagState::SERVER_SHUTDOWN {
+                tracing::warn!("Server shutting down, leaving the sector");
+                return;
+            } else if p.header.flag == FlagState::DISCONNECTED {
+                tracing::warn!("Disconnected by the operator, leaving the sector");
                 return;
             }
-            _ => {}
-        };
+        }
 
         //wait for 5 seconds
         let ten_millis = time::Duration::from_secs(1);
@@ -177,3 +342,17 @@ async fn main() {
     let ten_millis = time::Duration::from_secs(5);
     thread::sleep(ten_millis);
 }
+
+/// Token proving the aircraft is the one flying its live session on the server, so it can take
+/// the session over when it reconnects. Taken from AIRCRAFT_TOKEN, or made up for this run.
+fn session_token() -> u64 {
+    env::var("AIRCRAFT_TOKEN")
+        .ok()
+        .and_then(|token| token.parse().ok())
+        .unwrap_or_else(|| {
+            let now = time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .unwrap_or_default();
+            now.as_nanos() as u64 ^ (std::process::id() as u64) << 32
+        })
+}
@@ -0,0 +1,126 @@
+//! Time one full detection tick at increasing traffic volumes: conflict detection, clustering,
+//! resolution, zones, wake and alert filtering.
+//! Run with `cargo bench -p server`. Fails if a tick does not fit in the configured interval.
+use server::alerting::AlertFilter;
+use server::config::Config;
+use server::detector::{PlaneId, TrackState};
+use server::encounter::Encounters;
+use server::manager::{Categories, Manager, SectorStatus};
+use server::zone::{Shape, Zone, ZoneKind};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Mutex;
+use utils::vector::Vector3;
+use utils::wake::WakeCategory;
+
+const RUNS: usize = 5;
+
+/// Small linear congruential generator so runs are reproducible without extra crates.
+struct Lcg(u64);
+
+impl Lcg {
+    /// Uniform value in [0, 1).
+    fn next(&mut self) -> f32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Side of the simulated airspace, which grows with the number of aircraft to keep the density
+/// constant.
+fn side(count: usize) -> f32 {
+    (count as f32).sqrt() * 10.0
+}
+
+/// Simulated traffic with constant density.
+/// Ids are u8 on the wire, so they wrap around in large fleets. Aircraft sharing an id share
+/// their alert bookkeeping, which changes what is reported but not the work done.
+fn simulate(count: usize, rng: &mut Lcg) -> Vec<TrackState> {
+    let side = side(count);
+    (0..count)
+        .map(|i| {
+            let heading = rng.next() * std::f32::consts::TAU;
+            TrackState {
+                id: (i % 256) as PlaneId,
+                position: Vector3::new(
+                    rng.next() * side,
+                    rng.next() * side,
+                    (rng.next() * 40.0).floor() * 1000.0,
+                ),
+                velocity: Vector3::new(heading.cos() * 5.0, heading.sin() * 5.0, 0.0),
+                uncertainty: 0.1,
+            }
+        })
+        .collect()
+}
+
+/// One restricted cylinder per hundred aircraft, spread over the airspace.
+fn zones(count: usize, rng: &mut Lcg) -> Vec<Zone> {
+    let side = side(count);
+    (0..count.div_ceil(100))
+        .map(|i| Zone {
+            name: format!("R-{}", i),
+            kind: ZoneKind::Restricted,
+            floor: 0.0,
+            ceiling: (rng.next() * 20.0).floor() * 1000.0 + 1000.0,
+            shape: Shape::Cylinder {
+                center: [rng.next() * side, rng.next() * side],
+                radius: 3.0,
+            },
+        })
+        .collect()
+}
+
+fn main() -> Result<(), String> {
+    let config = Config::default();
+    let tick = config.timing.tick();
+    let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+    let mut rng = Lcg(74000);
+    for count in [100, 1_000, 10_000] {
+        let states = simulate(count, &mut rng);
+        let zones = zones(count, &mut rng);
+        let categories: Categories = Arc::new(Mutex::new(
+            (0..=PlaneId::MAX)
+                .map(|id| (id, WakeCategory::ALL[id as usize % WakeCategory::ALL.len()]))
+                .collect::<HashMap<_, _>>(),
+        ));
+        let sectors: SectorStatus = Arc::new(Mutex::new(Default::default()));
+        let mut encounters = Encounters::default();
+        let mut filter = AlertFilter::default();
+        let mut times = Vec::new();
+        let mut alerts = 0;
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            alerts = runtime
+                .block_on(Manager::process_states(
+                    &states,
+                    &config,
+                    &zones,
+                    &sectors,
+                    &categories,
+                    &mut encounters,
+                    &mut filter,
+                ))
+                .len();
+            times.push(start.elapsed());
+        }
+        times.sort();
+        let median = times[RUNS / 2];
+        println!(
+            "{:>6} aircraft: median tick {:>10.3?}, worst {:>10.3?}, {} alerts on the last",
+            count,
+            median,
+            times[RUNS - 1],
+            alerts
+        );
+
+        if times[RUNS - 1] >= tick {
+            return Err(format!("Tick for {} aircraft exceeded {:?}", count, tick));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,520 @@
+use crate::detector::{Conflict, PlaneId};
+use crate::wake::WakeViolation;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use utils::advisory::{Advisory, TrafficAdvisory};
+use utils::packet::{FlagState, Packet, PacketHeader};
+use utils::wake::WakeAdvisory;
+
+/// Time to closest approach, in cycles, under which each alert tier is raised,
+/// and how far past them a pair must get before its alert is lowered.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertThresholds {
+    /// Raise a traffic advisory at or under this time.
+    pub traffic: u32,
+    /// Raise a resolution advisory at or under this time. Must be below `traffic`.
+    pub resolution: u32,
+    /// Cycles past a threshold the closest approach must move before a pair drops below its tier.
+    pub exit_margin: u32,
+    /// Distance past the detector tolerance an alerted pair must separate by to leave conflict.
+    pub exit_buffer: f32,
+    /// Ticks a pair stays at least at a tier once it is raised.
+    pub min_dwell: u32,
+}
+
+/// Severity of the alert raised for a conflict, ordered by severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlertTier {
+    /// Awareness only, the aircraft is not asked to move.
+    Traffic,
+    /// The aircraft is commanded to manoeuvre.
+    Resolution,
+}
+
+/// An alert addressed to a single aircraft.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alert {
+    Traffic(TrafficAdvisory),
+    Resolution(Advisory),
+    /// Every encounter the aircraft was advised for is over, it may resume its cleared profile.
+    Clear,
+    /// The aircraft is closer behind a leader than their wake categories allow.
+    Wake(WakeAdvisory),
+}
+
+impl AlertThresholds {
+    /// Tier of the alert a conflict calls for, if any.
+    pub fn classify(&self, conflict: &Conflict) -> Option<AlertTier> {
+        if conflict.time <= self.resolution {
+            Some(AlertTier::Resolution)
+        } else if conflict.time <= self.traffic {
+            Some(AlertTier::Traffic)
+        } else {
+            None
+        }
+    }
+
+    /// Tier an alerted conflict keeps, with the thresholds moved out by the exit margin.
+    pub fn classify_exit(&self, conflict: &Conflict) -> Option<AlertTier> {
+        if conflict.time <= self.resolution + self.exit_margin {
+            Some(AlertTier::Resolution)
+        } else if conflict.time <= self.traffic + self.exit_margin {
+            Some(AlertTier::Traffic)
+        } else {
+            None
+        }
+    }
+}
+
+/// Alert state of a pair of aircraft, kept between ticks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PairAlert {
+    tier: AlertTier,
+    /// Tick the pair reached its tier.
+    since: u64,
+    /// Last conflict detected between the pair.
+    conflict: Conflict,
+}
+
+/// Hysteresis, dwell time and duplicate suppression for the alerts of successive ticks, so that
+/// pairs hovering around a threshold are not alerted, dropped and alerted again.
+#[derive(Debug, Clone, Default)]
+pub struct AlertFilter {
+    tick: u64,
+    pairs: HashMap<(PlaneId, PlaneId), PairAlert>,
+    /// Last resolution advisory sent to each aircraft.
+    advised: HashMap<PlaneId, Advisory>,
+    /// Aircraft and the intruders they were told about.
+    notified: HashSet<(PlaneId, PlaneId)>,
+    /// Followers and the leaders whose wake they were told about.
+    waked: HashSet<(PlaneId, PlaneId)>,
+    /// Aircraft whose clearance could not be queued, to be cleared again.
+    unsent: HashSet<PlaneId>,
+}
+
+impl AlertFilter {
+    /// Advance one tick and decide the tier of every pair.
+    /// `conflicts` are detected within the tolerance widened by the exit buffer, and `entering`
+    /// tells whether one is also within the detector tolerance. A pair is raised as soon as it
+    /// enters a higher tier, and lowered only once it is past the exit thresholds and has dwelt at
+    /// its tier.
+    /// Returns the alerting pairs with their last conflict and tier, ordered by pair.
+    pub fn update(
+        &mut self,
+        conflicts: &[Conflict],
+        entering: impl Fn(&Conflict) -> bool,
+        thresholds: &AlertThresholds,
+    ) -> Vec<(Conflict, AlertTier)> {
+        self.tick += 1;
+        let mut keys: Vec<(PlaneId, PlaneId)> = conflicts
+            .iter()
+            .map(|c| (c.a, c.b))
+            .chain(self.pairs.keys().copied())
+            .collect();
+        keys.sort();
+        keys.dedup();
+
+        let mut alerting = Vec::new();
+        for key in keys {
+            let conflict = conflicts.iter().find(|c| (c.a, c.b) == key);
+            let entry = conflict
+                .filter(|c| entering(c))
+                .and_then(|c| thresholds.classify(c));
+            let exit = conflict.and_then(|c| thresholds.classify_exit(c));
+            let held = self.pairs.get(&key).copied();
+            let tier = match held {
+                None => entry,
+                Some(held) => {
+                    let dwelt = self.tick - held.since >= thresholds.min_dwell as u64;
+                    if entry > Some(held.tier) {
+                        entry
+                    } else if exit >= Some(held.tier) || !dwelt {
+                        Some(held.tier)
+                    } else {
+                        entry.max(exit)
+                    }
+                }
+            };
+
+            match (tier, conflict.copied().or(held.map(|h| h.conflict))) {
+                (Some(tier), Some(conflict)) => {
+                    let since = match held {
+                        Some(held) if held.tier == tier => held.since,
+                        _ => self.tick,
+                    };
+                    self.pairs.insert(
+                        key,
+                        PairAlert {
+                            tier,
+                            since,
+                            conflict,
+                        },
+                    );
+                    alerting.push((conflict, tier));
+                }
+                _ => {
+                    self.pairs.remove(&key);
+                    self.notified.remove(&key);
+                    self.notified.remove(&(key.1, key.0));
+                }
+            }
+        }
+        alerting
+    }
+
+    /// Check whether an alert tells an aircraft something new, and remember it if so.
+    /// The same resolution advisory is not sent twice in a row, and a traffic advisory is sent
+    /// once per intruder while their pair is alerting, a wake advisory once per leader while the
+    /// follower stays in its wake. Clearing an aircraft forgets its advisory, and a new
+    /// advisory supersedes a clearance still to be sent again.
+    pub fn fresh(&mut self, plane_id: PlaneId, alert: &Alert) -> bool {
+        match alert {
+            Alert::Traffic(traffic) => self.notified.insert((plane_id, traffic.intruder)),
+            Alert::Wake(wake) => self.waked.insert((plane_id, wake.leader)),
+            Alert::Resolution(advisory) => {
+                self.unsent.remove(&plane_id);
+                self.advised.insert(plane_id, *advisory) != Some(*advisory)
+            }
+            Alert::Clear => {
+                self.unsent.remove(&plane_id);
+                self.advised.remove(&plane_id);
+                true
+            }
+        }
+    }
+
+    /// Forget an alert that could not be delivered, so that it is news again next tick.
+    /// A clearance is only ever raised once, so it is kept to be sent again instead.
+    pub fn undelivered(&mut self, plane_id: PlaneId, alert: &Alert) {
+        match alert {
+            Alert::Traffic(traffic) => {
+                self.notified.remove(&(plane_id, traffic.intruder));
+            }
+            Alert::Wake(wake) => {
+                self.waked.remove(&(plane_id, wake.leader));
+            }
+            Alert::Resolution(advisory) => {
+                if self.advised.get(&plane_id) == Some(advisory) {
+                    self.advised.remove(&plane_id);
+                }
+            }
+            Alert::Clear => {
+                self.unsent.insert(plane_id);
+            }
+        }
+    }
+
+    /// Forget everything sent to or about an aircraft, once it has left or another client has
+    /// taken it over, so that nothing is held back from whoever flies it next.
+    pub fn forget(&mut self, plane_id: PlaneId) {
+        self.advised.remove(&plane_id);
+        self.unsent.remove(&plane_id);
+        self.notified
+            .retain(|(to, about)| *to != plane_id && *about != plane_id);
+        self.waked
+            .retain(|(follower, leader)| *follower != plane_id && *leader != plane_id);
+    }
+
+    /// Aircraft whose clearance could not be queued yet, ordered by id.
+    pub fn unsent(&self) -> Vec<PlaneId> {
+        let mut unsent: Vec<PlaneId> = self.unsent.iter().copied().collect();
+        unsent.sort();
+        unsent
+    }
+
+    /// Forget the wake advisories of followers that are out of their leader's wake, so that
+    /// entering it again is news. The wake is widened by the exit buffer, so that a follower
+    /// hovering at the minimum is not told again and again.
+    pub fn expire_wakes(&mut self, violations: &[WakeViolation]) {
+        self.waked.retain(|(follower, leader)| {
+            violations
+                .iter()
+                .any(|v| (v.follower, v.leader) == (*follower, *leader))
+        });
+    }
+}
+
+/// Traffic advisories telling each aircraft of a conflict about the other one.
+pub fn traffic_advisories(conflict: &Conflict) -> [(PlaneId, Alert); 2] {
+    let time = conflict.time as f32;
+    [
+        (
+            conflict.a,
+            Alert::Traffic(TrafficAdvisory::new(conflict.b, conflict.cpa, time)),
+        ),
+        (
+            conflict.b,
+            Alert::Traffic(TrafficAdvisory::new(conflict.a, conflict.cpa, time)),
+        ),
+    ]
+}
+
+impl Alert {
+    /// Build the packet delivering this alert to an aircraft.
+    pub fn to_packet(&self, plane_id: PlaneId) -> Packet {
+        let (flag, body) = match self {
+            Alert::Traffic(traffic) => (FlagState::TRAFFIC, traffic.to_bytes()),
+            Alert::Resolution(advisory) => (FlagState::COLLISION, advisory.to_bytes()),
+            Alert::Clear => (FlagState::CLEAR_OF_CONFLICT, Vec::new()),
+            Alert::Wake(wake) => (FlagState::WAKE, wake.to_bytes()),
+        };
+        Packet {
+            header: PacketHeader {
+                flag,
+                plane_id,
+                body_size: body.len() as u16,
+                seq_len: 0,
+            },
+            body,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::advisory::Sense;
+
+    const THRESHOLDS: AlertThresholds = AlertThresholds {
+        traffic: 6,
+        resolution: 3,
+        exit_margin: 1,
+        exit_buffer: 1.0,
+        min_dwell: 2,
+    };
+
+    fn conflict(time: u32) -> Conflict {
+        Conflict {
+            a: 1,
+            b: 2,
+            cpa: 0.5,
+            time,
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(
+            THRESHOLDS.classify(&conflict(2)),
+            Some(AlertTier::Resolution)
+        );
+        assert_eq!(
+            THRESHOLDS.classify(&conflict(3)),
+            Some(AlertTier::Resolution)
+        );
+        assert_eq!(THRESHOLDS.classify(&conflict(4)), Some(AlertTier::Traffic));
+        assert_eq!(THRESHOLDS.classify(&conflict(6)), Some(AlertTier::Traffic));
+        assert_eq!(THRESHOLDS.classify(&conflict(7)), None);
+    }
+
+    #[test]
+    fn test_classify_exit() {
+        assert_eq!(
+            THRESHOLDS.classify_exit(&conflict(4)),
+            Some(AlertTier::Resolution)
+        );
+        assert_eq!(
+            THRESHOLDS.classify_exit(&conflict(7)),
+            Some(AlertTier::Traffic)
+        );
+        assert_eq!(THRESHOLDS.classify_exit(&conflict(8)), None);
+    }
+
+    /// Tier of the pair after each tick, given the time to closest approach of each tick
+    /// and whether the pair is within the entry tolerance.
+    fn tiers(ticks: &[(Option<u32>, bool)]) -> Vec<Option<AlertTier>> {
+        let mut filter = AlertFilter::default();
+        ticks
+            .iter()
+            .map(|(time, entering)| {
+                let conflicts: Vec<Conflict> = time.iter().map(|t| conflict(*t)).collect();
+                let alerting = filter.update(&conflicts, |_| *entering, &THRESHOLDS);
+                alerting.first().map(|(_, tier)| *tier)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_holds_tiers_around_time_thresholds() {
+        use AlertTier::{Resolution, Traffic};
+        // Closest approach oscillating across the resolution threshold, then drifting away.
+        let ticks = [3, 4, 3, 4, 4, 4, 5, 6, 7, 8].map(|t| (Some(t), true));
+
+        assert_eq!(
+            tiers(&ticks),
+            vec![
+                Some(Resolution),
+                Some(Resolution),
+                Some(Resolution),
+                Some(Resolution),
+                Some(Resolution),
+                Some(Resolution),
+                Some(Traffic),
+                Some(Traffic),
+                Some(Traffic),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_filter_holds_pairs_around_the_tolerance() {
+        use AlertTier::Traffic;
+        // In and out of the tolerance every tick while staying within the exit buffer,
+        // then out of both.
+        let ticks = [
+            (Some(5), true),
+            (Some(5), false),
+            (Some(5), true),
+            (Some(5), false),
+            (None, false),
+            (None, false),
+        ];
+
+        assert_eq!(
+            tiers(&ticks),
+            vec![
+                Some(Traffic),
+                Some(Traffic),
+                Some(Traffic),
+                Some(Traffic),
+                None,
+                None
+            ]
+        );
+        // A pair only within the exit buffer is not raised.
+        assert_eq!(tiers(&[(Some(5), false)]), vec![None]);
+        // A pair that leaves at once is held for the dwell time.
+        assert_eq!(
+            tiers(&[(Some(5), true), (None, false), (None, false)]),
+            vec![Some(Traffic), Some(Traffic), None]
+        );
+    }
+
+    #[test]
+    fn test_filter_escalates_without_waiting() {
+        use AlertTier::{Resolution, Traffic};
+        let ticks = [(Some(6), true), (Some(2), true)];
+
+        assert_eq!(tiers(&ticks), vec![Some(Traffic), Some(Resolution)]);
+    }
+
+    #[test]
+    fn test_filter_suppresses_duplicates() {
+        let mut filter = AlertFilter::default();
+        let climb = Alert::Resolution(Advisory::new(Sense::CLIMB, 1000.0, 500.0));
+        let higher = Alert::Resolution(Advisory::new(Sense::CLIMB, 2000.0, 500.0));
+        let traffic = |cpa| Alert::Traffic(TrafficAdvisory::new(2, cpa, 5.0));
+
+        assert!(filter.fresh(1, &climb));
+        assert!(!filter.fresh(1, &climb));
+        assert!(filter.fresh(1, &higher));
+        assert!(filter.fresh(1, &Alert::Clear));
+        assert!(filter.fresh(1, &higher));
+
+        filter.update(&[conflict(5)], |_| true, &THRESHOLDS);
+        assert!(filter.fresh(1, &traffic(0.5)));
+        assert!(!filter.fresh(1, &traffic(0.4)));
+        // Once the pair stops alerting, the next traffic advisory is news again.
+        for _ in 0..3 {
+            filter.update(&[], |_| true, &THRESHOLDS);
+        }
+        assert!(filter.fresh(1, &traffic(0.5)));
+
+        let wake = Alert::Wake(WakeAdvisory::new(2, 4.0, 3.0));
+        let violation = WakeViolation {
+            leader: 2,
+            follower: 1,
+            required: 4.0,
+            distance: 3.0,
+            time: 0,
+        };
+        assert!(filter.fresh(1, &wake));
+        filter.expire_wakes(&[violation]);
+        assert!(!filter.fresh(1, &wake));
+        filter.expire_wakes(&[]);
+        assert!(filter.fresh(1, &wake));
+    }
+
+    #[test]
+    fn test_filter_resends_undelivered_alerts() {
+        let mut filter = AlertFilter::default();
+        let climb = Alert::Resolution(Advisory::new(Sense::CLIMB, 1000.0, 500.0));
+        let traffic = Alert::Traffic(TrafficAdvisory::new(2, 0.5, 5.0));
+        assert!(filter.fresh(1, &climb) && filter.fresh(1, &traffic));
+
+        filter.undelivered(1, &climb);
+        filter.undelivered(1, &traffic);
+
+        assert!(filter.fresh(1, &climb));
+        assert!(filter.fresh(1, &traffic));
+        // A stale failure does not forget a newer advisory.
+        filter.undelivered(
+            1,
+            &Alert::Resolution(Advisory::new(Sense::DESCEND, 0.0, 500.0)),
+        );
+        assert!(!filter.fresh(1, &climb));
+
+        // A clearance is kept until it goes out, or a new advisory supersedes it.
+        filter.undelivered(1, &Alert::Clear);
+        filter.undelivered(2, &Alert::Clear);
+        assert_eq!(filter.unsent(), vec![1, 2]);
+        assert!(filter.fresh(1, &Alert::Clear));
+        assert!(filter.fresh(2, &climb));
+        assert!(filter.unsent().is_empty());
+    }
+
+    #[test]
+    fn test_filter_forgets_aircraft() {
+        let mut filter = AlertFilter::default();
+        let climb = Alert::Resolution(Advisory::new(Sense::CLIMB, 1000.0, 500.0));
+        let traffic = |intruder| Alert::Traffic(TrafficAdvisory::new(intruder, 0.5, 5.0));
+        assert!(filter.fresh(1, &climb) && filter.fresh(1, &traffic(2)));
+        let wake = Alert::Wake(WakeAdvisory::new(1, 4.0, 3.0));
+        assert!(filter.fresh(2, &traffic(1)) && filter.fresh(3, &traffic(2)));
+        assert!(filter.fresh(4, &wake));
+
+        filter.forget(1);
+
+        assert!(filter.fresh(1, &climb));
+        assert!(filter.fresh(1, &traffic(2)));
+        assert!(filter.fresh(2, &traffic(1)));
+        assert!(filter.fresh(4, &wake));
+        // Alerts between other aircraft are still remembered.
+        assert!(!filter.fresh(3, &traffic(2)));
+    }
+
+    #[test]
+    fn test_traffic_advisories() {
+        let [(a, a_alert), (b, b_alert)] = traffic_advisories(&conflict(5));
+
+        assert_eq!(
+            (a, a_alert),
+            (1, Alert::Traffic(TrafficAdvisory::new(2, 0.5, 5.0)))
+        );
+        assert_eq!(
+            (b, b_alert),
+            (2, Alert::Traffic(TrafficAdvisory::new(1, 0.5, 5.0)))
+        );
+    }
+
+    #[test]
+    fn test_to_packet() {
+        let advisory = Advisory::new(Sense::CLIMB, 1000.0, 500.0);
+
+        let traffic = Alert::Traffic(TrafficAdvisory::new(2, 0.5, 5.0)).to_packet(1);
+        let resolution = Alert::Resolution(advisory).to_packet(1);
+        let clear = Alert::Clear.to_packet(1);
+        let wake = Alert::Wake(WakeAdvisory::new(2, 4.0, 3.0)).to_packet(1);
+
+        assert_eq!(traffic.header.flag, FlagState::TRAFFIC);
+        assert_eq!(traffic.header.body_size as usize, TrafficAdvisory::size());
+        assert_eq!(resolution.header.flag, FlagState::COLLISION);
+        assert_eq!(resolution.body, advisory.to_bytes());
+        assert_eq!(clear.header.flag, FlagState::CLEAR_OF_CONFLICT);
+        assert_eq!(clear.header.body_size, 0);
+        assert_eq!(wake.header.flag, FlagState::WAKE);
+        assert_eq!(wake.header.body_size as usize, WakeAdvisory::size());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::detector::PlaneId;
+use crate::wake::WakeConfig;
+use utils::vector::Vector3;
+use utils::wake::WakeCategory;
+
+/// An aircraft following a flight plan to its destination.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Inbound {
+    pub id: PlaneId,
+    pub category: WakeCategory,
+    pub destination: Vector3,
+    /// Ticks until the aircraft reaches its destination along its plan.
+    pub eta: f32,
+    /// Planned distance flown per tick.
+    pub speed: f32,
+}
+
+/// Place of an aircraft in the arrival sequence of its destination.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arrival {
+    pub id: PlaneId,
+    pub category: WakeCategory,
+    pub destination: Vector3,
+    /// Aircraft landing just before this one, if any.
+    pub leader: Option<PlaneId>,
+    /// Ticks until the aircraft reaches its destination along its plan.
+    pub eta: f32,
+    /// Ticks until the aircraft may land, spaced behind its leader.
+    pub slot: f32,
+}
+
+impl Arrival {
+    /// Ticks the aircraft has to lose to keep its spacing behind the leader.
+    pub fn delay(&self) -> f32 {
+        self.slot - self.eta
+    }
+}
+
+/// Order the aircraft landing at each destination by their estimated arrival, and space each one
+/// behind the one before by the wake minimum of their categories, or by the separation when the
+/// pair needs none. The spacing is flown at the follower's speed.
+/// Destinations closer than the separation are one runway. Sequences are ordered by their first
+/// arrival, each from first to last.
+pub fn sequence(inbound: &[Inbound], wake: &WakeConfig, separation: f32) -> Vec<Arrival> {
+    let mut ordered = inbound.to_vec();
+    ordered.sort_by(|a, b| a.eta.total_cmp(&b.eta).then(a.id.cmp(&b.id)));
+
+    // Group the aircraft by runway, each group in arrival order.
+    let mut runways: Vec<Vec<Inbound>> = Vec::new();
+    for flight in ordered {
+        match runways
+            .iter_mut()
+            .find(|r| Vector3::distance(r[0].destination, flight.destination) < separation)
+        {
+            Some(runway) => runway.push(flight),
+            None => runways.push(vec![flight]),
+        }
+    }
+
+    let mut arrivals = Vec::new();
+    for runway in runways {
+        let mut previous: Option<Arrival> = None;
+        for flight in runway {
+            let slot = match previous {
+                Some(leader) => {
+                    let spacing = wake
+                        .minimum(leader.category, flight.category)
+                        .max(separation);
+                    flight.eta.max(leader.slot + spacing / flight.speed)
+                }
+                None => flight.eta,
+            };
+            let arrival = Arrival {
+                id: flight.id,
+                category: flight.category,
+                destination: flight.destination,
+                leader: previous.map(|p| p.id),
+                eta: flight.eta,
+                slot,
+            };
+            arrivals.push(arrival);
+            previous = Some(arrival);
+        }
+    }
+    arrivals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wake() -> WakeConfig {
+        WakeConfig {
+            light: [0.0; 4],
+            medium: [3.5, 0.0, 0.0, 0.0],
+            heavy: [4.0, 3.5, 3.0, 0.0],
+            super_: [5.5, 4.5, 4.0, 0.0],
+            lateral: 1.0,
+            below: 1000.0,
+            above: 100.0,
+        }
+    }
+
+    fn inbound(id: PlaneId, category: WakeCategory, x: f32, eta: f32) -> Inbound {
+        Inbound {
+            id,
+            category,
+            destination: Vector3::new(x, 0.0, 0.0),
+            eta,
+            speed: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_sequence_spaces_lights_behind_heavies() {
+        let flights = [
+            inbound(3, WakeCategory::LIGHT, 40.0, 101.0),
+            inbound(1, WakeCategory::HEAVY, 40.0, 100.0),
+            inbound(2, WakeCategory::MEDIUM, 40.5, 120.0),
+        ];
+
+        let arrivals = sequence(&flights, &wake(), 3.0);
+
+        let order: Vec<(PlaneId, Option<PlaneId>)> =
+            arrivals.iter().map(|a| (a.id, a.leader)).collect();
+        assert_eq!(order, vec![(1, None), (3, Some(1)), (2, Some(3))]);
+        assert_eq!(arrivals[0].delay(), 0.0);
+        // The light aircraft keeps 4 behind the heavy one instead of the usual 3.
+        assert_eq!(arrivals[1].slot, 104.0);
+        assert_eq!(arrivals[1].delay(), 3.0);
+        // Far enough behind the light one to need no delay.
+        assert_eq!(arrivals[2].delay(), 0.0);
+    }
+
+    #[test]
+    fn test_sequence_keeps_runways_apart() {
+        let flights = [
+            inbound(1, WakeCategory::SUPER, 0.0, 50.0),
+            inbound(2, WakeCategory::LIGHT, 30.0, 50.0),
+        ];
+
+        let arrivals = sequence(&flights, &wake(), 3.0);
+
+        assert!(
+            arrivals
+                .iter()
+                .all(|a| a.leader.is_none() && a.delay() == 0.0)
+        );
+    }
+}
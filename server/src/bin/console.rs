@@ -0,0 +1,67 @@
+//! Operator console of a running server: send a command and print the reply, or with no command,
+//! send every line read from the standard input and print the replies.
+use server::config::NetworkConfig;
+use std::process::ExitCode;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+const USAGE: &str = "Usage: console [--address ADDRESS] [COMMAND [ARGUMENT...]]
+  -a, --address ADDRESS     Console address of the server (default 127.0.0.1:8002)
+
+Commands:
+  aircraft                                  list connected aircraft
+  conflicts                                 list open conflicts and zone encounters
+  disconnect ID                             end the session of an aircraft
+  advise ID climb|descend ALTITUDE RATE     send an aircraft an altitude advisory
+  state [open|degraded|draining]            show the server state, or change it";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut address = NetworkConfig::default().control_address;
+    let mut words: Vec<String> = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-a" | "--address" => match args.next() {
+                Some(value) => address = value,
+                None => {
+                    eprintln!("{USAGE}");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ => words.push(arg),
+        }
+    }
+
+    let stream = match TcpStream::connect(&address).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Unable to reach the console at {}: {}", address, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let (mut reader, mut writer) = stream.into_split();
+    // Hanging up once everything is sent lets the server close the connection after replying.
+    let send = async {
+        if words.is_empty() {
+            tokio::io::copy(&mut tokio::io::stdin(), &mut writer).await?;
+        } else {
+            let line = format!("{}\n", words.join(" "));
+            writer.write_all(line.as_bytes()).await?;
+        }
+        writer.shutdown().await
+    };
+    let mut stdout = tokio::io::stdout();
+    let receive = tokio::io::copy(&mut reader, &mut stdout);
+    match tokio::try_join!(send, receive) {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Console connection failed: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Replay recorded telemetry through the detector and print the alerts it raises.
+//! With a baseline, print the alerts that differ from it instead and fail if there are any,
+//! to compare two detector versions on identical traffic.
+use server::config::{Config, ConfigError};
+use server::replay::{self, diff};
+use server::zone;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: replay TELEMETRY [--baseline ALERTS] [--output ALERTS] [--config PATH] [--set SECTION.KEY=VALUE]...
+  -b, --baseline ALERTS     Alerts of an earlier replay to compare against
+  -o, --output ALERTS       Write the alerts to a file instead of printing them
+  -c, --config PATH         TOML config file (default ./server/config.toml if present)
+  -s, --set KEY=VALUE       Override a config value, e.g. --set detection.tolerance=3.0";
+
+/// Read a whole file, with its path in the error.
+fn read(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(telemetry) = args.next().filter(|arg| !arg.starts_with('-')) else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    // Keep the replay options, the rest is the same as for the server.
+    let mut baseline: Option<String> = None;
+    let mut output: Option<String> = None;
+    let mut config_args: Vec<String> = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-b" | "--baseline" => baseline = args.next(),
+            "-o" | "--output" => output = args.next(),
+            _ => config_args.push(arg),
+        }
+    }
+
+    let config = match Config::from_args(&config_args) {
+        Ok(config) => config,
+        Err(ConfigError::Usage(e)) => {
+            eprintln!("{e}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let zones = match zone::load(&config.zones) {
+        Ok(zones) => zones,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let records =
+        match read(&telemetry).and_then(|text| replay::parse(&text).map_err(|e| e.to_string())) {
+            Ok(records) => records,
+            Err(e) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        };
+
+    let alerts: Vec<String> = replay::replay(&records, &config, &zones)
+        .await
+        .iter()
+        .map(|alert| alert.to_string())
+        .collect();
+    eprintln!(
+        "Replayed {} records, {} alerts",
+        records.len(),
+        alerts.len()
+    );
+
+    match &output {
+        Some(path) => {
+            let text: String = alerts.iter().map(|line| format!("{line}\n")).collect();
+            if let Err(e) = std::fs::write(path, text) {
+                eprintln!("Unable to write {}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        }
+        None if baseline.is_none() => {
+            for line in &alerts {
+                println!("{line}");
+            }
+        }
+        None => {}
+    }
+
+    let Some(baseline) = baseline else {
+        return ExitCode::SUCCESS;
+    };
+    let expected: Vec<String> = match read(&baseline) {
+        Ok(text) => text.lines().map(String::from).collect(),
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let changes = diff(&expected, &alerts);
+    for line in &changes.removed {
+        println!("- {line}");
+    }
+    for line in &changes.added {
+        println!("+ {line}");
+    }
+    eprintln!(
+        "{} alerts removed, {} added against {}",
+        changes.removed.len(),
+        changes.added.len(),
+        baseline
+    );
+    if changes.removed.is_empty() && changes.added.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,157 @@
+use crate::detector::TrackState;
+use std::collections::{HashMap, HashSet};
+use utils::vector::Vector3;
+
+/// Axis aligned box holding everywhere a track can be within the look-ahead window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweptBox {
+    pub min: Vector3,
+    pub max: Vector3,
+}
+
+impl SweptBox {
+    /// Box swept by a track over max_cycles, padded by half the separation and the track's
+    /// uncertainty on each side so that two boxes overlap whenever their tracks can conflict.
+    pub fn new(state: &TrackState, max_cycles: u32, tolerance: f32) -> SweptBox {
+        let start = state.position;
+        let end = start.add(Vector3::new(
+            state.velocity.x * max_cycles as f32,
+            state.velocity.y * max_cycles as f32,
+            state.velocity.z * max_cycles as f32,
+        ));
+        let pad = tolerance / 2.0 + state.uncertainty;
+        SweptBox {
+            min: Vector3::new(
+                start.x.min(end.x) - pad,
+                start.y.min(end.y) - pad,
+                start.z.min(end.z) - pad,
+            ),
+            max: Vector3::new(
+                start.x.max(end.x) + pad,
+                start.y.max(end.y) + pad,
+                start.z.max(end.z) + pad,
+            ),
+        }
+    }
+
+    /// Check whether two boxes overlap, edges included.
+    pub fn overlaps(&self, other: &SweptBox) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+            && self.min.z <= other.max.z
+            && other.min.z <= self.max.z
+    }
+
+    /// Length of the longest edge of the box.
+    fn longest_edge(&self) -> f32 {
+        (self.max.x - self.min.x)
+            .max(self.max.y - self.min.y)
+            .max(self.max.z - self.min.z)
+    }
+}
+
+/// Grid cell coordinates of a point.
+fn cell_of(v: Vector3, cell_size: f32) -> (i64, i64, i64) {
+    (
+        (v.x / cell_size).floor() as i64,
+        (v.y / cell_size).floor() as i64,
+        (v.z / cell_size).floor() as i64,
+    )
+}
+
+/// Broad phase of the detector.
+/// Buckets the swept box of every track into a uniform grid and returns the index pairs,
+/// lowest index first, of tracks whose boxes overlap. Only these pairs can be in conflict.
+pub fn candidate_pairs(
+    states: &[TrackState],
+    max_cycles: u32,
+    tolerance: f32,
+) -> Vec<(usize, usize)> {
+    let boxes: Vec<SweptBox> = states
+        .iter()
+        .map(|state| SweptBox::new(state, max_cycles, tolerance))
+        .collect();
+    if boxes.len() < 2 {
+        return Vec::new();
+    }
+
+    // Size cells after the average box so each box only covers a handful of cells.
+    let mean_edge = boxes.iter().map(SweptBox::longest_edge).sum::<f32>() / boxes.len() as f32;
+    let cell_size = mean_edge.max(tolerance).max(f32::EPSILON);
+
+    let mut grid: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+    for (i, swept) in boxes.iter().enumerate() {
+        let (min_x, min_y, min_z) = cell_of(swept.min, cell_size);
+        let (max_x, max_y, max_z) = cell_of(swept.max, cell_size);
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                for z in min_z..=max_z {
+                    grid.entry((x, y, z)).or_default().push(i);
+                }
+            }
+        }
+    }
+
+    let mut seen: HashSet<(usize, usize)> = HashSet::new();
+    let mut pairs = Vec::new();
+    for members in grid.values() {
+        for (k, &i) in members.iter().enumerate() {
+            for &j in members.iter().skip(k + 1) {
+                let pair = (i.min(j), i.max(j));
+                if seen.insert(pair) && boxes[pair.0].overlaps(&boxes[pair.1]) {
+                    pairs.push(pair);
+                }
+            }
+        }
+    }
+    pairs.sort();
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(id: u8, position: Vector3, velocity: Vector3) -> TrackState {
+        TrackState {
+            id,
+            position,
+            velocity,
+            uncertainty: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_swept_box() {
+        let swept = SweptBox::new(
+            &state(1, Vector3::new(1.0, 1.0, 1.0), Vector3::new(-1.0, 2.0, 0.0)),
+            3,
+            2.0,
+        );
+
+        assert_eq!(swept.min, Vector3::new(-3.0, 0.0, 0.0));
+        assert_eq!(swept.max, Vector3::new(2.0, 8.0, 2.0));
+    }
+
+    #[test]
+    fn test_candidate_pairs() {
+        let states = vec![
+            state(1, Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0)),
+            state(
+                2,
+                Vector3::new(100.0, 0.0, 0.0),
+                Vector3::new(1.0, 0.0, 0.0),
+            ),
+            state(3, Vector3::new(6.0, 0.0, 0.0), Vector3::new(-1.0, 0.0, 0.0)),
+            state(
+                4,
+                Vector3::new(0.0, 0.0, 500.0),
+                Vector3::new(1.0, 0.0, 0.0),
+            ),
+        ];
+
+        assert_eq!(candidate_pairs(&states, 3, 2.0), vec![(0, 2)]);
+    }
+}
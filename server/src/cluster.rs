@@ -0,0 +1,149 @@
+use crate::detector::{Conflict, PlaneId};
+use std::collections::HashMap;
+
+/// Aircraft linked by a chain of conflicts, to be resolved together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    /// Aircraft of the cluster, ordered by id.
+    pub members: Vec<PlaneId>,
+    /// Conflicts between members, ordered by pair.
+    pub conflicts: Vec<Conflict>,
+}
+
+impl Cluster {
+    /// Earliest closest approach of the cluster, in cycles.
+    pub fn time(&self) -> u32 {
+        self.conflicts.iter().map(|c| c.time).min().unwrap_or(0)
+    }
+}
+
+/// Disjoint sets over indices, with path halving and union by size.
+struct UnionFind {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(len: usize) -> UnionFind {
+        UnionFind {
+            parent: (0..len).collect(),
+            size: vec![1; len],
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    fn union(&mut self, i: usize, j: usize) {
+        let (i, j) = (self.find(i), self.find(j));
+        if i == j {
+            return;
+        }
+        let (big, small) = if self.size[i] >= self.size[j] {
+            (i, j)
+        } else {
+            (j, i)
+        };
+        self.parent[small] = big;
+        self.size[big] += self.size[small];
+    }
+}
+
+/// Group conflicts into clusters of transitively conflicting aircraft.
+/// Clusters are ordered by their lowest member id.
+pub fn clusters(conflicts: &[Conflict]) -> Vec<Cluster> {
+    let mut index: HashMap<PlaneId, usize> = HashMap::new();
+    let mut ids: Vec<PlaneId> = Vec::new();
+    for conflict in conflicts {
+        for id in [conflict.a, conflict.b] {
+            index.entry(id).or_insert_with(|| {
+                ids.push(id);
+                ids.len() - 1
+            });
+        }
+    }
+
+    let mut sets = UnionFind::new(ids.len());
+    for conflict in conflicts {
+        sets.union(index[&conflict.a], index[&conflict.b]);
+    }
+
+    let mut by_root: HashMap<usize, Cluster> = HashMap::new();
+    for (i, id) in ids.iter().enumerate() {
+        by_root
+            .entry(sets.find(i))
+            .or_insert_with(|| Cluster {
+                members: Vec::new(),
+                conflicts: Vec::new(),
+            })
+            .members
+            .push(*id);
+    }
+    for conflict in conflicts {
+        if let Some(cluster) = by_root.get_mut(&sets.find(index[&conflict.a])) {
+            cluster.conflicts.push(*conflict);
+        }
+    }
+
+    let mut clusters: Vec<Cluster> = by_root.into_values().collect();
+    for cluster in clusters.iter_mut() {
+        cluster.members.sort();
+        cluster.conflicts.sort_by_key(|c| (c.a, c.b));
+    }
+    clusters.sort_by_key(|cluster| cluster.members.first().copied());
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conflict(a: PlaneId, b: PlaneId, time: u32) -> Conflict {
+        Conflict {
+            a,
+            b,
+            cpa: 0.0,
+            time,
+        }
+    }
+
+    #[test]
+    fn test_clusters_group_transitive_conflicts() {
+        let conflicts = [
+            conflict(1, 5, 3),
+            conflict(2, 8, 4),
+            conflict(5, 9, 2),
+            conflict(3, 4, 5),
+            conflict(4, 8, 6),
+        ];
+
+        let clusters = clusters(&conflicts);
+
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].members, vec![1, 5, 9]);
+        assert_eq!(clusters[0].conflicts.len(), 2);
+        assert_eq!(clusters[0].time(), 2);
+        assert_eq!(clusters[1].members, vec![2, 3, 4, 8]);
+        assert_eq!(clusters[1].conflicts.len(), 3);
+        assert_eq!(clusters[1].time(), 4);
+    }
+
+    #[test]
+    fn test_clusters_keep_pairs_apart() {
+        let clusters = clusters(&[conflict(1, 2, 3), conflict(3, 4, 3)]);
+
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].members, vec![1, 2]);
+        assert_eq!(clusters[1].members, vec![3, 4]);
+    }
+
+    #[test]
+    fn test_clusters_empty() {
+        assert!(clusters(&[]).is_empty());
+    }
+}
@@ -0,0 +1,658 @@
+use crate::alerting::AlertThresholds;
+use crate::detector::DetectorConfig;
+use crate::history::HistoryConfig;
+use crate::kalman::TrackingConfig;
+use crate::plausibility::PlausibilityLimits;
+use crate::probe::ProbeConfig;
+use crate::replay::RecordingConfig;
+use crate::resolution::ResolutionConfig;
+use crate::sector::{Sector, SectorConfig};
+use crate::wake::WakeConfig;
+use crate::zone::ZoneConfig;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use utils::vector::{Bounds, Vector3};
+
+/// Config file read when no --config argument is given, if it exists.
+pub const DEFAULT_CONFIG_PATH: &str = "./server/config.toml";
+
+/// Errors raised while loading or validating the configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file could not be read.
+    Io(String, std::io::Error),
+    /// The config file or an override is not valid TOML for the config.
+    Parse(String),
+    /// A value is out of range or inconsistent with another value.
+    Invalid(String),
+    /// The command line could not be understood.
+    Usage(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "Unable to read config file {}: {}", path, e),
+            ConfigError::Parse(e) => write!(f, "Unable to parse config: {}", e),
+            ConfigError::Invalid(e) => write!(f, "Invalid config: {}", e),
+            ConfigError::Usage(e) => write!(f, "{}\n{}", e, USAGE),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Command line help.
+pub const USAGE: &str = "Usage: server [--config PATH] [--set SECTION.KEY=VALUE]...
+  -c, --config PATH         TOML config file (default ./server/config.toml if present)
+  -s, --set KEY=VALUE       Override a config value, e.g. --set detection.tolerance=3.0";
+
+/// Everything that can be configured on the server.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: NetworkConfig,
+    pub timing: TimingConfig,
+    pub detection: DetectorConfig,
+    pub wake: WakeConfig,
+    pub tracking: TrackingConfig,
+    pub history: HistoryConfig,
+    pub alerts: AlertThresholds,
+    pub probe: ProbeConfig,
+    pub resolution: ResolutionConfig,
+    pub plausibility: PlausibilityLimits,
+    pub airspace: AirspaceConfig,
+    pub zones: ZoneConfig,
+    pub sectors: SectorConfig,
+    pub recording: RecordingConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// Address the server listens on for clients.
+    pub bind_address: String,
+    /// Packets each client may have waiting to be sent before newer ones are dropped.
+    pub queue_length: usize,
+    /// Loopback address of the operator console, empty to disable it.
+    pub control_address: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimingConfig {
+    /// Time between two runs of the detector.
+    pub tick_ms: u64,
+    /// Time a client may stay silent before it is dropped.
+    pub client_timeout_ms: u64,
+    /// Time given to clients to finish their uploads once the server is asked to stop.
+    pub shutdown_grace_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AirspaceConfig {
+    /// Lowest x, y and z a report may have.
+    pub min: [f32; 3],
+    /// Highest x, y and z a report may have.
+    pub max: [f32; 3],
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub directory: String,
+    pub file: String,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            bind_address: String::from("127.0.0.1:8001"),
+            queue_length: 32,
+            control_address: String::from("127.0.0.1:8002"),
+        }
+    }
+}
+
+impl Default for TimingConfig {
+    fn default() -> Self {
+        TimingConfig {
+            tick_ms: 1000,
+            client_timeout_ms: 5000,
+            shutdown_grace_ms: 5000,
+        }
+    }
+}
+
+impl Default for DetectorConfig {
+    fn default() -> Self {
+        // The look-ahead covers the traffic advisory threshold.
+        DetectorConfig {
+            max_cycles: 6,
+            tolerance: 2.0,
+        }
+    }
+}
+
+impl Default for WakeConfig {
+    fn default() -> Self {
+        // Only heavier leaders need more than the detection tolerance behind them.
+        WakeConfig {
+            light: [0.0, 0.0, 0.0, 0.0],
+            medium: [3.5, 0.0, 0.0, 0.0],
+            heavy: [4.0, 3.5, 3.0, 0.0],
+            super_: [5.5, 4.5, 4.0, 0.0],
+            lateral: 1.0,
+            below: 1000.0,
+            above: 100.0,
+        }
+    }
+}
+
+impl Default for TrackingConfig {
+    fn default() -> Self {
+        TrackingConfig {
+            horizontal_process_noise: 0.5,
+            vertical_process_noise: 10000.0,
+            measurement_noise: 0.1,
+            stale_after_ms: 3000,
+            clock_tolerance_ms: 5000,
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        // A minute of reports at full rate, then one every 10 seconds for two hours.
+        HistoryConfig {
+            live_capacity: 60,
+            downsample_factor: 10,
+            archive_retention: 720,
+            report_interval_ms: 60000,
+        }
+    }
+}
+
+impl Default for AlertThresholds {
+    fn default() -> Self {
+        AlertThresholds {
+            traffic: 6,
+            resolution: 3,
+            exit_margin: 1,
+            exit_buffer: 1.0,
+            min_dwell: 3,
+        }
+    }
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        // Five minutes ahead at the default tick.
+        ProbeConfig {
+            horizon: 300,
+            interval_ms: 10000,
+            separation: 3.0,
+        }
+    }
+}
+
+impl Default for ResolutionConfig {
+    fn default() -> Self {
+        ResolutionConfig {
+            vertical_offset: 1000.0,
+            vertical_rate: 500.0,
+            floor: 0.0,
+            ceiling: 50000.0,
+        }
+    }
+}
+
+impl Default for PlausibilityLimits {
+    fn default() -> Self {
+        PlausibilityLimits {
+            max_speed: 10.0,
+            max_climb_rate: 1000.0,
+        }
+    }
+}
+
+impl Default for AirspaceConfig {
+    fn default() -> Self {
+        AirspaceConfig {
+            min: [-10.0, -10.0, -100.0],
+            max: [50.0, 50.0, 60000.0],
+        }
+    }
+}
+
+impl Default for ZoneConfig {
+    fn default() -> Self {
+        ZoneConfig {
+            file: String::from("./server/zones.toml"),
+        }
+    }
+}
+
+impl Default for SectorConfig {
+    fn default() -> Self {
+        // The node grid split in four quadrants, the edges of the airspace included.
+        let quadrant = |name: &str, min: [f32; 2], max: [f32; 2]| Sector {
+            name: String::from(name),
+            min,
+            max,
+            capacity: 8,
+        };
+        SectorConfig {
+            look_ahead: 6,
+            sector: vec![
+                quadrant("South West", [-10.0, -10.0], [20.0, 20.0]),
+                quadrant("South East", [20.0, -10.0], [50.0, 20.0]),
+                quadrant("North West", [-10.0, 20.0], [20.0, 50.0]),
+                quadrant("North East", [20.0, 20.0], [50.0, 50.0]),
+            ],
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            directory: String::from("./server/log"),
+            file: String::from("server.log"),
+        }
+    }
+}
+
+impl AirspaceConfig {
+    /// Bounds every report is checked against.
+    pub fn bounds(&self) -> Bounds {
+        Bounds::new(
+            Vector3::new(self.min[0], self.min[1], self.min[2]),
+            Vector3::new(self.max[0], self.max[1], self.max[2]),
+        )
+    }
+}
+
+impl TimingConfig {
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(self.tick_ms)
+    }
+
+    pub fn client_timeout(&self) -> Duration {
+        Duration::from_millis(self.client_timeout_ms)
+    }
+
+    pub fn shutdown_grace(&self) -> Duration {
+        Duration::from_millis(self.shutdown_grace_ms)
+    }
+}
+
+/// Return an Invalid error with the message if the condition does not hold.
+fn check(condition: bool, message: &str) -> Result<(), ConfigError> {
+    if condition {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid(String::from(message)))
+    }
+}
+
+/// Check that a float is finite and strictly positive.
+fn positive(value: f32) -> bool {
+    value.is_finite() && value > 0.0
+}
+
+impl Config {
+    /// Build the configuration from the command line arguments, program name excluded.
+    /// Reads the config file, applies every --set override in order, then validates.
+    pub fn from_args(args: &[String]) -> Result<Config, ConfigError> {
+        let mut path: Option<String> = None;
+        let mut overrides: Vec<String> = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let value = match iter.next() {
+                Some(value) => value.clone(),
+                None => return Err(ConfigError::Usage(format!("Missing value for {}", arg))),
+            };
+            match arg.as_str() {
+                "-c" | "--config" => path = Some(value),
+                "-s" | "--set" => overrides.push(value),
+                _ => return Err(ConfigError::Usage(format!("Unknown argument {}", arg))),
+            }
+        }
+
+        let text = match path {
+            Some(path) => match std::fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) => return Err(ConfigError::Io(path, e)),
+            },
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                match std::fs::read_to_string(DEFAULT_CONFIG_PATH) {
+                    Ok(text) => text,
+                    Err(e) => return Err(ConfigError::Io(String::from(DEFAULT_CONFIG_PATH), e)),
+                }
+            }
+            None => String::new(),
+        };
+
+        Config::parse(&text, &overrides)
+    }
+
+    /// Parse a TOML document, apply `section.key=value` overrides and validate the result.
+    pub fn parse(text: &str, overrides: &[String]) -> Result<Config, ConfigError> {
+        let mut table: toml::Table = match text.parse() {
+            Ok(table) => table,
+            Err(e) => return Err(ConfigError::Parse(e.to_string())),
+        };
+        for assignment in overrides {
+            Self::apply_override(&mut table, assignment)?;
+        }
+
+        let config: Config = match table.try_into() {
+            Ok(config) => config,
+            Err(e) => return Err(ConfigError::Parse(e.to_string())),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Set `section.key` in the table. The value is read as TOML, or as a string if that fails.
+    fn apply_override(table: &mut toml::Table, assignment: &str) -> Result<(), ConfigError> {
+        let Some((path, raw)) = assignment.split_once('=') else {
+            return Err(ConfigError::Usage(format!(
+                "Override {} is not SECTION.KEY=VALUE",
+                assignment
+            )));
+        };
+        let Some((section, key)) = path.trim().split_once('.') else {
+            return Err(ConfigError::Usage(format!(
+                "Override {} is not SECTION.KEY=VALUE",
+                assignment
+            )));
+        };
+
+        let raw = raw.trim();
+        let value = match format!("value = {}", raw).parse::<toml::Table>() {
+            Ok(mut parsed) => match parsed.remove("value") {
+                Some(value) => value,
+                None => toml::Value::String(String::from(raw)),
+            },
+            Err(_) => toml::Value::String(String::from(raw)),
+        };
+
+        let entry = table
+            .entry(section)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        match entry.as_table_mut() {
+            Some(section_table) => {
+                section_table.insert(String::from(key), value);
+                Ok(())
+            }
+            None => Err(ConfigError::Parse(format!("{} is not a section", section))),
+        }
+    }
+
+    /// Check every value, and the values that depend on each other.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check(
+            self.network.bind_address.parse::<SocketAddr>().is_ok(),
+            "network.bind_address must be an IP address and port",
+        )?;
+        check(
+            self.network.queue_length > 0,
+            "network.queue_length must be above 0",
+        )?;
+        check(
+            self.network.control_address.is_empty()
+                || self
+                    .network
+                    .control_address
+                    .parse::<SocketAddr>()
+                    .is_ok_and(|address| address.ip().is_loopback()),
+            "network.control_address must be a loopback IP address and port, or empty",
+        )?;
+
+        check(self.timing.tick_ms > 0, "timing.tick_ms must be above 0")?;
+        check(
+            self.timing.client_timeout_ms > 0,
+            "timing.client_timeout_ms must be above 0",
+        )?;
+
+        check(
+            self.detection.max_cycles > 0,
+            "detection.max_cycles must be above 0",
+        )?;
+        check(
+            positive(self.detection.tolerance),
+            "detection.tolerance must be a positive number",
+        )?;
+
+        self.wake.validate()?;
+
+        check(
+            positive(self.tracking.horizontal_process_noise),
+            "tracking.horizontal_process_noise must be a positive number",
+        )?;
+        check(
+            positive(self.tracking.vertical_process_noise),
+            "tracking.vertical_process_noise must be a positive number",
+        )?;
+        check(
+            positive(self.tracking.measurement_noise),
+            "tracking.measurement_noise must be a positive number",
+        )?;
+        check(
+            self.tracking.stale_after_ms >= self.timing.tick_ms,
+            "tracking.stale_after_ms must not be below timing.tick_ms",
+        )?;
+
+        check(
+            self.history.live_capacity >= 2,
+            "history.live_capacity must be at least 2",
+        )?;
+        check(
+            self.history.downsample_factor > 0,
+            "history.downsample_factor must be above 0",
+        )?;
+        check(
+            self.history.report_interval_ms > 0,
+            "history.report_interval_ms must be above 0",
+        )?;
+
+        check(
+            self.alerts.resolution > 0,
+            "alerts.resolution must be above 0",
+        )?;
+        check(
+            self.alerts.resolution < self.alerts.traffic,
+            "alerts.resolution must be below alerts.traffic",
+        )?;
+        check(
+            self.alerts.traffic <= self.detection.max_cycles,
+            "alerts.traffic must not exceed detection.max_cycles",
+        )?;
+        check(
+            self.alerts.exit_buffer.is_finite() && self.alerts.exit_buffer >= 0.0,
+            "alerts.exit_buffer must not be negative",
+        )?;
+
+        check(self.probe.horizon > 0, "probe.horizon must be above 0")?;
+        check(
+            self.probe.interval_ms > 0,
+            "probe.interval_ms must be above 0",
+        )?;
+        check(
+            positive(self.probe.separation),
+            "probe.separation must be a positive number",
+        )?;
+
+        check(
+            positive(self.resolution.vertical_offset),
+            "resolution.vertical_offset must be a positive number",
+        )?;
+        check(
+            positive(self.resolution.vertical_rate),
+            "resolution.vertical_rate must be a positive number",
+        )?;
+        check(
+            self.resolution.floor.is_finite()
+                && self.resolution.ceiling.is_finite()
+                && self.resolution.floor < self.resolution.ceiling,
+            "resolution.floor must be below resolution.ceiling",
+        )?;
+
+        check(
+            positive(self.plausibility.max_speed),
+            "plausibility.max_speed must be a positive number",
+        )?;
+        check(
+            positive(self.plausibility.max_climb_rate),
+            "plausibility.max_climb_rate must be a positive number",
+        )?;
+        check(
+            self.resolution.vertical_rate <= self.plausibility.max_climb_rate,
+            "resolution.vertical_rate must not exceed plausibility.max_climb_rate",
+        )?;
+
+        let bounds = self.airspace.bounds();
+        check(
+            bounds.min.is_finite()
+                && bounds.max.is_finite()
+                && bounds.min.x < bounds.max.x
+                && bounds.min.y < bounds.max.y
+                && bounds.min.z < bounds.max.z,
+            "airspace.min must be below airspace.max on every axis",
+        )?;
+
+        self.sectors.validate()?;
+
+        check(
+            !self.log.directory.is_empty() && !self.log.file.is_empty(),
+            "log.directory and log.file must not be empty",
+        )?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match toml::to_string(self) {
+            Ok(text) => write!(f, "{}", text),
+            Err(_) => write!(f, "{:?}", self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|a| String::from(*a)).collect()
+    }
+
+    #[test]
+    fn test_default_is_valid() {
+        assert!(Config::default().validate().is_ok());
+        assert_eq!(Config::parse("", &[]).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_shipped_config_matches_defaults() {
+        let text = include_str!("../config.toml");
+
+        assert_eq!(Config::parse(text, &[]).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_parse_partial_file() {
+        let text = "[network]\nbind_address = \"0.0.0.0:9000\"\n\n[detection]\ntolerance = 3.5\n";
+
+        let config = Config::parse(text, &[]).unwrap();
+
+        assert_eq!(config.network.bind_address, "0.0.0.0:9000");
+        assert_eq!(config.detection.tolerance, 3.5);
+        assert_eq!(config.detection.max_cycles, 6);
+        assert_eq!(config.timing, TimingConfig::default());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let text = "[timing]\ntick_ms = 500\n";
+        let overrides = args(&[
+            "timing.tick_ms=250",
+            "network.bind_address=127.0.0.1:9001",
+            "log.directory = /tmp/log",
+        ]);
+
+        let config = Config::parse(text, &overrides).unwrap();
+
+        assert_eq!(config.timing.tick_ms, 250);
+        assert_eq!(config.network.bind_address, "127.0.0.1:9001");
+        assert_eq!(config.log.directory, "/tmp/log");
+    }
+
+    #[test]
+    fn test_rejects_invalid_values() {
+        let cases = [
+            "network.bind_address=nowhere",
+            "network.queue_length=0",
+            "network.control_address=0.0.0.0:8002",
+            "timing.tick_ms=0",
+            "detection.tolerance=-1.0",
+            "detection.tolerance=nan",
+            "wake.heavy=[-1.0, 0.0, 0.0, 0.0]",
+            "wake.lateral=0",
+            "tracking.measurement_noise=0.0",
+            "tracking.stale_after_ms=500",
+            "history.live_capacity=1",
+            "history.downsample_factor=0",
+            "alerts.resolution=6",
+            "alerts.traffic=10",
+            "alerts.exit_buffer=-1.0",
+            "probe.separation=0",
+            "resolution.floor=60000.0",
+            "resolution.vertical_rate=5000.0",
+            "airspace.min=[0.0, 0.0, 70000.0]",
+            "sectors.look_ahead=0",
+        ];
+        for case in cases {
+            match Config::parse("", &args(&[case])) {
+                Err(ConfigError::Invalid(_)) => {}
+                other => panic!("{} was accepted: {:?}", case, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_unknown_and_mistyped_fields() {
+        assert!(matches!(
+            Config::parse("[detection]\ntolerence = 2.0\n", &[]),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(matches!(
+            Config::parse("", &args(&["timing.tick_ms=fast"])),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_args() {
+        assert!(matches!(
+            Config::from_args(&args(&["--bogus", "1"])),
+            Err(ConfigError::Usage(_))
+        ));
+        assert!(matches!(
+            Config::from_args(&args(&["--config"])),
+            Err(ConfigError::Usage(_))
+        ));
+        assert!(matches!(
+            Config::from_args(&args(&["--config", "/nonexistent/server.toml"])),
+            Err(ConfigError::Io(_, _))
+        ));
+    }
+}
@@ -0,0 +1,508 @@
+use crate::alerting::Alert;
+use crate::config::Config;
+use crate::detector::PlaneId;
+use crate::encounter::Phase;
+use crate::manager::{Categories, Coordinates, OpenEncounters, Sessions, SharedFilter};
+use crate::state_machine::{State, StateMachine};
+use core::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use utils::advisory::{Advisory, Sense};
+use utils::vector::Vector3;
+use utils::wake::WakeCategory;
+
+const HELP: &str = "aircraft                                  list connected aircraft
+conflicts                                 list open conflicts and zone encounters
+disconnect ID                             end the session of an aircraft
+advise ID climb|descend ALTITUDE RATE     send an aircraft an altitude advisory
+clear ID                                  tell an aircraft it is clear of conflict
+state [open|degraded|draining]            show the server state, or change it
+help                                      show this list";
+
+/// A line typed at the operator console.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Help,
+    /// List the connected aircraft with their last position and speed.
+    Aircraft,
+    /// List the open conflicts and zone encounters.
+    Conflicts,
+    /// End the session of an aircraft.
+    Disconnect(PlaneId),
+    /// Send an aircraft an altitude advisory, as if the detector had raised it.
+    Advise(PlaneId, Advisory),
+    /// Tell an aircraft it is clear of conflict, ending the advisory it holds.
+    Clear(PlaneId),
+    /// Show the server state, or move it to another.
+    State(Option<State>),
+}
+
+/// A console line that cannot be carried out.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    /// No command by that name.
+    Unknown(String),
+    /// The arguments do not fit the command, with its usage.
+    Usage(&'static str),
+    /// The command was understood but the server refused it.
+    Refused(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::Unknown(name) => write!(f, "Unknown command {}, try help", name),
+            CommandError::Usage(usage) => write!(f, "Usage: {}", usage),
+            CommandError::Refused(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl Command {
+    /// Parse a console line. Command names and keywords are not case sensitive.
+    pub fn parse(line: &str) -> Result<Command, CommandError> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some(name) = words.first() else {
+            return Err(CommandError::Usage("COMMAND [ARGUMENT...]"));
+        };
+        let arguments = &words[1..];
+        match name.to_ascii_lowercase().as_str() {
+            "help" => Ok(Command::Help),
+            "aircraft" => Ok(Command::Aircraft),
+            "conflicts" => Ok(Command::Conflicts),
+            "disconnect" => match arguments {
+                [id] => id
+                    .parse()
+                    .map(Command::Disconnect)
+                    .map_err(|_| CommandError::Usage("disconnect ID")),
+                _ => Err(CommandError::Usage("disconnect ID")),
+            },
+            "clear" => match arguments {
+                [id] => id
+                    .parse()
+                    .map(Command::Clear)
+                    .map_err(|_| CommandError::Usage("clear ID")),
+                _ => Err(CommandError::Usage("clear ID")),
+            },
+            "advise" => {
+                let usage = CommandError::Usage("advise ID climb|descend ALTITUDE RATE");
+                let [id, sense, altitude, rate] = arguments else {
+                    return Err(usage);
+                };
+                let sense = match sense.to_ascii_lowercase().as_str() {
+                    "climb" => Sense::CLIMB,
+                    "descend" => Sense::DESCEND,
+                    _ => return Err(usage),
+                };
+                let (Ok(id), Ok(altitude), Ok(rate)) =
+                    (id.parse(), altitude.parse::<f32>(), rate.parse::<f32>())
+                else {
+                    return Err(usage);
+                };
+                if !altitude.is_finite() || !rate.is_finite() || rate <= 0.0 {
+                    return Err(usage);
+                }
+                Ok(Command::Advise(id, Advisory::new(sense, altitude, rate)))
+            }
+            "state" => {
+                let usage = CommandError::Usage("state [open|degraded|draining]");
+                match arguments {
+                    [] => Ok(Command::State(None)),
+                    [to] => [State::OPEN, State::DEGRADED, State::DRAINING]
+                        .into_iter()
+                        .find(|state| state.to_string().eq_ignore_ascii_case(to))
+                        .map(|state| Command::State(Some(state)))
+                        .ok_or(usage),
+                    _ => Err(usage),
+                }
+            }
+            _ => Err(CommandError::Unknown(String::from(*name))),
+        }
+    }
+}
+
+/// Operator console, a line based text interface to a running server.
+/// Each line received is a command, answered with one or more lines of text. Failed commands are
+/// answered with a line starting with "error:".
+#[derive(Debug, Clone)]
+pub struct Console {
+    pub(crate) coordinates: Coordinates,
+    pub(crate) categories: Categories,
+    pub(crate) sessions: Sessions,
+    pub(crate) encounters: OpenEncounters,
+    pub(crate) filter: SharedFilter,
+    pub(crate) state_machine: Arc<StateMachine>,
+    pub(crate) config: Arc<Config>,
+}
+
+impl Console {
+    /// Answer every operator that connects to a listener, until the task is dropped.
+    pub async fn serve(self, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    tracing::info!("Console opened from {}", addr);
+                    tokio::spawn(self.clone().answer(stream));
+                }
+                Err(e) => tracing::error!("Unable to accept console connection: {e}"),
+            }
+        }
+    }
+
+    /// Reply to each line of an operator until they hang up.
+    async fn answer(self, stream: TcpStream) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let reply = format!("{}\n", self.reply(&line).await);
+            if writer.write_all(reply.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Text answering a console line.
+    pub async fn reply(&self, line: &str) -> String {
+        match Command::parse(line) {
+            Ok(command) => match self.execute(command).await {
+                Ok(reply) => reply,
+                Err(e) => format!("error: {}", e),
+            },
+            Err(e) => format!("error: {}", e),
+        }
+    }
+
+    /// Carry out a command.
+    pub async fn execute(&self, command: Command) -> Result<String, CommandError> {
+        match command {
+            Command::Help => Ok(String::from(HELP)),
+            Command::Aircraft => Ok(self.aircraft().await),
+            Command::Conflicts => Ok(self.conflicts().await),
+            Command::Disconnect(plane_id) => {
+                if !self.sessions.lock().await.disconnect(plane_id) {
+                    return Err(CommandError::Refused(format!(
+                        "Aircraft {} is not connected",
+                        plane_id
+                    )));
+                }
+                tracing::warn!("Operator disconnected {}", plane_id);
+                Ok(format!("Disconnecting {}", plane_id))
+            }
+            Command::Advise(plane_id, advisory) => {
+                let [min, max] = [self.config.airspace.min[2], self.config.airspace.max[2]];
+                if !(min..=max).contains(&advisory.target_altitude) {
+                    return Err(CommandError::Refused(format!(
+                        "Altitude {} is outside the airspace, {} to {}",
+                        advisory.target_altitude, min, max
+                    )));
+                }
+                let pkt = Alert::Resolution(advisory).to_packet(plane_id);
+                if !self.sessions.lock().await.deliver(plane_id, pkt) {
+                    return Err(CommandError::Refused(format!(
+                        "Unable to reach {}, not connected or queue full",
+                        plane_id
+                    )));
+                }
+                tracing::warn!("Operator advised {}: {}", plane_id, advisory);
+                Ok(format!("Sent {} {}", plane_id, advisory))
+            }
+            Command::Clear(plane_id) => {
+                let pkt = Alert::Clear.to_packet(plane_id);
+                if !self.sessions.lock().await.deliver(plane_id, pkt) {
+                    return Err(CommandError::Refused(format!(
+                        "Unable to reach {}, not connected or queue full",
+                        plane_id
+                    )));
+                }
+                // The detector advises the aircraft again if it still needs to be.
+                self.filter.lock().await.fresh(plane_id, &Alert::Clear);
+                tracing::warn!("Operator cleared {}", plane_id);
+                Ok(format!("Cleared {}", plane_id))
+            }
+            Command::State(None) => Ok(format!("Server is {}", self.state_machine.get_state())),
+            Command::State(Some(to)) => {
+                let from = self
+                    .state_machine
+                    .transition(to)
+                    .map_err(|e| CommandError::Refused(e.to_string()))?;
+                tracing::warn!("Operator moved server from {} to {}", from, to);
+                Ok(format!("Server state {} -> {}", from, to))
+            }
+        }
+    }
+
+    /// One line per connected aircraft, in id order.
+    async fn aircraft(&self) -> String {
+        let queues = self.sessions.lock().await.queues();
+        if queues.is_empty() {
+            return String::from("No aircraft connected");
+        }
+        let coordinates = self.coordinates.lock().await;
+        let categories = self.categories.lock().await;
+        let now = Instant::now();
+        let mut lines = Vec::new();
+        for queue in queues {
+            let category = categories
+                .get(&queue.plane_id)
+                .copied()
+                .unwrap_or(WakeCategory::MEDIUM);
+            let track = coordinates.get(&queue.plane_id);
+            let position = match track.and_then(|track| track.last()) {
+                Some(sample) => {
+                    let speed = track
+                        .and_then(|track| track.filter)
+                        .map(|filter| {
+                            Vector3::distance(Vector3::new(0.0, 0.0, 0.0), filter.velocity())
+                        })
+                        .unwrap_or(0.0);
+                    let age = track.and_then(|track| track.age(now)).unwrap_or_default();
+                    format!(
+                        "at {}, speed {:.2} per tick, last report {:.1}s ago{}",
+                        sample.position,
+                        speed,
+                        age.as_secs_f32(),
+                        if track.is_some_and(|track| track.stale) {
+                            ", stale"
+                        } else {
+                            ""
+                        }
+                    )
+                }
+                None => String::from("no position yet"),
+            };
+            lines.push(format!(
+                "{} {}: {}, {} queued, {} dropped",
+                queue.plane_id, category, position, queue.queued, queue.dropped
+            ));
+        }
+        lines.join("\n")
+    }
+
+    /// One line per open encounter.
+    async fn conflicts(&self) -> String {
+        let open = self.encounters.lock().await.open();
+        if open.is_empty() {
+            return String::from("No conflicts");
+        }
+        open.iter()
+            .map(|(encounter, phase)| {
+                let phase = match phase {
+                    Phase::Detected => "detected",
+                    Phase::Advised => "advised",
+                };
+                format!("{}: {}", encounter, phase)
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerting::AlertFilter;
+    use crate::encounter::{Encounter, Encounters};
+    use crate::history::Sample;
+    use crate::registry::SessionRegistry;
+    use crate::track::Track;
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use tokio::sync::Mutex;
+    use utils::packet::FlagState;
+
+    fn console() -> Console {
+        Console {
+            coordinates: Arc::new(Mutex::new(HashMap::new())),
+            categories: Arc::new(Mutex::new(HashMap::new())),
+            sessions: Arc::new(Mutex::new(SessionRegistry::default())),
+            encounters: Arc::new(Mutex::new(Encounters::default())),
+            filter: Arc::new(Mutex::new(AlertFilter::default())),
+            state_machine: Arc::new(StateMachine::new()),
+            config: Arc::new(Config::default()),
+        }
+    }
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse("AIRCRAFT"), Ok(Command::Aircraft));
+        assert_eq!(Command::parse(" disconnect 4 "), Ok(Command::Disconnect(4)));
+        assert_eq!(
+            Command::parse("advise 2 climb 11000 100"),
+            Ok(Command::Advise(
+                2,
+                Advisory::new(Sense::CLIMB, 11000.0, 100.0)
+            ))
+        );
+        assert_eq!(
+            Command::parse("state degraded"),
+            Ok(Command::State(Some(State::DEGRADED)))
+        );
+        assert_eq!(Command::parse("state"), Ok(Command::State(None)));
+        assert_eq!(Command::parse("Clear 7"), Ok(Command::Clear(7)));
+
+        assert!(matches!(
+            Command::parse("land 4"),
+            Err(CommandError::Unknown(_))
+        ));
+        for invalid in [
+            "",
+            "disconnect",
+            "disconnect 300",
+            "clear",
+            "clear me",
+            "advise 2 sideways 11000 100",
+            "advise 2 climb 11000 0",
+            "advise 2 climb high 100",
+            "state closed",
+        ] {
+            assert!(
+                matches!(Command::parse(invalid), Err(CommandError::Usage(_))),
+                "{} was accepted",
+                invalid
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_aircraft_and_conflicts() {
+        let console = console();
+        assert_eq!(console.reply("aircraft").await, "No aircraft connected");
+        assert_eq!(console.reply("conflicts").await, "No conflicts");
+
+        console.sessions.lock().await.register(1, None, peer(1000));
+        console.sessions.lock().await.register(2, None, peer(2000));
+        console
+            .categories
+            .lock()
+            .await
+            .insert(1, WakeCategory::HEAVY);
+        let config = Config::default();
+        let mut track = Track::new(&config.history);
+        let now = Instant::now();
+        for (i, x) in [10.0, 12.0].into_iter().enumerate() {
+            let received = now - config.timing.tick() * (1 - i as u32);
+            let sample = Sample::new(Vector3::new(x, 5.0, 10000.0), received, None);
+            track.push(sample, config.timing.tick(), &config.tracking);
+        }
+        console.coordinates.lock().await.insert(1, track);
+        console
+            .encounters
+            .lock()
+            .await
+            .advise(Encounter::Conflict(1, 2));
+
+        let aircraft = console.reply("aircraft").await;
+        let lines: Vec<&str> = aircraft.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("1 HEAVY: at [12,5,10000], speed "));
+        assert_eq!(lines[1], "2 MEDIUM: no position yet, 0 queued, 0 dropped");
+        assert_eq!(
+            console.reply("conflicts").await,
+            "conflict between 1 and 2: advised"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_and_advise_reach_the_session() {
+        let console = console();
+        let mut session = console
+            .sessions
+            .lock()
+            .await
+            .register(3, None, peer(1000))
+            .unwrap();
+
+        assert_eq!(
+            console.reply("advise 3 descend 9000 50").await,
+            "Sent 3 DESCEND to 9000 at 50 per tick"
+        );
+        let pkt = session.urgent.try_recv().unwrap();
+        assert_eq!(pkt.header.flag, FlagState::COLLISION);
+        assert_eq!(
+            Advisory::from_bytes(&pkt.body),
+            Some(Advisory::new(Sense::DESCEND, 9000.0, 50.0))
+        );
+        assert!(
+            console
+                .reply("advise 3 climb 90000 50")
+                .await
+                .starts_with("error:")
+        );
+        assert!(
+            console
+                .reply("advise 4 climb 9000 50")
+                .await
+                .starts_with("error:")
+        );
+
+        // The manual advisory is held until the operator clears it, after which the detector
+        // sends its own advisory again if it is still needed.
+        let climb = Alert::Resolution(Advisory::new(Sense::CLIMB, 11000.0, 50.0));
+        console.filter.lock().await.fresh(3, &climb);
+        assert_eq!(console.reply("clear 3").await, "Cleared 3");
+        assert_eq!(
+            session.urgent.try_recv().map(|p| p.header.flag),
+            Ok(FlagState::CLEAR_OF_CONFLICT)
+        );
+        assert!(console.filter.lock().await.fresh(3, &climb));
+        assert!(console.reply("clear 4").await.starts_with("error:"));
+
+        assert_eq!(console.reply("disconnect 3").await, "Disconnecting 3");
+        assert_eq!(session.disconnected.try_recv(), Ok(()));
+        assert!(console.reply("disconnect 3").await.starts_with("error:"));
+    }
+
+    #[tokio::test]
+    async fn test_state_changes_follow_the_lifecycle() {
+        let console = console();
+        console.state_machine.open().unwrap();
+
+        assert_eq!(console.reply("state").await, "Server is OPEN");
+        assert_eq!(
+            console.reply("state degraded").await,
+            "Server state OPEN -> DEGRADED"
+        );
+        assert_eq!(
+            console.reply("state open").await,
+            "Server state DEGRADED -> OPEN"
+        );
+        console.reply("state draining").await;
+        assert_eq!(
+            console.reply("state open").await,
+            "error: Server cannot go from DRAINING to OPEN"
+        );
+        assert_eq!(console.state_machine.get_state(), State::DRAINING);
+    }
+
+    #[tokio::test]
+    async fn test_serve_answers_each_line() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let serving = tokio::spawn(console().serve(listener));
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(b"state\n\nfly\n").await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut reply = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut reply)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            reply,
+            "Server is STARTING\nerror: Unknown command fly, try help\n"
+        );
+        serving.abort();
+    }
+}
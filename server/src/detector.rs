@@ -0,0 +1,256 @@
+use crate::broad_phase::candidate_pairs;
+use crate::kalman::TrackingConfig;
+use crate::track::{Track, ticks};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use utils::vector::Vector3;
+
+/// Identifier of an aircraft, as carried in the packet header.
+pub type PlaneId = u8;
+
+/// A predicted loss of separation between two aircraft.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conflict {
+    /// Aircraft with the lower id.
+    pub a: PlaneId,
+    /// Aircraft with the higher id.
+    pub b: PlaneId,
+    /// Distance between the aircraft at their closest point of approach.
+    pub cpa: f32,
+    /// Cycles until the closest point of approach.
+    pub time: u32,
+}
+
+/// Parameters of the short term conflict detector.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DetectorConfig {
+    /// Number of cycles to extrapolate each track.
+    pub max_cycles: u32,
+    /// Distance under which two aircraft are in conflict.
+    pub tolerance: f32,
+}
+
+/// Filtered position and per-cycle velocity of a track.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackState {
+    pub id: PlaneId,
+    pub position: Vector3,
+    pub velocity: Vector3,
+    /// One standard deviation of the position error.
+    pub uncertainty: f32,
+}
+
+/// Take a snapshot of every fresh track whose filter has seen at least two reports, ordered by id.
+/// Each filter is extrapolated from its last report to `now`, so that all states share one time.
+pub fn track_states(
+    tracks: &HashMap<PlaneId, Track>,
+    now: Instant,
+    tick: Duration,
+    config: &TrackingConfig,
+) -> Vec<TrackState> {
+    let mut states: Vec<TrackState> = tracks
+        .iter()
+        .filter(|(_, track)| !track.stale)
+        .filter_map(|(id, track)| {
+            let filter = track.filter.filter(|f| f.updates >= 2)?;
+            let filter = filter.predicted(ticks(track.age(now)?, tick), config);
+            Some(TrackState {
+                id: *id,
+                position: filter.position(),
+                velocity: filter.velocity(),
+                uncertainty: filter.position_uncertainty(),
+            })
+        })
+        .collect();
+    states.sort_by_key(|state| state.id);
+    states
+}
+
+/// Time in cycles and distance of the closest approach of two tracks within the look-ahead.
+/// Tracks fly straight between reports, so the approach is solved exactly instead of once per
+/// cycle, which would miss fast aircraft passing each other between two cycles.
+fn closest_approach(a: &TrackState, b: &TrackState, config: &DetectorConfig) -> (f32, f32) {
+    Vector3::closest_approach_within(
+        a.position,
+        a.velocity,
+        b.position,
+        b.velocity,
+        config.max_cycles as f32,
+    )
+}
+
+/// Check whether two tracks come within the tolerance, widened by the uncertainty of both,
+/// at any time from now to the end of the look-ahead.
+pub fn conflicting(a: &TrackState, b: &TrackState, config: &DetectorConfig) -> bool {
+    closest_approach(a, b, config).1 < config.tolerance + a.uncertainty + b.uncertainty
+}
+
+/// Detect conflicts between the given tracks.
+/// A grid based broad phase selects nearby pairs, each of which is then checked exactly.
+/// The separation is widened by the position uncertainty of both tracks.
+/// Each pair is reported once, ordered by id.
+pub fn detect_conflicts(states: &[TrackState], config: &DetectorConfig) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    for (i, j) in candidate_pairs(states, config.max_cycles, config.tolerance) {
+        let (a, b) = if states[i].id < states[j].id {
+            (&states[i], &states[j])
+        } else {
+            (&states[j], &states[i])
+        };
+        let (time, cpa) = closest_approach(a, b, config);
+        if cpa >= config.tolerance + a.uncertainty + b.uncertainty {
+            continue;
+        }
+
+        conflicts.push(Conflict {
+            a: a.id,
+            b: b.id,
+            cpa,
+            time: time.round() as u32,
+        });
+    }
+    conflicts.sort_by_key(|c| (c.a, c.b));
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Sample;
+
+    const CONFIG: DetectorConfig = DetectorConfig {
+        max_cycles: 3,
+        tolerance: 2.0,
+    };
+    const TRACKING: TrackingConfig = TrackingConfig {
+        horizontal_process_noise: 0.01,
+        vertical_process_noise: 0.01,
+        measurement_noise: 0.0001,
+        stale_after_ms: 3000,
+        clock_tolerance_ms: 5000,
+    };
+    const TICK: Duration = Duration::from_millis(1000);
+
+    /// A track with one report per tick, the last one received at `now`.
+    fn track_at(samples: &[Vector3], now: Instant) -> Track {
+        let mut track = Track::default();
+        for (i, position) in samples.iter().enumerate() {
+            let received = now - TICK * (samples.len() - 1 - i) as u32;
+            track.push(Sample::new(*position, received, None), TICK, &TRACKING);
+        }
+        track
+    }
+
+    fn track(samples: &[Vector3]) -> Track {
+        track_at(samples, Instant::now())
+    }
+
+    fn states(tracks: &HashMap<PlaneId, Track>) -> Vec<TrackState> {
+        track_states(tracks, Instant::now(), TICK, &TRACKING)
+    }
+
+    #[test]
+    fn test_detect_head_on() {
+        let mut tracks = HashMap::new();
+        tracks.insert(
+            9,
+            track(&[Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0)]),
+        );
+        tracks.insert(
+            4,
+            track(&[Vector3::new(6.0, 0.0, 0.0), Vector3::new(5.0, 0.0, 0.0)]),
+        );
+
+        let conflicts = detect_conflicts(&states(&tracks), &CONFIG);
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!((conflicts[0].a, conflicts[0].b), (4, 9));
+        assert_eq!(conflicts[0].time, 2);
+        assert!(conflicts[0].cpa < 0.01);
+    }
+
+    #[test]
+    fn test_detect_ignores_short_and_distant_tracks() {
+        let mut tracks = HashMap::new();
+        tracks.insert(1, track(&[Vector3::new(0.0, 0.0, 0.0)]));
+        tracks.insert(
+            2,
+            track(&[Vector3::new(0.0, 1.0, 0.0), Vector3::new(1.0, 1.0, 0.0)]),
+        );
+        tracks.insert(
+            3,
+            track(&[Vector3::new(0.0, 20.0, 0.0), Vector3::new(1.0, 20.0, 0.0)]),
+        );
+
+        assert_eq!(states(&tracks).len(), 2);
+        assert!(detect_conflicts(&states(&tracks), &CONFIG).is_empty());
+    }
+
+    #[test]
+    fn test_track_states_extrapolates_to_now() {
+        let now = Instant::now();
+        let mut tracks = HashMap::new();
+        // Last report received one and a half ticks ago.
+        tracks.insert(
+            1,
+            track_at(
+                &[Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 0.0, 0.0)],
+                now - Duration::from_millis(1500),
+            ),
+        );
+        tracks.insert(
+            2,
+            track_at(
+                &[Vector3::new(0.0, 5.0, 0.0), Vector3::new(2.0, 5.0, 0.0)],
+                now,
+            ),
+        );
+
+        let states = track_states(&tracks, now, TICK, &TRACKING);
+
+        assert!((states[0].position.x - 5.0).abs() < 0.01);
+        assert!((states[0].velocity.x - 2.0).abs() < 0.01);
+        assert!(states[0].uncertainty > states[1].uncertainty);
+    }
+
+    #[test]
+    fn test_track_states_skips_stale_tracks() {
+        let now = Instant::now();
+        let mut tracks = HashMap::new();
+        tracks.insert(
+            1,
+            track_at(
+                &[Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0)],
+                now - Duration::from_millis(5000),
+            ),
+        );
+        for track in tracks.values_mut() {
+            track.mark_stale(now, &TRACKING);
+        }
+
+        assert!(track_states(&tracks, now, TICK, &TRACKING).is_empty());
+    }
+
+    #[test]
+    fn test_detect_widens_separation_by_uncertainty() {
+        let mut a = TrackState {
+            id: 1,
+            position: Vector3::new(0.0, 0.0, 0.0),
+            velocity: Vector3::new(1.0, 0.0, 0.0),
+            uncertainty: 0.0,
+        };
+        let mut b = TrackState {
+            id: 2,
+            position: Vector3::new(0.0, 3.0, 0.0),
+            velocity: Vector3::new(1.0, 0.0, 0.0),
+            uncertainty: 0.0,
+        };
+        assert!(detect_conflicts(&[a, b], &CONFIG).is_empty());
+
+        a.uncertainty = 0.6;
+        b.uncertainty = 0.6;
+        assert_eq!(detect_conflicts(&[a, b], &CONFIG).len(), 1);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::detector::{Conflict, DetectorConfig, PlaneId, TrackState};
+use crate::zone::{Zone, clear_of};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Something an aircraft may have to be advised away from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Encounter {
+    /// A conflict between two aircraft, the lower id first.
+    Conflict(PlaneId, PlaneId),
+    /// An aircraft about to enter a zone, by name.
+    Zone(PlaneId, String),
+}
+
+/// Stage an encounter has reached. Resolved encounters are forgotten.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    /// The detector has seen the encounter, no advisory was sent for it.
+    Detected,
+    /// A resolution advisory was sent for the encounter.
+    Advised,
+}
+
+/// Every open encounter and the stage it has reached.
+#[derive(Debug, Clone, Default)]
+pub struct Encounters {
+    phases: HashMap<Encounter, Phase>,
+}
+
+impl Encounter {
+    /// Encounter for a conflict, with its aircraft in order.
+    pub fn conflict(conflict: &Conflict) -> Encounter {
+        Encounter::Conflict(conflict.a.min(conflict.b), conflict.a.max(conflict.b))
+    }
+
+    /// Aircraft involved in the encounter.
+    pub fn members(&self) -> Vec<PlaneId> {
+        match self {
+            Encounter::Conflict(a, b) => vec![*a, *b],
+            Encounter::Zone(plane_id, _) => vec![*plane_id],
+        }
+    }
+}
+
+impl fmt::Display for Encounter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Encounter::Conflict(a, b) => write!(f, "conflict between {} and {}", a, b),
+            Encounter::Zone(plane_id, zone) => write!(f, "plane {} near zone {}", plane_id, zone),
+        }
+    }
+}
+
+/// Check whether two aircraft have passed their closest approach, or are not closing.
+fn diverging(a: &TrackState, b: &TrackState) -> bool {
+    let p = b.position.sub(a.position);
+    let v = b.velocity.sub(a.velocity);
+    p.x * v.x + p.y * v.y + p.z * v.z >= 0.0
+}
+
+impl Encounters {
+    /// Record an encounter seen by the detector. Returns true if it is new.
+    pub fn detect(&mut self, encounter: Encounter) -> bool {
+        if self.phases.contains_key(&encounter) {
+            return false;
+        }
+        self.phases.insert(encounter, Phase::Detected);
+        true
+    }
+
+    /// Record that an advisory was sent for an encounter. Returns true if it was not advised yet.
+    pub fn advise(&mut self, encounter: Encounter) -> bool {
+        self.phases.insert(encounter, Phase::Advised) != Some(Phase::Advised)
+    }
+
+    /// Stage of an encounter, if it is open.
+    pub fn phase(&self, encounter: &Encounter) -> Option<Phase> {
+        self.phases.get(encounter).copied()
+    }
+
+    /// Every open encounter with its stage, ordered by the aircraft involved.
+    pub fn open(&self) -> Vec<(Encounter, Phase)> {
+        let mut open: Vec<(Encounter, Phase)> = self
+            .phases
+            .iter()
+            .map(|(encounter, phase)| (encounter.clone(), *phase))
+            .collect();
+        open.sort_by_key(|(encounter, _)| encounter.members());
+        open
+    }
+
+    /// Check whether an aircraft has an advisory out for any open encounter.
+    pub fn is_advised(&self, plane_id: PlaneId) -> bool {
+        self.phases
+            .iter()
+            .any(|(e, phase)| *phase == Phase::Advised && e.members().contains(&plane_id))
+    }
+
+    /// Close every encounter that is over, and return them with the stage they had reached.
+    /// A conflict is over once it is no longer detected and the aircraft have passed their
+    /// closest approach. A zone encounter is over once the aircraft's path stays clear of the
+    /// zone's outline. Encounters of aircraft that are no longer tracked are over too.
+    pub fn resolve(
+        &mut self,
+        states: &[TrackState],
+        conflicts: &[Conflict],
+        zones: &[Zone],
+        detector: &DetectorConfig,
+    ) -> Vec<(Encounter, Phase)> {
+        let state = |id: &PlaneId| states.iter().find(|s| s.id == *id);
+        let over = |encounter: &Encounter| match encounter {
+            Encounter::Conflict(a, b) => match (state(a), state(b)) {
+                (Some(a), Some(b)) => {
+                    !conflicts
+                        .iter()
+                        .any(|c| Encounter::conflict(c) == *encounter)
+                        && diverging(a, b)
+                }
+                _ => true,
+            },
+            Encounter::Zone(plane_id, name) => {
+                match (state(plane_id), zones.iter().find(|z| z.name == *name)) {
+                    (Some(state), Some(zone)) => clear_of(state, zone, detector),
+                    _ => true,
+                }
+            }
+        };
+
+        let mut resolved: Vec<(Encounter, Phase)> = self
+            .phases
+            .iter()
+            .filter(|(encounter, _)| over(encounter))
+            .map(|(encounter, phase)| (encounter.clone(), *phase))
+            .collect();
+        for (encounter, _) in &resolved {
+            self.phases.remove(encounter);
+        }
+        resolved.sort_by_key(|(encounter, _)| encounter.members());
+        resolved
+    }
+
+    /// Aircraft that had an advisory out for a resolved encounter and none left for any open one.
+    /// They are clear of conflict and may return to their cleared profile.
+    pub fn cleared(&self, resolved: &[(Encounter, Phase)]) -> Vec<PlaneId> {
+        let mut planes: Vec<PlaneId> = resolved
+            .iter()
+            .filter(|(_, phase)| *phase == Phase::Advised)
+            .flat_map(|(encounter, _)| encounter.members())
+            .filter(|plane_id| !self.is_advised(*plane_id))
+            .collect();
+        planes.sort();
+        planes.dedup();
+        planes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::{Shape, ZoneKind};
+    use utils::vector::Vector3;
+
+    const DETECTOR: DetectorConfig = DetectorConfig {
+        max_cycles: 3,
+        tolerance: 2.0,
+    };
+
+    fn state(id: PlaneId, x: f32, vx: f32) -> TrackState {
+        TrackState {
+            id,
+            position: Vector3::new(x, 0.0, 10.0),
+            velocity: Vector3::new(vx, 0.0, 0.0),
+            uncertainty: 0.0,
+        }
+    }
+
+    fn conflict(a: PlaneId, b: PlaneId) -> Conflict {
+        Conflict {
+            a,
+            b,
+            cpa: 0.0,
+            time: 2,
+        }
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let mut encounters = Encounters::default();
+        let encounter = Encounter::conflict(&conflict(2, 1));
+
+        assert!(encounters.detect(encounter.clone()));
+        assert!(!encounters.detect(encounter.clone()));
+        assert_eq!(encounters.phase(&encounter), Some(Phase::Detected));
+        assert!(!encounters.is_advised(1));
+        assert!(encounters.advise(encounter.clone()));
+        assert!(!encounters.advise(encounter.clone()));
+        assert!(!encounters.detect(encounter.clone()));
+        assert_eq!(encounters.phase(&encounter), Some(Phase::Advised));
+        assert!(encounters.is_advised(1) && encounters.is_advised(2));
+
+        encounters.detect(Encounter::Zone(1, String::from("Range")));
+        assert_eq!(
+            encounters.open(),
+            vec![
+                (Encounter::Zone(1, String::from("Range")), Phase::Detected),
+                (encounter, Phase::Advised)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conflicts_resolve_past_closest_approach() {
+        let mut encounters = Encounters::default();
+        encounters.advise(Encounter::Conflict(1, 2));
+
+        // Still closing, though the advisory already keeps them apart.
+        let closing = [state(1, 0.0, 1.0), state(2, 10.0, -1.0)];
+        assert!(encounters.resolve(&closing, &[], &[], &DETECTOR).is_empty());
+
+        // Still detected while diverging.
+        let passed = [state(1, 6.0, 1.0), state(2, 4.0, -1.0)];
+        assert!(
+            encounters
+                .resolve(&passed, &[conflict(1, 2)], &[], &DETECTOR)
+                .is_empty()
+        );
+
+        let resolved = encounters.resolve(&passed, &[], &[], &DETECTOR);
+        assert_eq!(resolved, vec![(Encounter::Conflict(1, 2), Phase::Advised)]);
+        assert_eq!(encounters.cleared(&resolved), vec![1, 2]);
+        assert_eq!(encounters.phase(&Encounter::Conflict(1, 2)), None);
+    }
+
+    #[test]
+    fn test_cleared_waits_for_every_advisory() {
+        let mut encounters = Encounters::default();
+        encounters.advise(Encounter::Conflict(1, 2));
+        encounters.advise(Encounter::Conflict(2, 3));
+        encounters.detect(Encounter::Conflict(4, 5));
+
+        // 1 and 2 have passed each other, 3 is still closing on 2, 5 has left.
+        let states = [
+            state(1, 6.0, 1.0),
+            state(2, 4.0, -1.0),
+            state(3, -4.0, 1.0),
+            state(4, 20.0, 0.0),
+        ];
+        let resolved = encounters.resolve(&states, &[], &[], &DETECTOR);
+
+        assert_eq!(
+            resolved,
+            vec![
+                (Encounter::Conflict(1, 2), Phase::Advised),
+                (Encounter::Conflict(4, 5), Phase::Detected),
+            ]
+        );
+        assert_eq!(encounters.cleared(&resolved), vec![1]);
+    }
+
+    #[test]
+    fn test_zone_encounters_resolve_once_past_the_zone() {
+        let zone = Zone {
+            name: String::from("Range"),
+            kind: ZoneKind::Restricted,
+            floor: 0.0,
+            ceiling: 20.0,
+            shape: Shape::Cylinder {
+                center: [10.0, 0.0],
+                radius: 3.0,
+            },
+        };
+        let zones = [zone];
+        let mut encounters = Encounters::default();
+        encounters.advise(Encounter::Zone(1, String::from("Range")));
+
+        let over = [state(1, 12.0, 1.0)];
+        assert!(encounters.resolve(&over, &[], &zones, &DETECTOR).is_empty());
+
+        let past = [state(1, 16.0, 1.0)];
+        let resolved = encounters.resolve(&past, &[], &zones, &DETECTOR);
+        assert_eq!(encounters.cleared(&resolved), vec![1]);
+    }
+}
@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use utils::vector::Vector3;
+
+/// Size of the live picture and of the archive kept for each aircraft.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Most recent reports kept at full rate.
+    pub live_capacity: usize,
+    /// One in this many reports leaving the live picture is archived.
+    pub downsample_factor: usize,
+    /// Archived reports kept before the oldest are dropped.
+    pub archive_retention: usize,
+    /// Time between two reports of the memory used by each track.
+    pub report_interval_ms: u64,
+}
+
+/// A reported position, stamped with the time it was received and the time the client sent it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub position: Vector3,
+    /// Server time the report was received.
+    pub received: Instant,
+    /// Client time since the UNIX epoch, if the client sent one.
+    pub sent: Option<Duration>,
+}
+
+impl Sample {
+    /// Create a new Sample.
+    pub fn new(position: Vector3, received: Instant, sent: Option<Duration>) -> Sample {
+        Sample {
+            position,
+            received,
+            sent,
+        }
+    }
+
+    /// Time elapsed since an earlier sample.
+    /// Client times are preferred since they are free of network jitter, unless they run backwards
+    /// or stray further than `tolerance` from the receive times. Samples sent at the same time
+    /// are none apart.
+    pub fn elapsed_since(&self, earlier: &Sample, tolerance: Duration) -> Duration {
+        let received = self.received.saturating_duration_since(earlier.received);
+        match (self.sent, earlier.sent) {
+            (Some(sent), Some(earlier_sent)) if sent == earlier_sent => Duration::ZERO,
+            (Some(sent), Some(earlier_sent))
+                if sent > earlier_sent && (sent - earlier_sent).abs_diff(received) <= tolerance =>
+            {
+                sent - earlier_sent
+            }
+            _ => received,
+        }
+    }
+}
+
+/// Bounded report history of one aircraft.
+/// Recent reports live in a ring buffer, older ones are downsampled into an archive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    live: VecDeque<Sample>,
+    archive: VecDeque<Sample>,
+    /// Reports evicted from the live picture so far, used to pick the ones to archive.
+    evicted: usize,
+    config: HistoryConfig,
+}
+
+impl History {
+    /// Create an empty history, allocating the live ring buffer up front.
+    pub fn new(config: &HistoryConfig) -> History {
+        History {
+            live: VecDeque::with_capacity(config.live_capacity),
+            archive: VecDeque::new(),
+            evicted: 0,
+            config: *config,
+        }
+    }
+
+    /// Record a report, moving the oldest live one to the archive if the buffer is full.
+    pub fn push(&mut self, sample: Sample) {
+        if self.live.len() >= self.config.live_capacity.max(1)
+            && let Some(oldest) = self.live.pop_front()
+        {
+            if self
+                .evicted
+                .is_multiple_of(self.config.downsample_factor.max(1))
+            {
+                self.archive.push_back(oldest);
+                while self.archive.len() > self.config.archive_retention {
+                    self.archive.pop_front();
+                }
+            }
+            self.evicted += 1;
+        }
+        self.live.push_back(sample);
+    }
+
+    /// Most recent report.
+    pub fn last(&self) -> Option<&Sample> {
+        self.live.back()
+    }
+
+    /// Live reports, oldest first.
+    pub fn live(&self) -> &VecDeque<Sample> {
+        &self.live
+    }
+
+    /// Archived reports, oldest first.
+    pub fn archive(&self) -> &VecDeque<Sample> {
+        &self.archive
+    }
+
+    /// Bytes used by this history, allocated capacity included.
+    pub fn memory_bytes(&self) -> usize {
+        std::mem::size_of::<History>()
+            + (self.live.capacity() + self.archive.capacity()) * std::mem::size_of::<Sample>()
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new(&HistoryConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: HistoryConfig = HistoryConfig {
+        live_capacity: 4,
+        downsample_factor: 3,
+        archive_retention: 2,
+        report_interval_ms: 60000,
+    };
+
+    fn sample(i: usize) -> Sample {
+        Sample::new(position(i), Instant::now(), None)
+    }
+
+    fn position(i: usize) -> Vector3 {
+        Vector3::new(i as f32, 0.0, 0.0)
+    }
+
+    fn positions(samples: &VecDeque<Sample>) -> Vec<Vector3> {
+        samples.iter().map(|s| s.position).collect()
+    }
+
+    #[test]
+    fn test_elapsed_since() {
+        let start = Instant::now();
+        let first = Sample::new(position(0), start, Some(Duration::from_millis(1000)));
+        let second = Sample::new(
+            position(1),
+            start + Duration::from_millis(1300),
+            Some(Duration::from_millis(2000)),
+        );
+        let unstamped = Sample::new(position(1), start + Duration::from_millis(1300), None);
+        let rewound = Sample::new(
+            position(1),
+            start + Duration::from_millis(1300),
+            Some(Duration::from_millis(500)),
+        );
+
+        // A client clock far in the future is not trusted.
+        let future = Sample::new(
+            position(1),
+            start + Duration::from_millis(1300),
+            Some(Duration::from_secs(u64::MAX / 2)),
+        );
+        let duplicate = Sample::new(position(1), start + Duration::from_secs(5), first.sent);
+        let tolerance = Duration::from_millis(500);
+
+        assert_eq!(
+            second.elapsed_since(&first, tolerance),
+            Duration::from_millis(1000)
+        );
+        assert_eq!(
+            unstamped.elapsed_since(&first, tolerance),
+            Duration::from_millis(1300)
+        );
+        assert_eq!(
+            rewound.elapsed_since(&first, tolerance),
+            Duration::from_millis(1300)
+        );
+        assert_eq!(
+            future.elapsed_since(&first, tolerance),
+            Duration::from_millis(1300)
+        );
+        assert_eq!(
+            second.elapsed_since(&first, Duration::from_millis(200)),
+            Duration::from_millis(1300)
+        );
+        assert_eq!(duplicate.elapsed_since(&first, tolerance), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_live_is_bounded() {
+        let mut history = History::new(&CONFIG);
+        for i in 0..100 {
+            history.push(sample(i));
+        }
+
+        assert_eq!(history.live().len(), 4);
+        assert_eq!(
+            history.live().front().map(|s| s.position),
+            Some(position(96))
+        );
+        assert_eq!(history.last().map(|s| s.position), Some(position(99)));
+    }
+
+    #[test]
+    fn test_archive_downsamples_and_retains() {
+        let mut history = History::new(&CONFIG);
+        for i in 0..10 {
+            history.push(sample(i));
+        }
+
+        // Reports 0 to 5 were evicted, one in three was archived.
+        assert_eq!(positions(history.archive()), vec![position(0), position(3)]);
+
+        for i in 10..13 {
+            history.push(sample(i));
+        }
+        assert_eq!(positions(history.archive()), vec![position(3), position(6)]);
+    }
+
+    #[test]
+    fn test_memory_is_bounded() {
+        let mut history = History::new(&CONFIG);
+        for i in 0..10 {
+            history.push(sample(i));
+        }
+        let settled = history.memory_bytes();
+        for i in 10..10000 {
+            history.push(sample(i));
+        }
+
+        assert_eq!(history.memory_bytes(), settled);
+    }
+}
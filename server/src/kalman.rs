@@ -0,0 +1,290 @@
+use serde::{Deserialize, Serialize};
+use utils::vector::Vector3;
+
+/// Noise parameters of the track filter.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackingConfig {
+    /// Variance of the unmodelled horizontal acceleration, per tick squared.
+    pub horizontal_process_noise: f32,
+    /// Variance of the unmodelled vertical acceleration, per tick squared.
+    /// Higher than the horizontal one since advisories start climbs abruptly.
+    pub vertical_process_noise: f32,
+    /// Variance of a reported position on each axis.
+    pub measurement_noise: f32,
+    /// Time without reports after which a track is stale and left out of detection.
+    pub stale_after_ms: u64,
+    /// Most the time a client says passed between two reports may differ from the time between
+    /// their arrivals, beyond which the arrival times are used instead.
+    pub clock_tolerance_ms: u64,
+}
+
+/// Constant velocity Kalman filter along a single axis.
+/// State is [position, velocity], covariance is the matching 2x2 matrix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisFilter {
+    pub state: [f32; 2],
+    pub covariance: [[f32; 2]; 2],
+}
+
+/// Velocity variance of a track that has only been seen once.
+const UNKNOWN_VELOCITY_VARIANCE: f32 = 1.0e6;
+
+impl AxisFilter {
+    /// Start from a first measurement, with an unknown velocity.
+    pub fn new(measurement: f32, measurement_noise: f32) -> AxisFilter {
+        AxisFilter {
+            state: [measurement, 0.0],
+            covariance: [[measurement_noise, 0.0], [0.0, UNKNOWN_VELOCITY_VARIANCE]],
+        }
+    }
+
+    /// Propagate the state dt ticks forward.
+    pub fn predict(&mut self, dt: f32, process_noise: f32) {
+        let [[p00, p01], [p10, p11]] = self.covariance;
+        self.state = [self.state[0] + self.state[1] * dt, self.state[1]];
+
+        // P = F P F' + Q, with F = [[1, dt], [0, 1]] and white noise acceleration Q.
+        let q00 = process_noise * dt.powi(4) / 4.0;
+        let q01 = process_noise * dt.powi(3) / 2.0;
+        let q11 = process_noise * dt.powi(2);
+        self.covariance = [
+            [
+                p00 + dt * (p01 + p10) + dt * dt * p11 + q00,
+                p01 + dt * p11 + q01,
+            ],
+            [p10 + dt * p11 + q01, p11 + q11],
+        ];
+    }
+
+    /// Correct the state with a position measurement.
+    pub fn update(&mut self, measurement: f32, measurement_noise: f32) {
+        let [[p00, p01], [p10, p11]] = self.covariance;
+        let innovation = measurement - self.state[0];
+        let innovation_variance = p00 + measurement_noise;
+        let gain = [p00 / innovation_variance, p10 / innovation_variance];
+
+        self.state = [
+            self.state[0] + gain[0] * innovation,
+            self.state[1] + gain[1] * innovation,
+        ];
+        self.covariance = [
+            [(1.0 - gain[0]) * p00, (1.0 - gain[0]) * p01],
+            [p10 - gain[1] * p00, p11 - gain[1] * p01],
+        ];
+    }
+}
+
+/// Per-aircraft track filter, one constant velocity filter per axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KalmanFilter {
+    pub axes: [AxisFilter; 3],
+    /// Number of measurements folded into the filter.
+    pub updates: u32,
+}
+
+impl KalmanFilter {
+    /// Start a filter at the first reported position.
+    pub fn new(measurement: Vector3, config: &TrackingConfig) -> KalmanFilter {
+        let r = config.measurement_noise;
+        KalmanFilter {
+            axes: [
+                AxisFilter::new(measurement.x, r),
+                AxisFilter::new(measurement.y, r),
+                AxisFilter::new(measurement.z, r),
+            ],
+            updates: 1,
+        }
+    }
+
+    /// Advance the filter dt ticks and fold in a new reported position.
+    pub fn step(&mut self, measurement: Vector3, dt: f32, config: &TrackingConfig) {
+        let values = [measurement.x, measurement.y, measurement.z];
+        for ((axis, q), z) in self.axes.iter_mut().zip(process_noise(config)).zip(values) {
+            axis.predict(dt, q);
+            axis.update(z, config.measurement_noise);
+        }
+        self.updates += 1;
+    }
+
+    /// The filter extrapolated dt ticks forward without a new report.
+    /// The position uncertainty grows with dt.
+    pub fn predicted(&self, dt: f32, config: &TrackingConfig) -> KalmanFilter {
+        let mut filter = *self;
+        for (axis, q) in filter.axes.iter_mut().zip(process_noise(config)) {
+            axis.predict(dt, q);
+        }
+        filter
+    }
+
+    /// Filtered position.
+    pub fn position(&self) -> Vector3 {
+        Vector3::new(
+            self.axes[0].state[0],
+            self.axes[1].state[0],
+            self.axes[2].state[0],
+        )
+    }
+
+    /// Filtered velocity, per tick.
+    pub fn velocity(&self) -> Vector3 {
+        Vector3::new(
+            self.axes[0].state[1],
+            self.axes[1].state[1],
+            self.axes[2].state[1],
+        )
+    }
+
+    /// One standard deviation of the position error, combined over the three axes.
+    pub fn position_uncertainty(&self) -> f32 {
+        self.axes
+            .iter()
+            .map(|axis| axis.covariance[0][0])
+            .sum::<f32>()
+            .sqrt()
+    }
+}
+
+/// Process noise of the x, y and z axes.
+fn process_noise(config: &TrackingConfig) -> [f32; 3] {
+    [
+        config.horizontal_process_noise,
+        config.horizontal_process_noise,
+        config.vertical_process_noise,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: TrackingConfig = TrackingConfig {
+        horizontal_process_noise: 0.01,
+        vertical_process_noise: 0.01,
+        measurement_noise: 0.09,
+        stale_after_ms: 3000,
+        clock_tolerance_ms: 5000,
+    };
+
+    /// Gaussian noise from a linear congruential generator and the Box-Muller transform.
+    struct Noise(u64);
+
+    impl Noise {
+        fn uniform(&mut self) -> f32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((self.0 >> 40) as f32 + 1.0) / (1u64 << 24) as f32
+        }
+
+        fn gaussian(&mut self, std_dev: f32) -> f32 {
+            let (u1, u2) = (self.uniform(), self.uniform());
+            (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos() * std_dev
+        }
+    }
+
+    #[test]
+    fn test_axis_filter_tracks_constant_velocity() {
+        let mut axis = AxisFilter::new(0.0, 0.1);
+        for t in 1..=10 {
+            axis.predict(1.0, 0.01);
+            axis.update(2.0 * t as f32, 0.1);
+        }
+
+        assert!((axis.state[0] - 20.0).abs() < 1.0e-3);
+        assert!((axis.state[1] - 2.0).abs() < 1.0e-3);
+        assert!(axis.covariance[0][0] < 0.1);
+    }
+
+    #[test]
+    fn test_filter_beats_raw_differencing_on_noisy_tracks() {
+        let mut noise = Noise(74000);
+        let velocity = Vector3::new(3.0, -2.0, 0.5);
+        let std_dev = 0.3;
+
+        let mut filter_error = 0.0;
+        let mut raw_error = 0.0;
+        let mut position_error = 0.0;
+        let mut samples = 0.0;
+        for track in 0..20 {
+            let start = Vector3::new(track as f32, 10.0, 100.0);
+            let truth = |t: u32| {
+                start.add(Vector3::new(
+                    velocity.x * t as f32,
+                    velocity.y * t as f32,
+                    velocity.z * t as f32,
+                ))
+            };
+            let mut measure = |t: u32| {
+                truth(t).add(Vector3::new(
+                    noise.gaussian(std_dev),
+                    noise.gaussian(std_dev),
+                    noise.gaussian(std_dev),
+                ))
+            };
+
+            let mut previous = measure(0);
+            let mut filter = KalmanFilter::new(previous, &CONFIG);
+            for t in 1..=30 {
+                let current = measure(t);
+                filter.step(current, 1.0, &CONFIG);
+
+                // Let the filter settle before scoring it.
+                if t > 10 {
+                    filter_error += Vector3::distance(filter.velocity(), velocity);
+                    raw_error += Vector3::distance(current.sub(previous), velocity);
+                    position_error += Vector3::distance(filter.position(), truth(t));
+                    samples += 1.0;
+                }
+                previous = current;
+            }
+        }
+        let filter_error = filter_error / samples;
+        let raw_error = raw_error / samples;
+        let position_error = position_error / samples;
+
+        // Raw differencing is off by about 0.67 per tick with this noise.
+        assert!(raw_error > 0.5, "raw velocity error {}", raw_error);
+        assert!(
+            filter_error < 0.15,
+            "filtered velocity error {}",
+            filter_error
+        );
+        assert!(
+            filter_error < raw_error / 4.0,
+            "filtered {} vs raw {}",
+            filter_error,
+            raw_error
+        );
+        // A single measurement is off by about 0.48 with this noise.
+        assert!(position_error < 0.35, "position error {}", position_error);
+    }
+
+    #[test]
+    fn test_uncertainty_shrinks_with_measurements() {
+        let mut filter = KalmanFilter::new(Vector3::new(0.0, 0.0, 0.0), &CONFIG);
+        let first = filter.position_uncertainty();
+        for t in 1..=10 {
+            filter.step(Vector3::new(t as f32, 0.0, 0.0), 1.0, &CONFIG);
+        }
+
+        assert!(filter.position_uncertainty() < first);
+        assert_eq!(filter.updates, 11);
+    }
+
+    #[test]
+    fn test_predicted_extrapolates_and_grows_uncertainty() {
+        let mut filter = KalmanFilter::new(Vector3::new(0.0, 0.0, 0.0), &CONFIG);
+        for t in 1..=10 {
+            filter.step(Vector3::new(2.0 * t as f32, 0.0, 0.0), 1.0, &CONFIG);
+        }
+
+        let predicted = filter.predicted(2.5, &CONFIG);
+
+        assert!((predicted.position().x - 25.0).abs() < 0.1);
+        assert_eq!(predicted.velocity(), filter.velocity());
+        assert!(predicted.position_uncertainty() > filter.position_uncertainty());
+        assert_eq!(predicted.updates, filter.updates);
+    }
+}
@@ -0,0 +1,23 @@
+pub mod alerting;
+pub mod arrival;
+pub mod broad_phase;
+pub mod cluster;
+pub mod config;
+pub mod console;
+pub mod detector;
+pub mod encounter;
+pub mod history;
+pub mod kalman;
+pub mod manager;
+pub mod plausibility;
+pub mod probe;
+pub mod registry;
+pub mod replay;
+pub mod resolution;
+pub mod sector;
+pub mod session;
+pub mod state_machine;
+pub mod track;
+pub mod upload;
+pub mod wake;
+pub mod zone;
@@ -1,25 +1,105 @@
-use crate::manager::Manager;
-use tracing;
-pub mod manager;
-pub mod state_machine;
+use server::config::Config;
+use server::manager::Manager;
+use server::replay::Recorder;
+use server::zone;
+use std::process::ExitCode;
 
 #[tokio::main]
-async fn main() {
-    let appender = tracing_appender::rolling::never("./server/log", "server.log");
-    let (non_blocking_appender, _guard) = tracing_appender::non_blocking(appender);
+async fn main() -> ExitCode {
+    // Load and validate the configuration before anything else, refusing to start on errors.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let zones = match zone::load(&config.zones) {
+        Ok(zones) => zones,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let recorder = match Recorder::create(&config.recording) {
+        Ok(recorder) => recorder,
+        Err(e) => {
+            eprintln!(
+                "Unable to create telemetry file {}: {e}",
+                config.recording.file
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let appender = tracing_appender::rolling::never(&config.log.directory, &config.log.file);
+    let (non_blocking_appender, guard) = tracing_appender::non_blocking(appender);
 
     tracing_subscriber::fmt()
         .with_writer(non_blocking_appender)
         .with_ansi(false)
         .init();
 
+    tracing::info!("Effective configuration:\n{config}");
+    for zone in &zones {
+        tracing::info!(
+            "Zone {}: {} from {} to {}",
+            zone.name,
+            zone.kind,
+            zone.floor,
+            zone.ceiling
+        );
+    }
+
     // Initialize and run server manager.
-    match Manager::new().run().await {
+    let code = match Manager::new(config, zones, recorder)
+        .run(shutdown_signal())
+        .await
+    {
         Ok(_) => {
             tracing::info!("Manager exited gracefully...");
+            ExitCode::SUCCESS
         }
         Err(e) => {
             tracing::error!("Manager exitied with error: {e}");
+            ExitCode::FAILURE
         }
+    };
+
+    // The appender writes from a background thread; dropping the guard flushes what is left.
+    drop(guard);
+    code
+}
+
+/// Resolves on the first SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Unable to listen for SIGINT: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Unable to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => tracing::info!("Received SIGINT"),
+        _ = terminate => tracing::info!("Received SIGTERM"),
     }
 }
@@ -1,375 +1,1988 @@
+use crate::alerting::{Alert, AlertFilter, AlertTier, traffic_advisories};
+use crate::arrival::{Arrival, Inbound, sequence};
+use crate::cluster::{Cluster, clusters};
+use crate::config::Config;
+use crate::console::Console;
+use crate::detector::{
+    Conflict, DetectorConfig, PlaneId, TrackState, conflicting, detect_conflicts, track_states,
+};
+use crate::encounter::{Encounter, Encounters};
+use crate::history::Sample;
+use crate::probe::{ProbeConflict, Trajectory, probe};
+use crate::registry::{Registration, SessionRegistry, hello_token};
+use crate::replay::{Event, Recorder};
+use crate::resolution::resolve_cluster;
+use crate::sector::{Occupancy, predict_overloads};
+use crate::session::{Session, SessionState};
 use crate::state_machine::{State, StateMachine};
+use crate::track::{Track, ticks};
+use crate::upload::Upload;
+use crate::wake::detect_wake;
+use crate::zone::{Zone, avoid, predict_incursions};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::Write;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::tcp::OwnedWriteHalf;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{Mutex, broadcast, mpsc};
-use tokio::time::{Duration, timeout};
-use utils::packet::{FlagState, Packet, PacketHeader, deserialize_packet, serialize_packet};
+use tokio::sync::{Mutex, mpsc, oneshot, watch};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use utils::flight_plan::FlightPlan;
+use utils::packet::{FlagState, Packet, PacketHeader, forward_packets, serialize_packet};
+use utils::report::Report;
+use utils::vector::DecodeError;
 use utils::vector::Vector3;
+use utils::wake::{WakeAdvisory, WakeCategory};
 
-/// Type to asynchronously store/share the coordinates of active plane coordinates.
-type Coordinates = Arc<Mutex<HashMap<u8, Vec<Vector3>>>>;
+/// Type to asynchronously store/share the tracks of active planes.
+pub(crate) type Coordinates = Arc<Mutex<HashMap<u8, Track>>>;
 
-#[derive(Debug)]
+/// Type to share the occupancy of each sector between the detector and the clients.
+pub type SectorStatus = Arc<Mutex<Occupancy>>;
+
+/// Type to asynchronously store/share the flight plans filed by active planes.
+type Plans = Arc<Mutex<HashMap<PlaneId, FlightPlan>>>;
+
+/// Type to asynchronously store/share the wake category each plane declared when it connected.
+pub type Categories = Arc<Mutex<HashMap<PlaneId, WakeCategory>>>;
+
+/// Type to asynchronously share the live client sessions by aircraft identity.
+pub(crate) type Sessions = Arc<Mutex<SessionRegistry>>;
+
+/// Type to share the open encounters between the detector and the operator console.
+pub(crate) type OpenEncounters = Arc<Mutex<Encounters>>;
+
+/// Type to share the alert filter between the detector and the client sessions.
+pub(crate) type SharedFilter = Arc<Mutex<AlertFilter>>;
+
+/// Type to share the telemetry recorder, if recording is enabled.
+pub(crate) type Recording = Option<Arc<Mutex<Recorder>>>;
+
+/// What a client session reacts to next.
+enum SessionEvent {
+    /// A packet queued for the aircraft.
+    Outgoing(Packet),
+    /// What the client sent, None once it stops sending.
+    Incoming(Option<Result<Packet, std::io::Error>>),
+    /// The client said nothing for too long.
+    Silent,
+    /// The departure of the aircraft can be released.
+    Released,
+    /// The server is shutting down.
+    Stopping,
+    /// Another session took the aircraft over.
+    TakenOver,
+    /// The operator ended the session.
+    Disconnected,
+}
+
+/// Task cancelled when dropped, so it never outlives the session that spawned it.
+struct AbortOnDrop<T>(tokio::task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct Manager {
     coordinates: Coordinates,
-    state_machine: StateMachine,
+    state_machine: Arc<StateMachine>,
+    config: Arc<Config>,
+    zones: Arc<Vec<Zone>>,
+    sectors: SectorStatus,
+    plans: Plans,
+    categories: Categories,
+    sessions: Sessions,
+    encounters: OpenEncounters,
+    filter: SharedFilter,
+    recording: Recording,
 }
 
 impl Manager {
-    /// Create a new Manager.
-    pub fn new() -> Manager {
+    /// Create a new Manager from a validated configuration, the zones to keep aircraft out of
+    /// and the recorder to write received telemetry to, if any.
+    pub fn new(config: Config, zones: Vec<Zone>, recorder: Option<Recorder>) -> Manager {
+        let sessions = SessionRegistry::new(config.network.queue_length);
         Manager {
             coordinates: Arc::new(Mutex::new(HashMap::new())),
-            state_machine: StateMachine::new(),
+            state_machine: Arc::new(StateMachine::new()),
+            config: Arc::new(config),
+            zones: Arc::new(zones),
+            sectors: Arc::new(Mutex::new(Occupancy::default())),
+            plans: Arc::new(Mutex::new(HashMap::new())),
+            categories: Arc::new(Mutex::new(HashMap::new())),
+            sessions: Arc::new(Mutex::new(sessions)),
+            encounters: Arc::new(Mutex::new(Encounters::default())),
+            filter: Arc::new(Mutex::new(AlertFilter::default())),
+            recording: recorder.map(|recorder| Arc::new(Mutex::new(recorder))),
         }
     }
 
-    /// Main logic loop of the manager class
-    pub async fn run(self) -> Result<(), std::io::Error> {
-        // Listen into port 8001 on localhost
-        let listener = TcpListener::bind("127.0.0.1:8001").await?;
-        let (col_sender, _) = broadcast::channel::<(u8, f32)>(100);
-        let (warn_sender, _) = broadcast::channel::<u8>(100);
+    /// Main logic loop of the manager class.
+    /// Serves clients until `shutdown` completes, then drains: stops accepting, tells every
+    /// connected client the server is shutting down and gives uploads in flight the shutdown
+    /// grace period to finish before they are cancelled.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) -> Result<(), std::io::Error> {
+        // Listen on the configured address.
+        let listener = match TcpListener::bind(&self.config.network.bind_address).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                let _ = self.state_machine.close();
+                return Err(e);
+            }
+        };
+        tracing::info!("Listening on {}", self.config.network.bind_address);
+
+        // Answer the operator console, for as long as the server runs.
+        let control_address = &self.config.network.control_address;
+        let _console = if control_address.is_empty() {
+            None
+        } else {
+            let control = match TcpListener::bind(control_address).await {
+                Ok(control) => control,
+                Err(e) => {
+                    let _ = self.state_machine.close();
+                    return Err(e);
+                }
+            };
+            tracing::info!("Console listening on {}", control_address);
+            Some(AbortOnDrop(tokio::spawn(self.console().serve(control))))
+        };
         let (exit_sender, mut exit_receiver) = mpsc::channel::<u8>(100);
 
         // Spawn task to handle client exits.
         let coord_clone = self.coordinates.clone();
+        let plans_clone = self.plans.clone();
+        let categories_clone = self.categories.clone();
+        let sectors_clone = self.sectors.clone();
+        let filter_clone = self.filter.clone();
+        let recording_clone = self.recording.clone();
         tokio::spawn(async move {
             while let Some(plane_id) = exit_receiver.recv().await {
                 tracing::info!("Client {} disconnected", plane_id);
-                let mut data = coord_clone.lock().await;
-                data.remove(&plane_id);
+                coord_clone.lock().await.remove(&plane_id);
+                sectors_clone.lock().await.cancel(plane_id);
+                plans_clone.lock().await.remove(&plane_id);
+                categories_clone.lock().await.remove(&plane_id);
+                filter_clone.lock().await.forget(plane_id);
+                if let Some(recorder) = &recording_clone {
+                    recorder
+                        .lock()
+                        .await
+                        .record(Instant::now(), plane_id, Event::Exit);
+                }
             }
         });
 
         // Spawn task to process new data.
         let coord_clone = self.coordinates.clone();
-        let col_sender_clone = col_sender.clone();
+        let sessions_clone = self.sessions.clone();
+        let config_clone = self.config.clone();
+        let zones_clone = self.zones.clone();
+        let sectors_clone = self.sectors.clone();
+        let categories_clone = self.categories.clone();
+        let encounters_clone = self.encounters.clone();
+        let filter_clone = self.filter.clone();
+        let state_machine = self.state_machine.clone();
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+            let tick = config_clone.timing.tick();
+            let mut interval = tokio::time::interval(tick);
+            // Whether this task degraded the server, and should open it again once it keeps up.
+            let mut overrun = false;
             loop {
                 interval.tick().await;
-                Self::process_data(&coord_clone, &col_sender_clone).await;
+                let state = state_machine.get_state();
+                if state == State::CLOSED {
+                    break;
+                }
+                if !state.detects() {
+                    continue;
+                }
+                let started = Instant::now();
+                let mut filter = filter_clone.lock().await;
+                let alerts = Self::process_data(
+                    &coord_clone,
+                    &config_clone,
+                    &zones_clone,
+                    &sectors_clone,
+                    &categories_clone,
+                    &mut *encounters_clone.lock().await,
+                    &mut filter,
+                    started,
+                )
+                .await;
+                // Queue each alert for the plane it is for. An alert that could not be queued is
+                // sent again next tick.
+                {
+                    let mut sessions = sessions_clone.lock().await;
+                    for (plane_id, alert) in alerts {
+                        if !sessions.deliver(plane_id, alert.to_packet(plane_id)) {
+                            filter.undelivered(plane_id, &alert);
+                        }
+                    }
+                }
+                drop(filter);
+                // Hold departures while detection cannot keep up with the tick.
+                let elapsed = started.elapsed();
+                if elapsed > tick && !overrun {
+                    tracing::warn!("Detection took {:?}, longer than a tick", elapsed);
+                    overrun = state_machine.degrade().is_ok();
+                } else if elapsed <= tick && overrun {
+                    overrun = false;
+                    let _ = state_machine.open();
+                }
             }
         });
 
-        // Listen for new client connections.
-        // Spawn task to handle client.
-        loop {
-            match self.state_machine.get_state() {
-                State::OPEN => {
-                    let (stream, addr) = listener.accept().await?;
-                    tracing::info!("New client connected: {}", addr);
-                    let coord_clone = self.coordinates.clone();
-                    let col_receiver = col_sender.subscribe();
-                    let exit_sender = exit_sender.clone();
-                    let warn_sender = warn_sender.clone();
-                    let warn_receiver = warn_sender.subscribe();
-                    tokio::spawn(Self::handle_client(
-                        stream,
-                        coord_clone,
-                        col_receiver,
-                        exit_sender,
-                        warn_sender,
-                        warn_receiver,
-                    ));
+        // Spawn task to probe the filed flight plans for medium term conflicts, and sequence
+        // the arrivals at each destination.
+        let coord_clone = self.coordinates.clone();
+        let plans_clone = self.plans.clone();
+        let categories_clone = self.categories.clone();
+        let config_clone = self.config.clone();
+        tokio::spawn(async move {
+            let period = tokio::time::Duration::from_millis(config_clone.probe.interval_ms);
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                Self::probe_plans(&coord_clone, &plans_clone, &categories_clone, &config_clone)
+                    .await;
+            }
+        });
+
+        // Spawn task to report the memory used by each track, the sector occupancy and the client
+        // delivery queues.
+        let coord_clone = self.coordinates.clone();
+        let sectors_clone = self.sectors.clone();
+        let sessions_clone = self.sessions.clone();
+        let config_clone = self.config.clone();
+        let state_machine = self.state_machine.clone();
+        tokio::spawn(async move {
+            let period =
+                tokio::time::Duration::from_millis(config_clone.history.report_interval_ms);
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let state = state_machine.get_state();
+                if state == State::CLOSED {
+                    break;
                 }
-                State::CLOSED => {}
+                tracing::info!("Server is {}", state);
+                Self::report_status(&coord_clone, &sectors_clone, &sessions_clone, &config_clone)
+                    .await;
             }
+        });
+
+        // Listen for new client connections while the state allows it, until asked to shut down.
+        // Spawn task to handle client.
+        let _ = self.state_machine.open();
+        tokio::pin!(shutdown);
+        let mut state = self.state_machine.subscribe();
+        let mut clients = JoinSet::new();
+        while self.state_machine.get_state().accepts() {
+            let (stream, addr) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = &mut shutdown => {
+                    tracing::info!("Shutting down, no longer accepting clients");
+                    if let Err(e) = self.state_machine.drain() {
+                        tracing::error!("{e}");
+                    }
+                    break;
+                }
+                _ = state.changed() => continue,
+            };
+            tracing::info!("New client connected: {}", addr);
+            // Forget the clients that have left since.
+            while clients.try_join_next().is_some() {}
+            let coord_clone = self.coordinates.clone();
+            let exit_sender = exit_sender.clone();
+            clients.spawn(Self::handle_client(
+                stream,
+                coord_clone,
+                exit_sender,
+                self.sectors.clone(),
+                self.plans.clone(),
+                self.categories.clone(),
+                self.sessions.clone(),
+                self.filter.clone(),
+                self.recording.clone(),
+                self.config.clone(),
+                self.state_machine.subscribe(),
+            ));
+        }
+        drop(listener);
+
+        // Draining tells every client, then wait for the uploads in flight to finish.
+        let grace = self.config.timing.shutdown_grace();
+        let finished = timeout(grace, async {
+            while clients.join_next().await.is_some() {}
+        })
+        .await;
+        if finished.is_err() {
+            tracing::warn!(
+                "{} clients still busy after {:?}, cancelling them",
+                clients.len(),
+                grace
+            );
+            clients.shutdown().await;
         }
+        tracing::info!("All clients closed");
+        if let Err(e) = self.state_machine.close() {
+            tracing::error!("{e}");
+        }
+        Ok(())
     }
 
-    /// Receive and process packets from a client.
+    /// Serve a client until its session ends.
+    /// The session decides which packets are legal; anything else is a protocol error that ends
+    /// it. A client with the identity of a live session is rejected, unless it takes the session
+    /// over. However the session ends, the manager is told to forget the aircraft, unless another
+    /// session took it over.
+    /// Packets are read on a task of their own, so the session reacts to whichever comes first of
+    /// a packet from the client, an alert queued for it, the client falling silent, a shutdown or
+    /// a takeover. Alerts are sent as soon as they are queued, whether or not the client is
+    /// talking.
+    #[allow(clippy::too_many_arguments)]
     pub async fn handle_client(
-        mut stream: TcpStream,
+        stream: TcpStream,
         coordinates: Coordinates,
-        mut col_receiver: broadcast::Receiver<(u8, f32)>,
         exit_sender: mpsc::Sender<u8>,
-        warn_sender: broadcast::Sender<u8>,
-        mut warn_receiver: broadcast::Receiver<u8>,
+        sectors: SectorStatus,
+        plans: Plans,
+        categories: Categories,
+        sessions: Sessions,
+        filter: SharedFilter,
+        recording: Recording,
+        config: Arc<Config>,
+        mut state: watch::Receiver<State>,
     ) {
-        let plane_id = match deserialize_packet(&mut stream).await {
-            Ok(p) => {
-                tracing::info!("Received ID: {p}");
-                p.header.plane_id
-            }
+        let peer = match stream.peer_addr() {
+            Ok(peer) => peer,
             Err(e) => {
+                tracing::error!("Unable to get address of client: {e}");
+                return;
+            }
+        };
+        let (reader, mut writer) = stream.into_split();
+        let (inbound_sender, mut inbound) = mpsc::channel(1);
+        let _reading = AbortOnDrop(tokio::spawn(forward_packets(reader, inbound_sender)));
+
+        let mut session = Session::new();
+        let hello = match timeout(config.timing.client_timeout(), inbound.recv()).await {
+            Ok(Some(Ok(p))) => p,
+            Ok(Some(Err(e))) => {
                 tracing::error!("Error deserializing packet: {e}");
                 return;
             }
+            Ok(None) => return,
+            Err(_) => {
+                tracing::error!("Timed out waiting for client {} to identify", peer);
+                return;
+            }
         };
+        if let Err(e) = session.receive(&hello.header) {
+            tracing::error!("Protocol error from new client: {e}");
+            return;
+        }
+        tracing::info!("Received ID: {hello}");
+        let plane_id = hello.header.plane_id;
 
-        loop {
-            tracing::info!("Waiting for next packet from client...");
-            // Read packet from stream.
-            let pkt = match timeout(Duration::from_secs(5), deserialize_packet(&mut stream)).await {
-                Ok(Ok(p)) => {
+        // Make sure no other client is flying this aircraft.
+        let token = hello_token(&hello.body, Report::size());
+        let registration = sessions.lock().await.register(plane_id, token, peer);
+        let Some(mut registration) = registration else {
+            let pkt = Self::flag_packet(FlagState::REJECTED, plane_id);
+            if let Err(e) = serialize_packet(pkt, &mut writer).await {
+                tracing::error!("Error sending packet: {e}");
+            }
+            return;
+        };
+
+        let departure = Report::from_bytes_checked(&hello.body, &config.airspace.bounds())
+            .ok()
+            .map(|report| report.position);
+        let category = WakeCategory::from_hello(&hello.body, Report::size());
+        tracing::info!("Client {} is {}", plane_id, category);
+        categories.lock().await.insert(plane_id, category);
+        if let Some(recorder) = &recording {
+            recorder
+                .lock()
+                .await
+                .record(Instant::now(), plane_id, Event::Category(category));
+        }
+
+        let current = *state.borrow();
+        if registration.took_over {
+            // The new client has not seen the alerts sent to the old one, so they are sent again.
+            filter.lock().await.forget(plane_id);
+        }
+        if registration.airborne {
+            // Already flying, carry on where the replaced session stopped.
+            Self::release(
+                &mut writer,
+                &mut session,
+                &sessions,
+                &registration,
+                plane_id,
+            )
+            .await;
+        } else if Self::try_clear(plane_id, departure, &sectors, &config, current).await {
+            tracing::info!("Released departure of {}", plane_id);
+            Self::release(
+                &mut writer,
+                &mut session,
+                &sessions,
+                &registration,
+                plane_id,
+            )
+            .await;
+        } else {
+            if current.releases() {
+                tracing::warn!("Holding departure of {}, sector is full", plane_id);
+            } else {
+                tracing::warn!("Holding departure of {}, server is {}", plane_id, current);
+            }
+            let pkt = Self::flag_packet(FlagState::HOLD, plane_id);
+            if let Err(e) = serialize_packet(pkt, &mut writer).await {
+                tracing::error!("Error sending packet: {e}");
+                session.close();
+            }
+        }
+
+        // Flight log being uploaded, once the client has started.
+        let mut upload: Option<Upload> = None;
+        // The client is silent for too long once this passes, reset by every packet.
+        let mut deadline = tokio::time::Instant::now() + config.timing.client_timeout();
+        while session.state() != SessionState::CLOSING {
+            // An upload in progress is finished before shutting down, unless it outlasts the
+            // shutdown grace period. An unfinished upload leaves no file behind.
+            let transferring = session.state() == SessionState::TRANSFERRING;
+            let held = session.state() == SessionState::IDENTIFIED;
+            let event = tokio::select! {
+                // Alerts go out before anything else is looked at, advisories first.
+                biased;
+                Some(pkt) = registration.urgent.recv() => SessionEvent::Outgoing(pkt),
+                Some(pkt) = registration.outbox.recv() => SessionEvent::Outgoing(pkt),
+                received = inbound.recv() => SessionEvent::Incoming(received),
+                _ = tokio::time::sleep_until(deadline) => SessionEvent::Silent,
+                _ = Self::departure_cleared(plane_id, departure, &sectors, &config, state.clone()), if held => SessionEvent::Released,
+                _ = Self::stopping(&mut state), if !transferring => SessionEvent::Stopping,
+                _ = Self::signalled(&mut registration.taken_over) => SessionEvent::TakenOver,
+                _ = Self::signalled(&mut registration.disconnected) => SessionEvent::Disconnected,
+            };
+            let pkt = match event {
+                SessionEvent::Outgoing(pkt) => {
+                    if let Err(e) = serialize_packet(pkt, &mut writer).await {
+                        tracing::error!("Error sending packet: {e}");
+                        session.close();
+                    }
+                    continue;
+                }
+                SessionEvent::Incoming(Some(Ok(p))) => {
                     tracing::info!("Received packet: {p}");
+                    deadline = tokio::time::Instant::now() + config.timing.client_timeout();
                     p
                 }
-                Ok(Err(e)) => {
+                SessionEvent::Incoming(Some(Err(e))) => {
                     tracing::error!("Error deserializing packet: {e}");
-                    return;
+                    session.close();
+                    continue;
                 }
-                Err(_) => {
+                SessionEvent::Incoming(None) => {
+                    session.close();
+                    continue;
+                }
+                SessionEvent::Released => {
+                    tracing::info!("Released departure of {}", plane_id);
+                    Self::release(
+                        &mut writer,
+                        &mut session,
+                        &sessions,
+                        &registration,
+                        plane_id,
+                    )
+                    .await;
+                    continue;
+                }
+                SessionEvent::Silent => {
                     tracing::error!("Timed out waiting for packet");
-                    if warn_sender.send(plane_id).is_err() {
-                        tracing::error!("Error sending exit flag to manager...");
+                    // Warn every other client, unless the aircraft never left the ground.
+                    if !held {
+                        let warning = Self::flag_packet(FlagState::WARNING, plane_id);
+                        sessions.lock().await.deliver_others(plane_id, &warning);
                     }
-
-                    return;
+                    session.close();
+                    continue;
+                }
+                SessionEvent::Stopping => {
+                    Self::notify_shutdown(&mut writer, plane_id).await;
+                    session.close();
+                    continue;
+                }
+                SessionEvent::TakenOver => {
+                    tracing::warn!("Session of {} taken over", plane_id);
+                    session.close();
+                    continue;
+                }
+                SessionEvent::Disconnected => {
+                    Self::notify_disconnect(&mut writer, plane_id).await;
+                    session.close();
+                    continue;
                 }
             };
 
-            println!("Received packet: {}", pkt);
+            if let Err(e) = session.receive(&pkt.header) {
+                tracing::error!("Protocol error from client {}: {}", plane_id, e);
+                session.close();
+                continue;
+            }
 
             //packet handler
             match pkt.header.flag {
                 FlagState::COORDINATE => {
-                    // tracing::info!("Packet is COORDINATE");
-                    // Read coordinates from packet body.
-                    let new_coord: Vector3 = match Vector3::from_bytes(pkt.body.as_slice()) {
-                        Some(c) => c,
-                        None => {
-                            tracing::error!("Unable to create Vector3 from bytes...");
-                            tracing::error!("Exiting task now...");
-                            if exit_sender.send(pkt.header.plane_id).await.is_err() {
-                                tracing::error!("Error sending exit flag to manager...");
-                            }
-                            return;
-                        }
-                    };
-                    tracing::info!("Client {}: {}", pkt.header.plane_id, new_coord);
-
-                    // Acquire lock, push new coordinate to shared HashMap.
+                    // Read coordinates from packet body and store them.
+                    if let Err(DecodeError::TooShort(_)) = Self::store_coordinate(
+                        &coordinates,
+                        plane_id,
+                        &pkt.body,
+                        Instant::now(),
+                        &config,
+                        &recording,
+                    )
+                    .await
                     {
-                        let mut coord_data = coordinates.lock().await;
-                        coord_data
-                            .entry(pkt.header.plane_id)
-                            .or_default()
-                            .push(new_coord);
+                        tracing::error!("Exiting task now...");
+                        session.close();
+                        continue;
                     }
                 }
                 FlagState::EXIT => {
-                    //TODO: Handle massive load from client :weary:
-                    // tracing::info!("Packet is EXIT");
-                    let mut file =
-                        File::create(format!("plane_{}.txt", pkt.header.plane_id)).unwrap();
-
-                    match file.write_all(&pkt.body) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            tracing::error!("Failed to write final data to file... {}", e)
-                        }
-                    }
-
-                    loop {
-                        let pkt: Packet = match deserialize_packet(&mut stream).await {
-                            Ok(p) => {
-                                tracing::info!("Deserialized packet: {p}");
-                                p
-                            }
+                    let chunks = match &mut upload {
+                        Some(chunks) => chunks,
+                        None => match Upload::create(Path::new("."), plane_id) {
+                            Ok(created) => upload.insert(created),
                             Err(e) => {
-                                tracing::error!("Error deserializing exit packet: {e}");
-                                return;
+                                tracing::error!("Unable to store upload of {}: {}", plane_id, e);
+                                session.close();
+                                continue;
                             }
-                        };
+                        },
+                    };
+                    // A chunk that cannot be written ruins the upload, dropping it removes the
+                    // part file.
+                    if let Err(e) = chunks.write(&pkt.body) {
+                        tracing::error!("Failed to write final data to file... {}", e);
+                        upload = None;
+                        session.close();
+                        continue;
+                    }
 
-                        match file.write_all(&pkt.body) {
-                            Ok(_) => {}
+                    // The last chunk closes the session.
+                    if session.state() == SessionState::CLOSING
+                        && let Some(complete) = upload.take()
+                    {
+                        match complete.finish() {
+                            Ok(path) => {
+                                tracing::info!(
+                                    "Stored upload of {} in {}",
+                                    plane_id,
+                                    path.display()
+                                )
+                            }
                             Err(e) => {
-                                tracing::error!("Failed to write final data to file... {}", e)
+                                tracing::error!("Unable to store upload of {}: {}", plane_id, e)
                             }
                         }
-
-                        if pkt.header.seq_len == 0 {
-                            break;
-                        }
                     }
-
-                    // Remove plane from active planes.
-                    {
-                        let mut data: tokio::sync::MutexGuard<'_, HashMap<u8, Vec<Vector3>>> =
-                            coordinates.lock().await;
-                        if data.remove(&pkt.header.plane_id).is_none() {
-                            tracing::error!(
-                                "Unable to remove Plane #{} from active planes: entry not found",
-                                pkt.header.plane_id
-                            );
-                        }
+                }
+                FlagState::FLIGHT_PLAN => match FlightPlan::from_bytes(&pkt.body) {
+                    Some(plan) => {
+                        tracing::info!("Client {} filed flight plan {}", plane_id, plan);
+                        plans.lock().await.insert(plane_id, plan);
                     }
+                    None => tracing::error!("Rejected flight plan from client {}", plane_id),
+                },
+                // The session lets no other flag through.
+                _ => {}
+            }
+        }
 
-                    // Send exit message to main thread.
-                    if exit_sender.send(pkt.header.plane_id).await.is_err() {
-                        tracing::error!("Error sending exit flag to manager...");
-                    }
-                }
-                _ => {
-                    tracing::error!(
-                        "Something went terribly wrong, the server recieved a COLLISION packet..."
-                    );
-                }
+        // Send exit message to main thread, unless another session flies the aircraft now.
+        let released = sessions
+            .lock()
+            .await
+            .release(plane_id, registration.generation);
+        if released && exit_sender.send(plane_id).await.is_err() {
+            tracing::error!("Error sending exit flag to manager...");
+        }
+        session.closed();
+    }
+
+    /// Decode a coordinate from a packet body and push it to the shared HashMap.
+    /// The sample is stamped with the receive time, and the client time when the body carries one.
+    /// Non-finite and out of bounds values are dropped, implausible motion is flagged.
+    /// Accepted reports are recorded when recording is enabled.
+    pub(crate) async fn store_coordinate(
+        coordinates: &Coordinates,
+        plane_id: u8,
+        body: &[u8],
+        received: Instant,
+        config: &Config,
+        recording: &Recording,
+    ) -> Result<(), DecodeError> {
+        let report = match Report::from_bytes_checked(body, &config.airspace.bounds()) {
+            Ok(r) => r,
+            Err(e) => {
+                tracing::error!("Rejected coordinate from client {}: {}", plane_id, e);
+                return Err(e);
             }
+        };
+        tracing::info!("Client {}: {}", plane_id, report);
+        let sample = Sample::new(report.position, received, report.sent);
 
-            // Check for collision warnings.
-            // Send collision packet to affected clients.
-            match col_receiver.try_recv() {
-                Ok(col_alert) => {
-                    if col_alert.0 == pkt.header.plane_id {
-                        let header = PacketHeader {
-                            flag: FlagState::COLLISION,
-                            plane_id,
-                            body_size: std::mem::size_of::<Vector3>() as u16,
-                            seq_len: 0,
-                        };
-                        let new_altitude = Vector3::new(0.0, 0.0, col_alert.1);
-                        let body = new_altitude.to_bytes();
-                        let pkt = Packet { header, body };
-
-                        if let Err(e) = serialize_packet(pkt, &mut stream).await {
-                            tracing::error!("Error sending packet: {e}");
-                            return;
-                        }
-                    }
-                }
-                Err(tokio::sync::broadcast::error::TryRecvError::Empty) => {}
-                Err(_) => {
-                    tracing::error!("Error reading warning from mananger...");
-                    return;
-                }
-            };
+        // Acquire lock, push new coordinate to shared HashMap.
+        let mut coord_data = coordinates.lock().await;
+        let track = coord_data
+            .entry(plane_id)
+            .or_insert_with(|| Track::new(&config.history));
+        if let Some(prev) = track.last()
+            && let Some(elapsed) = track.elapsed(&sample, &config.tracking)
+            && !elapsed.is_zero()
+            && let Err(e) = config.plausibility.check(
+                prev.position,
+                sample.position,
+                ticks(elapsed, config.timing.tick()),
+            )
+        {
+            tracing::warn!("Implausible report from client {}: {}", plane_id, e);
+        }
+        if !track.push(sample, config.timing.tick(), &config.tracking) {
+            tracing::debug!("Dropped duplicate report from client {}", plane_id);
+        } else if let Some(recorder) = recording {
+            recorder
+                .lock()
+                .await
+                .record(received, plane_id, Event::Report(report));
+        }
+        Ok(())
+    }
 
-            // Check for timeout warnings.
-            match warn_receiver.try_recv() {
-                Ok(p) if p != plane_id => {
-                    // Create WARNING packet.
-                    let pkt = Packet {
-                        header: PacketHeader {
-                            flag: FlagState::WARNING,
-                            plane_id: p,
-                            body_size: 0 as u16,
-                            seq_len: 0,
-                        },
-                        body: Vec::new(),
+    /// Follow the flight plan of every tracked plane from its filtered position, and log the
+    /// conflicts predicted along them within the probe horizon. Then sequence the arrivals at
+    /// each destination with wake spacing, and log the planes that have to lose time.
+    async fn probe_plans(
+        coordinates: &Coordinates,
+        plans: &Plans,
+        categories: &Categories,
+        config: &Config,
+    ) -> (Vec<ProbeConflict>, Vec<Arrival>) {
+        let positions: Vec<(PlaneId, Vector3)> = {
+            let data = coordinates.lock().await;
+            data.iter()
+                .filter(|(_, track)| !track.stale)
+                .filter_map(|(id, track)| track.filter.map(|f| (*id, f.position())))
+                .collect()
+        };
+        let (trajectories, inbound): (Vec<Trajectory>, Vec<Inbound>) = {
+            let plans = plans.lock().await;
+            let categories = categories.lock().await;
+            positions
+                .iter()
+                .filter_map(|(id, position)| {
+                    let plan = plans.get(id)?;
+                    let trajectory = Trajectory::new(*id, plan, *position);
+                    let inbound = Inbound {
+                        id: *id,
+                        category: categories.get(id).copied().unwrap_or(WakeCategory::MEDIUM),
+                        destination: plan.destination,
+                        eta: trajectory.end(),
+                        speed: plan.speed,
                     };
+                    Some((trajectory, inbound))
+                })
+                .unzip()
+        };
 
-                    // Send WARNING packet.
-                    if let Err(e) = serialize_packet(pkt, &mut stream).await {
-                        tracing::error!("Error sending packet: {e}");
-                        return;
-                    }
-                }
-                Ok(p) if p == plane_id => {
-                    // Exit if this client timed out.
-                    if exit_sender.send(pkt.header.plane_id).await.is_err() {
-                        tracing::error!("Error sending exit flag to manager...");
-                        return;
-                    }
-                }
-                Ok(_) => {}
-                Err(tokio::sync::broadcast::error::TryRecvError::Empty) => {}
-                Err(e) => {
-                    tracing::error!("Unable to broadcast timeout warning {}", e);
-                }
+        let conflicts = probe(&trajectories, &config.probe);
+        for conflict in &conflicts {
+            tracing::warn!(
+                "Planned routes of {} and {} lose separation in {:.0} ticks, closest {:.2}",
+                conflict.a,
+                conflict.b,
+                conflict.time,
+                conflict.distance
+            );
+        }
+        tracing::info!(
+            "Probed {} flight plans, {} conflicts",
+            trajectories.len(),
+            conflicts.len()
+        );
+
+        let arrivals = sequence(&inbound, &config.wake, config.detection.tolerance);
+        for arrival in &arrivals {
+            match arrival.leader {
+                Some(leader) if arrival.delay() > 0.0 => tracing::warn!(
+                    "Arrival of {} ({}) at {} must lose {:.0} ticks behind {}",
+                    arrival.id,
+                    arrival.category,
+                    arrival.destination,
+                    arrival.delay(),
+                    leader
+                ),
+                _ => tracing::info!(
+                    "Arrival of {} ({}) at {} in {:.0} ticks",
+                    arrival.id,
+                    arrival.category,
+                    arrival.destination,
+                    arrival.slot
+                ),
             }
+        }
+        (conflicts, arrivals)
+    }
 
-            // Handle EXIT flag.
-            if pkt.header.flag == FlagState::EXIT {
-                // Remove plane from active planes.
-                {
-                    let mut data = coordinates.lock().await;
-                    if data.remove(&pkt.header.plane_id).is_none() {
-                        tracing::error!(
-                            "Unable to remove Plane #{} from active planes: entry not found",
-                            pkt.header.plane_id
-                        );
-                    }
-                }
+    /// Resolution advisories for every member of a cluster. A cluster that cannot be resolved
+    /// still gets traffic advisories for its conflicts, so that no member is left unwarned.
+    fn resolution_alerts(
+        cluster: &Cluster,
+        states: &[TrackState],
+        config: &Config,
+        encounters: &mut Encounters,
+    ) -> Vec<(PlaneId, Alert)> {
+        let Some(advisories) =
+            resolve_cluster(cluster, states, &config.detection, &config.resolution)
+        else {
+            tracing::error!(
+                "Unable to resolve conflicts between {:?}, sending traffic advisories",
+                cluster.members
+            );
+            return cluster
+                .conflicts
+                .iter()
+                .flat_map(traffic_advisories)
+                .collect();
+        };
+        for conflict in &cluster.conflicts {
+            let encounter = Encounter::conflict(conflict);
+            if encounters.advise(encounter.clone()) {
+                tracing::info!("Advised {}", encounter);
+            }
+        }
+        advisories
+            .iter()
+            .map(|(plane_id, advisory)| (*plane_id, Alert::Resolution(*advisory)))
+            .collect()
+    }
+
+    /// Try to clear a departure now: the server must release departures and the sector of the
+    /// departure is expected to have room, which is then reserved for it.
+    /// Clients that do not give a departure position only wait for the server.
+    async fn try_clear(
+        plane_id: PlaneId,
+        departure: Option<Vector3>,
+        sectors: &SectorStatus,
+        config: &Config,
+        state: State,
+    ) -> bool {
+        state.releases()
+            && match departure {
+                Some(position) => sectors
+                    .lock()
+                    .await
+                    .reserve(plane_id, position, &config.sectors),
+                None => true,
+            }
+    }
+
+    /// Wait until a held departure can be cleared, trying once per tick.
+    /// Sends nothing, so it may be cancelled at any point.
+    async fn departure_cleared(
+        plane_id: PlaneId,
+        departure: Option<Vector3>,
+        sectors: &SectorStatus,
+        config: &Config,
+        state: watch::Receiver<State>,
+    ) {
+        let mut interval = tokio::time::interval(config.timing.tick());
+        loop {
+            interval.tick().await;
+            let current = *state.borrow();
+            if Self::try_clear(plane_id, departure, sectors, config, current).await {
+                return;
+            }
+        }
+    }
+
+    /// Tell a client its departure is released, and let its session go airborne.
+    async fn release(
+        stream: &mut OwnedWriteHalf,
+        session: &mut Session,
+        sessions: &Sessions,
+        registration: &Registration,
+        plane_id: PlaneId,
+    ) {
+        let pkt = Self::flag_packet(FlagState::RELEASE, plane_id);
+        if let Err(e) = serialize_packet(pkt, stream).await {
+            tracing::error!("Error sending packet: {e}");
+            session.close();
+        } else if let Err(e) = session.release() {
+            tracing::error!("Protocol error from client {}: {}", plane_id, e);
+            session.close();
+        } else {
+            sessions
+                .lock()
+                .await
+                .take_off(plane_id, registration.generation);
+        }
+    }
+
+    /// Wait until the server is shutting down, or gone.
+    async fn stopping(state: &mut watch::Receiver<State>) {
+        let _ = state.wait_for(|state| state.stopping()).await;
+    }
+
+    /// Wait until the registry signals the session, to take it over or disconnect it.
+    async fn signalled(signal: &mut oneshot::Receiver<()>) {
+        if signal.await.is_err() {
+            // The session is no longer registered, nothing can signal it.
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Tell a client the server is shutting down.
+    async fn notify_shutdown(stream: &mut OwnedWriteHalf, plane_id: PlaneId) {
+        tracing::info!("Telling client {} the server is shutting down", plane_id);
+        let pkt = Self::flag_packet(FlagState::SERVER_SHUTDOWN, plane_id);
+        if let Err(e) = serialize_packet(pkt, stream).await {
+            tracing::error!("Error sending packet: {e}");
+        }
+    }
+
+    /// Tell a client the operator disconnected it.
+    async fn notify_disconnect(stream: &mut OwnedWriteHalf, plane_id: PlaneId) {
+        tracing::warn!("Disconnecting client {} on operator request", plane_id);
+        let pkt = Self::flag_packet(FlagState::DISCONNECTED, plane_id);
+        if let Err(e) = serialize_packet(pkt, stream).await {
+            tracing::error!("Error sending packet: {e}");
+        }
+    }
+
+    /// Operator console looking at and acting on the state of this manager.
+    pub fn console(&self) -> Console {
+        Console {
+            coordinates: self.coordinates.clone(),
+            categories: self.categories.clone(),
+            sessions: self.sessions.clone(),
+            encounters: self.encounters.clone(),
+            filter: self.filter.clone(),
+            state_machine: self.state_machine.clone(),
+            config: self.config.clone(),
+        }
+    }
+
+    /// Packet carrying only a flag.
+    fn flag_packet(flag: FlagState, plane_id: PlaneId) -> Packet {
+        Packet {
+            header: PacketHeader {
+                flag,
+                plane_id,
+                body_size: 0,
+                seq_len: 0,
+            },
+            body: Vec::new(),
+        }
+    }
+
+    /// Log the memory used by each track and the total, the occupancy of each sector, then the
+    /// delivery queue of each client.
+    async fn report_status(
+        coordinates: &Coordinates,
+        sectors: &SectorStatus,
+        sessions: &Sessions,
+        config: &Config,
+    ) {
+        let data = coordinates.lock().await;
+        let mut total = 0;
+        for (plane_id, track) in data.iter() {
+            let bytes = track.memory_bytes();
+            total += bytes;
+            tracing::info!(
+                "Track {}: {} bytes, {} live and {} archived reports",
+                plane_id,
+                bytes,
+                track.samples.live().len(),
+                track.samples.archive().len()
+            );
+        }
+        tracing::info!("{} tracks using {} bytes", data.len(), total);
 
-                // Send exit message to main thread.
-                if exit_sender.send(pkt.header.plane_id).await.is_err() {
-                    tracing::error!("Error sending exit flag to manager...");
+        for line in sectors.lock().await.describe(&config.sectors) {
+            tracing::info!("{}", line);
+        }
+
+        for queue in sessions.lock().await.queues() {
+            tracing::info!(
+                "Client {}: {} packets queued, {} dropped",
+                queue.plane_id,
+                queue.queued,
+                queue.dropped
+            );
+        }
+    }
+
+    /// Process data.
+    /// Send an avoidance advisory to every plane about to enter a zone.
+    /// Detect conflicts between active planes and alert every plane of each conflict cluster,
+    /// with traffic advisories first and one joint resolution once any conflict is close.
+    /// Planes avoiding a zone keep that advisory over any conflict alert.
+    /// Warn every plane closer behind a leader than their wake categories allow.
+    /// Follow every encounter until it is over, and tell each advised plane once it is clear of
+    /// all of them. Alerts go through the filter, which holds tiers against oscillation and drops
+    /// repeated advisories. Tracks are extrapolated to `now`, the current time unless replaying.
+    /// Returns the alerts to send, in order, each with the plane it is for.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn process_data(
+        coordinates: &Coordinates,
+        config: &Config,
+        zones: &[Zone],
+        sectors: &SectorStatus,
+        categories: &Categories,
+        encounters: &mut Encounters,
+        filter: &mut AlertFilter,
+        now: Instant,
+    ) -> Vec<(PlaneId, Alert)> {
+        // Only hold the lock long enough to mark stale tracks and snapshot the others.
+        let states = {
+            let mut data = coordinates.lock().await;
+            for (plane_id, track) in data.iter_mut() {
+                if track.mark_stale(now, &config.tracking) {
+                    tracing::warn!(
+                        "Track {} is stale, last report {:?} ago",
+                        plane_id,
+                        track.age(now).unwrap_or_default()
+                    );
                 }
             }
+            track_states(&data, now, config.timing.tick(), &config.tracking)
+        };
+        Self::process_states(
+            &states, config, zones, sectors, categories, encounters, filter,
+        )
+        .await
+    }
+
+    /// One tick of `process_data` on a snapshot of the tracks.
+    pub async fn process_states(
+        states: &[TrackState],
+        config: &Config,
+        zones: &[Zone],
+        sectors: &SectorStatus,
+        categories: &Categories,
+        encounters: &mut Encounters,
+        filter: &mut AlertFilter,
+    ) -> Vec<(PlaneId, Alert)> {
+        let mut sent = Vec::new();
+
+        // Refresh the sector occupancy, which also drops the reservations of the departures
+        // that are now tracked.
+        for overload in predict_overloads(states, &config.sectors) {
+            let name = config
+                .sectors
+                .sector
+                .get(overload.sector)
+                .map_or("?", |s| s.name.as_str());
+            tracing::warn!(
+                "Sector {} predicted over capacity: {} aircraft in {} cycles",
+                name,
+                overload.count,
+                overload.time
+            );
+        }
+        sectors.lock().await.refresh(states, &config.sectors);
 
-            // Read coordinates from packet body.
-            let new_coord: Vector3 = match Vector3::from_bytes(pkt.body.as_slice()) {
-                Some(c) => c,
-                None => {
-                    tracing::error!("Unable to create Vector3 from bytes...");
-                    tracing::error!("Exiting task now...");
-                    if exit_sender.send(pkt.header.plane_id).await.is_err() {
-                        tracing::error!("Error sending exit flag to manager...");
+        // Closures follow the wall clock, also when replaying.
+        let clock = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let mut avoiding = Vec::new();
+        for incursion in predict_incursions(states, zones, clock, &config.detection) {
+            tracing::warn!(
+                "Plane {} enters zone {} in {} cycles",
+                incursion.plane_id,
+                incursion.zone,
+                incursion.time
+            );
+            let encounter = Encounter::Zone(incursion.plane_id, incursion.zone.clone());
+            encounters.detect(encounter.clone());
+            match avoid(
+                &incursion,
+                states,
+                zones,
+                clock,
+                &config.detection,
+                &config.resolution,
+            ) {
+                Some(advisory) => {
+                    avoiding.push(incursion.plane_id);
+                    if encounters.advise(encounter.clone()) {
+                        tracing::info!("Advised {}", encounter);
                     }
-                    return;
+                    let alert = Alert::Resolution(advisory);
+                    if !filter.fresh(incursion.plane_id, &alert) {
+                        continue;
+                    }
+                    tracing::info!("Alert for {}: {:?}", incursion.plane_id, advisory);
+                    sent.push((incursion.plane_id, alert));
                 }
+                None => tracing::error!(
+                    "Unable to keep plane {} out of zone {}",
+                    incursion.plane_id,
+                    incursion.zone
+                ),
+            }
+        }
+
+        // Detect out to the exit buffer so that alerted pairs are held until clearly apart,
+        // while new conflicts must still come within the detector tolerance.
+        let wide = DetectorConfig {
+            tolerance: config.detection.tolerance + config.alerts.exit_buffer,
+            ..config.detection
+        };
+        let conflicts = detect_conflicts(states, &wide);
+        let state = |id: PlaneId| states.iter().find(|s| s.id == id);
+        let entering = |c: &Conflict| match (state(c.a), state(c.b)) {
+            (Some(a), Some(b)) => conflicting(a, b, &config.detection),
+            _ => false,
+        };
+        for conflict in conflicts.iter().filter(|c| entering(c)) {
+            tracing::warn!(
+                "Conflict between {} and {}: {} apart in {} cycles",
+                conflict.a,
+                conflict.b,
+                conflict.cpa,
+                conflict.time
+            );
+            let encounter = Encounter::conflict(conflict);
+            if encounters.detect(encounter.clone()) {
+                tracing::info!("Detected {}", encounter);
+            }
+        }
+
+        // Pairs held by the filter after a plane left are dropped, there is no one to alert.
+        let mut tiers = filter.update(&conflicts, entering, &config.alerts);
+        tiers.retain(|(c, _)| state(c.a).is_some() && state(c.b).is_some());
+        let alerting: Vec<Conflict> = tiers.iter().map(|(conflict, _)| *conflict).collect();
+
+        // Group alerting conflicts into clusters of transitively conflicting aircraft,
+        // so that each aircraft gets one advisory consistent with its whole cluster.
+        for cluster in clusters(&alerting) {
+            let resolution = tiers
+                .iter()
+                .any(|(c, tier)| *tier == AlertTier::Resolution && cluster.members.contains(&c.a));
+            let alerts: Vec<(PlaneId, Alert)> = if resolution {
+                Self::resolution_alerts(&cluster, states, config, encounters)
+            } else {
+                cluster
+                    .conflicts
+                    .iter()
+                    .flat_map(traffic_advisories)
+                    .collect()
             };
+            for (plane_id, alert) in alerts {
+                if avoiding.contains(&plane_id) {
+                    tracing::info!("Plane {} is avoiding a zone, holding {:?}", plane_id, alert);
+                    continue;
+                }
+                if !filter.fresh(plane_id, &alert) {
+                    tracing::debug!("Plane {} already has {:?}", plane_id, alert);
+                    continue;
+                }
+                tracing::info!("Alert for {}: {:?}", plane_id, alert);
+                sent.push((plane_id, alert));
+            }
+        }
 
-            // Acquire lock, push new coordinate to shared HashMap.
-            {
-                let mut coord_data = coordinates.lock().await;
-                coord_data
-                    .entry(pkt.header.plane_id)
-                    .or_default()
-                    .push(new_coord);
+        // Wake turbulence is reported apart from collision risk, to the follower only.
+        let categories = categories.lock().await.clone();
+        let wakes = detect_wake(
+            states,
+            &categories,
+            &config.wake,
+            config.detection.max_cycles,
+        );
+        filter.expire_wakes(&detect_wake(
+            states,
+            &categories,
+            &config.wake.widened(config.alerts.exit_buffer),
+            config.detection.max_cycles,
+        ));
+        for wake in &wakes {
+            let alert = Alert::Wake(WakeAdvisory::new(wake.leader, wake.required, wake.distance));
+            if !filter.fresh(wake.follower, &alert) {
+                continue;
             }
+            tracing::warn!(
+                "Plane {} in wake of {} in {} cycles: {:.2} behind, {:.2} required",
+                wake.follower,
+                wake.leader,
+                wake.time,
+                wake.distance,
+                wake.required
+            );
+            sent.push((wake.follower, alert));
         }
-    }
 
-    /// Process data.
-    async fn process_data(coordinates: &Coordinates, col_sender: &broadcast::Sender<(u8, f32)>) {
-        let data = coordinates.lock().await;
-        if data.len() <= 0 {
-            return;
+        // Close the encounters that are over, so no advisory is left standing once it is not
+        // needed.
+        let resolved = encounters.resolve(states, &conflicts, zones, &config.detection);
+        for (encounter, phase) in &resolved {
+            tracing::info!("Resolved {} after it was {:?}", encounter, phase);
+        }
+        for plane_id in encounters.cleared(&resolved) {
+            tracing::info!("Plane {} is clear of conflict", plane_id);
+            filter.fresh(plane_id, &Alert::Clear);
+            sent.push((plane_id, Alert::Clear));
+        }
+        // Clearances that could not be queued are sent again until they are.
+        for plane_id in filter.unsent() {
+            tracing::info!("Plane {} is still to be cleared", plane_id);
+            filter.fresh(plane_id, &Alert::Clear);
+            sent.push((plane_id, Alert::Clear));
         }
+        sent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use utils::packet::deserialize_packet;
 
-        let recent_coords: Vec<(Vector3, Vector3)> = data
-            .values()
-            .filter_map(|vec| {
-                if vec.len() >= 2 {
-                    let last_coord = vec[vec.len() - 2].clone();
-                    let current_coord = vec.last().unwrap().clone();
-                    Some((last_coord, current_coord))
-                } else {
-                    None
+    /// Tracks with one report per tick, the last one received `age` ago.
+    async fn coordinates_with(
+        tracks: &[(PlaneId, [(f32, f32); 2])],
+        age: Duration,
+        config: &Config,
+    ) -> Coordinates {
+        let coordinates: Coordinates = Arc::new(Mutex::new(HashMap::new()));
+        let last = Instant::now() - age;
+        let tick = config.timing.tick();
+        {
+            let mut data = coordinates.lock().await;
+            for (plane_id, samples) in tracks {
+                let track: &mut Track = data.entry(*plane_id).or_default();
+                for (i, (x, y)) in samples.iter().enumerate() {
+                    let received = last - tick * (samples.len() - 1 - i) as u32;
+                    let sample = Sample::new(Vector3::new(*x, *y, 0.0), received, None);
+                    track.push(sample, tick, &config.tracking);
                 }
+            }
+        }
+        coordinates
+    }
+
+    async fn alerts(coordinates: &Coordinates, config: &Config) -> Vec<(PlaneId, Alert)> {
+        alerts_with_zones(coordinates, config, &[]).await
+    }
+
+    async fn alerts_with_zones(
+        coordinates: &Coordinates,
+        config: &Config,
+        zones: &[Zone],
+    ) -> Vec<(PlaneId, Alert)> {
+        alerts_following(coordinates, config, zones, &mut Encounters::default()).await
+    }
+
+    /// Alerts of one tick, for encounters followed since earlier ticks.
+    async fn alerts_following(
+        coordinates: &Coordinates,
+        config: &Config,
+        zones: &[Zone],
+        encounters: &mut Encounters,
+    ) -> Vec<(PlaneId, Alert)> {
+        let sectors: SectorStatus = Arc::new(Mutex::new(Occupancy::default()));
+        let mut alerts = Manager::process_data(
+            coordinates,
+            config,
+            zones,
+            &sectors,
+            &Arc::new(Mutex::new(HashMap::new())),
+            encounters,
+            &mut AlertFilter::default(),
+            Instant::now(),
+        )
+        .await;
+        alerts.sort_by_key(|(plane_id, _)| *plane_id);
+        alerts
+    }
+
+    async fn alerted(coordinates: &Coordinates, config: &Config) -> Vec<PlaneId> {
+        let alerts = alerts(coordinates, config).await;
+        alerts.into_iter().map(|(plane_id, _)| plane_id).collect()
+    }
+
+    #[tokio::test]
+    async fn test_process_data_alerts_conflicting_planes() {
+        // Ids do not match the iteration order, and plane 3 is far from both others.
+        let config = Config::default();
+        let tracks = [
+            (42, [(10.0, 10.0), (11.0, 10.0)]),
+            (3, [(0.0, 30.0), (1.0, 30.0)]),
+            (7, [(16.0, 10.0), (15.0, 10.0)]),
+        ];
+        let coordinates = coordinates_with(&tracks, Duration::ZERO, &config).await;
+
+        assert_eq!(alerted(&coordinates, &config).await, vec![7, 42]);
+    }
+
+    #[tokio::test]
+    async fn test_process_data_resolves_clusters_jointly() {
+        // Three planes converging on the same point three ticks from now.
+        let config = Config::default();
+        let tracks: Vec<(PlaneId, [(f32, f32); 2])> = [5, 6, 7]
+            .iter()
+            .enumerate()
+            .map(|(i, plane_id)| {
+                let angle = std::f32::consts::TAU * i as f32 / 3.0;
+                let (sin, cos) = angle.sin_cos();
+                (
+                    *plane_id,
+                    [
+                        (20.0 + 4.0 * cos, 20.0 + 4.0 * sin),
+                        (20.0 + 3.0 * cos, 20.0 + 3.0 * sin),
+                    ],
+                )
+            })
+            .collect();
+        let coordinates = coordinates_with(&tracks, Duration::ZERO, &config).await;
+
+        let alerts = alerts(&coordinates, &config).await;
+
+        // Exactly one resolution advisory per plane, each to its own level.
+        assert_eq!(
+            alerts.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![5, 6, 7]
+        );
+        let mut targets: Vec<f32> = alerts
+            .iter()
+            .filter_map(|(_, alert)| match alert {
+                Alert::Resolution(advisory) => Some(advisory.target_altitude),
+                _ => None,
             })
             .collect();
+        targets.sort_by(f32::total_cmp);
+        assert_eq!(targets, vec![0.0, 1000.0, 2000.0]);
+    }
 
-        // Check potential collisions with each plane
-        for (i, plane_a) in recent_coords.iter().enumerate() {
-            for (j, plane_b) in recent_coords.iter().enumerate() {
-                if i == j {
+    #[test]
+    fn test_unresolved_clusters_fall_back_to_traffic_advisories() {
+        // Plane 2 has no track, so the cluster cannot be resolved.
+        let conflict = Conflict {
+            a: 1,
+            b: 2,
+            cpa: 0.5,
+            time: 3,
+        };
+        let cluster = Cluster {
+            members: vec![1, 2],
+            conflicts: vec![conflict],
+        };
+        let states = [TrackState {
+            id: 1,
+            position: Vector3::new(10.0, 10.0, 10000.0),
+            velocity: Vector3::new(1.0, 0.0, 0.0),
+            uncertainty: 0.0,
+        }];
+        let mut encounters = Encounters::default();
+
+        let alerts =
+            Manager::resolution_alerts(&cluster, &states, &Config::default(), &mut encounters);
+
+        assert_eq!(alerts, traffic_advisories(&conflict).to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_process_data_clears_planes_past_their_conflict() {
+        let config = Config::default();
+        let mut encounters = Encounters::default();
+        let converging = [
+            (1, [(8.0, 10.0), (9.0, 10.0)]),
+            (2, [(12.0, 10.0), (11.0, 10.0)]),
+        ];
+        let passed = [
+            (1, [(16.0, 10.0), (17.0, 10.0)]),
+            (2, [(4.0, 10.0), (3.0, 10.0)]),
+        ];
+
+        let coordinates = coordinates_with(&converging, Duration::ZERO, &config).await;
+        let advised = alerts_following(&coordinates, &config, &[], &mut encounters).await;
+        assert!(
+            advised
+                .iter()
+                .all(|(_, alert)| matches!(alert, Alert::Resolution(_)))
+        );
+        assert!(encounters.is_advised(1) && encounters.is_advised(2));
+
+        let coordinates = coordinates_with(&passed, Duration::ZERO, &config).await;
+        let cleared = alerts_following(&coordinates, &config, &[], &mut encounters).await;
+        assert_eq!(cleared, vec![(1, Alert::Clear), (2, Alert::Clear)]);
+        assert!(
+            alerts_following(&coordinates, &config, &[], &mut encounters)
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_data_resends_clearances_until_queued() {
+        // Pairs are let go as soon as they are apart, so that only the clearance is sent.
+        let mut config = Config::default();
+        config.alerts.min_dwell = 0;
+        let sectors: SectorStatus = Arc::new(Mutex::new(Occupancy::default()));
+        let categories: Categories = Arc::new(Mutex::new(HashMap::new()));
+        let mut encounters = Encounters::default();
+        let mut filter = AlertFilter::default();
+        let mut registry = SessionRegistry::new(1);
+        let mut session = registry
+            .register(1, None, "127.0.0.1:1000".parse().unwrap())
+            .unwrap();
+        let mut tick = async |tracks: &[(PlaneId, [(f32, f32); 2])]| {
+            let coordinates = coordinates_with(tracks, Duration::ZERO, &config).await;
+            let alerts = Manager::process_data(
+                &coordinates,
+                &config,
+                &[],
+                &sectors,
+                &categories,
+                &mut encounters,
+                &mut filter,
+                Instant::now(),
+            )
+            .await;
+            let mut delivered = Vec::new();
+            for (plane_id, alert) in alerts {
+                if plane_id != 1 {
                     continue;
                 }
-
-                let (prev_a, curr_a) = plane_a;
-                let (prev_b, curr_b) = plane_b;
-
-                let speed_a = Vector3::distance(*prev_a, *curr_a);
-                let speed_b = Vector3::distance(*prev_b, *curr_b);
-
-                let velocity_a = prev_a.displacement_vector(*curr_a, speed_a);
-                let velocity_b = prev_b.displacement_vector(*curr_b, speed_b);
-
-                // Send collision warnings if there will be a future collision.
-                let max_cycles = 3;
-                let tolerance = 2.0;
-                if Vector3::will_intersect_in_n_cycles(
-                    *curr_a, velocity_a, *curr_b, velocity_b, max_cycles, tolerance,
-                ) {
-                    let plane_a_alert = (i as u8, 32000.0);
-                    let plane_b_alert = (j as u8, 30000.0);
-                    if col_sender.send(plane_a_alert).is_err()
-                        || col_sender.send(plane_b_alert).is_err()
-                    {
-                        tracing::error!("Error sending collision alert to threads...");
-                    }
+                delivered.push((alert, registry.deliver(plane_id, alert.to_packet(plane_id))));
+                if !delivered.last().unwrap().1 {
+                    filter.undelivered(plane_id, &alert);
                 }
             }
+            delivered
+        };
+        let converging = [
+            (1, [(8.0, 10.0), (9.0, 10.0)]),
+            (2, [(12.0, 10.0), (11.0, 10.0)]),
+        ];
+        let passed = [
+            (1, [(16.0, 10.0), (17.0, 10.0)]),
+            (2, [(4.0, 10.0), (3.0, 10.0)]),
+        ];
+
+        // The advisory fills the urgent queue, so the clearance cannot be queued behind it.
+        let advised = tick(&converging).await;
+        assert!(matches!(advised[..], [(Alert::Resolution(_), true)]));
+        assert_eq!(tick(&passed).await, vec![(Alert::Clear, false)]);
+        assert_eq!(tick(&passed).await, vec![(Alert::Clear, false)]);
+
+        // Once the client catches up, the clearance goes out, and only once.
+        session.urgent.try_recv().unwrap();
+        assert_eq!(tick(&passed).await, vec![(Alert::Clear, true)]);
+        assert!(tick(&passed).await.is_empty());
+        assert_eq!(
+            session.urgent.try_recv().map(|p| p.header.flag),
+            Ok(FlagState::CLEAR_OF_CONFLICT)
+        );
+    }
+
+    /// Alerts of each tick for two planes side by side, the gap between them swinging across
+    /// the tolerance every tick while staying within the exit buffer.
+    async fn oscillating_alerts(config: &Config, ticks: usize) -> Vec<Vec<(PlaneId, Alert)>> {
+        let mut encounters = Encounters::default();
+        let mut filter = AlertFilter::default();
+        let mut alerts = Vec::new();
+        for tick in 0..ticks {
+            let gap = if tick % 2 == 0 { 2.0 } else { 3.7 };
+            let x = tick as f32;
+            let tracks = [
+                (1, [(x, 0.0), (x + 1.0, 0.0)]),
+                (2, [(x, gap), (x + 1.0, gap)]),
+            ];
+            let coordinates = coordinates_with(&tracks, Duration::ZERO, config).await;
+            let sectors: SectorStatus = Arc::new(Mutex::new(Occupancy::default()));
+            let sent = Manager::process_data(
+                &coordinates,
+                config,
+                &[],
+                &sectors,
+                &Arc::new(Mutex::new(HashMap::new())),
+                &mut encounters,
+                &mut filter,
+                Instant::now(),
+            )
+            .await;
+            alerts.push(sent);
+        }
+        alerts
+    }
+
+    #[tokio::test]
+    async fn test_process_data_debounces_oscillating_pairs() {
+        let config = Config::default();
+        let mut raw = Config::default();
+        raw.alerts.exit_margin = 0;
+        raw.alerts.exit_buffer = 0.0;
+        raw.alerts.min_dwell = 0;
+
+        // Without hysteresis the pair is advised and cleared over and over.
+        let raw_alerts = oscillating_alerts(&raw, 8).await;
+        assert!(raw_alerts.iter().all(|sent| sent.len() == 2));
+        assert!(
+            raw_alerts[1]
+                .iter()
+                .all(|(_, alert)| *alert == Alert::Clear)
+        );
+
+        // With it, one advisory each and nothing more while the pair hovers.
+        let alerts = oscillating_alerts(&config, 8).await;
+        assert_eq!(alerts[0].len(), 2);
+        assert!(
+            alerts[0]
+                .iter()
+                .all(|(_, alert)| matches!(alert, Alert::Resolution(_)))
+        );
+        assert!(alerts[1..].iter().all(|sent| sent.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_process_data_keeps_planes_out_of_zones() {
+        // Plane 42 flies towards a closure, plane 3 flies parallel to it well clear.
+        let config = Config::default();
+        let zones = crate::zone::parse(
+            "[[zone]]\nname = \"Closure\"\nkind = { temporary_closure = { start = 0, end = 4102444800 } }\nfloor = -100.0\nceiling = 2000.0\nshape = { type = \"cylinder\", center = [20.0, 10.0], radius = 2.0 }\n",
+        )
+        .expect("Expected valid zones");
+        let tracks = [
+            (42, [(10.0, 10.0), (11.0, 10.0)]),
+            (3, [(10.0, 30.0), (11.0, 30.0)]),
+        ];
+        let coordinates = coordinates_with(&tracks, Duration::ZERO, &config).await;
+
+        let alerts = alerts_with_zones(&coordinates, &config, &zones).await;
+
+        assert_eq!(alerts.len(), 1);
+        match alerts[0] {
+            (42, Alert::Resolution(advisory)) => {
+                assert_eq!(advisory.sense, utils::advisory::Sense::CLIMB);
+                assert!(advisory.target_altitude >= 2002.0);
+            }
+            other => panic!("Unexpected alert {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_data_refreshes_sector_occupancy() {
+        // Plane 42 is about to cross from the south west into the south east sector.
+        let config = Config::default();
+        let tracks = [
+            (42, [(18.0, 10.0), (19.0, 10.0)]),
+            (3, [(0.0, 30.0), (1.0, 30.0)]),
+        ];
+        let coordinates = coordinates_with(&tracks, Duration::ZERO, &config).await;
+        let sectors: SectorStatus = Arc::new(Mutex::new(Occupancy::default()));
+        assert!(
+            sectors
+                .lock()
+                .await
+                .reserve(3, Vector3::new(30.0, 30.0, 0.0), &config.sectors)
+        );
+
+        Manager::process_data(
+            &coordinates,
+            &config,
+            &[],
+            &sectors,
+            &Arc::new(Mutex::new(HashMap::new())),
+            &mut Encounters::default(),
+            &mut AlertFilter::default(),
+            Instant::now(),
+        )
+        .await;
+
+        let occupancy = sectors.lock().await.clone();
+        assert_eq!(occupancy.current, vec![1, 0, 1, 0]);
+        assert_eq!(occupancy.predicted, vec![1, 1, 1, 0]);
+        assert!(occupancy.reservations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_departures_stay_reserved_until_tracked() {
+        let mut config = Config::default();
+        config.sectors.sector[3].capacity = 1;
+        let departure = Vector3::new(30.0, 30.0, 10000.0);
+        let coordinates: Coordinates = Arc::new(Mutex::new(HashMap::new()));
+        let sectors: SectorStatus = Arc::new(Mutex::new(Occupancy::default()));
+        let tick = |coordinates: Coordinates| {
+            let sectors = sectors.clone();
+            let config = config.clone();
+            async move {
+                Manager::process_data(
+                    &coordinates,
+                    &config,
+                    &[],
+                    &sectors,
+                    &Arc::new(Mutex::new(HashMap::new())),
+                    &mut Encounters::default(),
+                    &mut AlertFilter::default(),
+                    Instant::now(),
+                )
+                .await;
+            }
+        };
+        assert!(sectors.lock().await.reserve(1, departure, &config.sectors));
+
+        // Plane 1 has not reported yet, so only its reservation holds the sector.
+        tick(coordinates.clone()).await;
+        assert!(!sectors.lock().await.reserve(2, departure, &config.sectors));
+
+        // Once tracked, plane 1 fills the sector itself.
+        let tracked = coordinates_with(
+            &[(1, [(30.0, 30.0), (30.0, 30.0)])],
+            Duration::ZERO,
+            &config,
+        )
+        .await;
+        tick(tracked).await;
+        let occupancy = sectors.lock().await.clone();
+        assert!(occupancy.reservations.is_empty());
+        assert_eq!(occupancy.current[3], 1);
+        assert!(!sectors.lock().await.reserve(2, departure, &config.sectors));
+    }
+
+    #[tokio::test]
+    async fn test_probe_plans_follows_filed_routes() {
+        // Plane 42 turns north onto the route plane 7 flies west on, and both reach
+        // (20, 20) about 38 ticks from now. Plane 3 has not filed a plan.
+        let config = Config::default();
+        let tracks = [
+            (42, [(1.0, 0.0), (2.0, 0.0)]),
+            (7, [(59.0, 20.0), (58.0, 20.0)]),
+            (3, [(20.0, -20.0), (21.0, -20.0)]),
+        ];
+        let coordinates = coordinates_with(&tracks, Duration::ZERO, &config).await;
+        let plans: Plans = Arc::new(Mutex::new(HashMap::new()));
+        {
+            let mut plans = plans.lock().await;
+            let point = |x, y| Vector3::new(x, y, 0.0);
+            plans.insert(
+                42,
+                FlightPlan::new(
+                    point(0.0, 0.0),
+                    point(20.0, 40.0),
+                    vec![point(20.0, 0.0)],
+                    1.0,
+                ),
+            );
+            plans.insert(
+                7,
+                FlightPlan::new(point(60.0, 20.0), point(0.0, 20.0), Vec::new(), 1.0),
+            );
+        }
+
+        let categories: Categories = Arc::new(Mutex::new(HashMap::new()));
+        let (conflicts, _) = Manager::probe_plans(&coordinates, &plans, &categories, &config).await;
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!((conflicts[0].a, conflicts[0].b), (7, 42));
+        assert!(conflicts[0].time > 30.0 && conflicts[0].time < 40.0);
+        assert!(conflicts[0].distance < 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_probe_plans_sequences_arrivals_with_wake_spacing() {
+        // A light plane a tick behind a heavy one on the way to the same destination.
+        let config = Config::default();
+        let tracks = [
+            (42, [(9.0, 0.0), (10.0, 0.0)]),
+            (7, [(8.0, 3.0), (9.0, 3.0)]),
+        ];
+        let coordinates = coordinates_with(&tracks, Duration::ZERO, &config).await;
+        let point = |x, y| Vector3::new(x, y, 0.0);
+        let plans: Plans = Arc::new(Mutex::new(HashMap::from([
+            (
+                42,
+                FlightPlan::new(point(0.0, 0.0), point(30.0, 0.0), Vec::new(), 1.0),
+            ),
+            (
+                7,
+                FlightPlan::new(point(0.0, 3.0), point(30.0, 0.0), Vec::new(), 1.0),
+            ),
+        ])));
+        let categories: Categories = Arc::new(Mutex::new(HashMap::from([
+            (42, WakeCategory::HEAVY),
+            (7, WakeCategory::LIGHT),
+        ])));
+
+        let (_, arrivals) = Manager::probe_plans(&coordinates, &plans, &categories, &config).await;
+
+        let order: Vec<(PlaneId, Option<PlaneId>)> =
+            arrivals.iter().map(|a| (a.id, a.leader)).collect();
+        assert_eq!(order, vec![(42, None), (7, Some(42))]);
+        // Spaced 4 behind the heavy plane rather than arriving a tick after it.
+        assert!((arrivals[1].slot - arrivals[0].slot - 4.0).abs() < 0.01);
+        assert!(arrivals[1].delay() > 2.5);
+    }
+
+    #[tokio::test]
+    async fn test_process_data_warns_planes_in_wake() {
+        // A light plane 3.6 behind a heavy one: clear of conflict, but inside its wake.
+        let config = Config::default();
+        let tracks = [
+            (1, [(12.6, 10.0), (13.6, 10.0)]),
+            (2, [(9.0, 10.0), (10.0, 10.0)]),
+        ];
+        let coordinates = coordinates_with(&tracks, Duration::ZERO, &config).await;
+        let heavy_light = HashMap::from([(1, WakeCategory::HEAVY), (2, WakeCategory::LIGHT)]);
+        let sectors: SectorStatus = Arc::new(Mutex::new(Occupancy::default()));
+        let mut filter = AlertFilter::default();
+
+        let mut sent = Vec::new();
+        for categories in [heavy_light.clone(), heavy_light, HashMap::new()] {
+            let alerts = Manager::process_data(
+                &coordinates,
+                &config,
+                &[],
+                &sectors,
+                &Arc::new(Mutex::new(categories)),
+                &mut Encounters::default(),
+                &mut filter,
+                Instant::now(),
+            )
+            .await;
+            sent.push(alerts);
+        }
+
+        // Warned once, not again while still in the wake, and never behind a medium plane.
+        assert_eq!(sent[0].len(), 1);
+        match sent[0][0] {
+            (2, Alert::Wake(wake)) => {
+                assert_eq!((wake.leader, wake.required), (1, 4.0));
+                assert!(wake.distance > 3.2 && wake.distance < 4.0);
+            }
+            other => panic!("Unexpected alert {:?}", other),
+        }
+        assert!(sent[1].is_empty());
+        assert!(sent[2].is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_process_data_ignores_stale_tracks() {
+        let config = Config::default();
+        let tracks = [
+            (42, [(10.0, 10.0), (11.0, 10.0)]),
+            (7, [(16.0, 10.0), (15.0, 10.0)]),
+        ];
+        let age = Duration::from_millis(config.tracking.stale_after_ms + 1000);
+        let coordinates = coordinates_with(&tracks, age, &config).await;
+
+        assert!(alerted(&coordinates, &config).await.is_empty());
+        assert!(coordinates.lock().await.values().all(|track| track.stale));
+    }
+
+    #[tokio::test]
+    async fn test_store_coordinate_stamps_and_drops_duplicates() {
+        let config = Config::default();
+        let coordinates: Coordinates = Arc::new(Mutex::new(HashMap::new()));
+        let report = Report::new(
+            Vector3::new(1.0, 2.0, 3.0),
+            Some(Duration::from_millis(5000)),
+        );
+
+        for _ in 0..2 {
+            let result = Manager::store_coordinate(
+                &coordinates,
+                1,
+                &report.to_bytes(),
+                Instant::now(),
+                &config,
+                &None,
+            )
+            .await;
+            assert_eq!(result, Ok(()));
+        }
+
+        let data = coordinates.lock().await;
+        let samples = data.get(&1).map(|track| track.samples.live().clone());
+        assert_eq!(samples.as_ref().map(|s| s.len()), Some(1));
+        assert_eq!(
+            samples.and_then(|s| s.back().and_then(|sample| sample.sent)),
+            Some(Duration::from_millis(5000))
+        );
+    }
+
+    /// Run a manager on a free port until the returned sender is used or dropped.
+    async fn serve(
+        manager: Manager,
+    ) -> (
+        TcpStream,
+        tokio::sync::oneshot::Sender<()>,
+        tokio::task::JoinHandle<Result<(), std::io::Error>>,
+    ) {
+        // Bind to a free port, then let the manager take it.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let mut config = (*manager.config).clone();
+        config.network.bind_address = format!("127.0.0.1:{}", port);
+        config.network.control_address = String::new();
+        let manager = Manager {
+            config: Arc::new(config),
+            ..manager
+        };
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(manager.run(async {
+            let _ = stopped.await;
+        }));
+
+        let stream = loop {
+            match TcpStream::connect(("127.0.0.1", port)).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        (stream, stop, server)
+    }
+
+    #[tokio::test]
+    async fn test_run_tells_clients_about_shutdown() {
+        let manager = Manager::new(Config::default(), Vec::new(), None);
+        let state_machine = manager.state_machine.clone();
+        let (mut stream, stop, server) = serve(manager).await;
+
+        let hello = Manager::flag_packet(FlagState::COORDINATE, 5);
+        serialize_packet(hello, &mut stream).await.unwrap();
+        let released = deserialize_packet(&mut stream).await.unwrap();
+        assert_eq!(released.header.flag, FlagState::RELEASE);
+
+        stop.send(()).unwrap();
+
+        let notice = deserialize_packet(&mut stream).await.unwrap();
+        assert_eq!(notice.header.flag, FlagState::SERVER_SHUTDOWN);
+        assert_eq!(notice.header.plane_id, 5);
+        let result = timeout(Duration::from_secs(5), server).await;
+        assert!(matches!(result, Ok(Ok(Ok(())))));
+        assert_eq!(state_machine.get_state(), State::CLOSED);
+    }
+
+    #[tokio::test]
+    async fn test_run_holds_departures_while_degraded() {
+        let mut config = Config::default();
+        config.timing.tick_ms = 10;
+        let manager = Manager::new(config, Vec::new(), None);
+        let state_machine = manager.state_machine.clone();
+        let mut state = state_machine.subscribe();
+        let (mut stream, _stop, _server) = serve(manager).await;
+        state.wait_for(|s| *s == State::OPEN).await.unwrap();
+        state_machine.degrade().unwrap();
+
+        let hello = Manager::flag_packet(FlagState::COORDINATE, 6);
+        serialize_packet(hello, &mut stream).await.unwrap();
+        let held = deserialize_packet(&mut stream).await.unwrap();
+        assert_eq!(held.header.flag, FlagState::HOLD);
+
+        state_machine.open().unwrap();
+
+        let released = deserialize_packet(&mut stream).await.unwrap();
+        assert_eq!(released.header.flag, FlagState::RELEASE);
+    }
+
+    #[tokio::test]
+    async fn test_run_ends_held_sessions_that_leave_or_fall_silent() {
+        let mut config = Config::default();
+        config.timing.tick_ms = 10;
+        config.timing.client_timeout_ms = 200;
+        let manager = Manager::new(config, Vec::new(), None);
+        let state_machine = manager.state_machine.clone();
+        let sessions = manager.sessions.clone();
+        let mut state = state_machine.subscribe();
+        let (mut first, _stop, _server) = serve(manager).await;
+        state.wait_for(|s| *s == State::OPEN).await.unwrap();
+        state_machine.degrade().unwrap();
+
+        serialize_packet(hello(6, 1), &mut first).await.unwrap();
+        let held = deserialize_packet(&mut first).await.unwrap();
+        assert_eq!(held.header.flag, FlagState::HOLD);
+        let mut second = connect(&first).await;
+        drop(first);
+
+        // The aircraft is free again as soon as the held client hangs up.
+        timeout(Duration::from_secs(5), async {
+            while sessions.lock().await.is_live(6) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        serialize_packet(hello(6, 2), &mut second).await.unwrap();
+        let held = deserialize_packet(&mut second).await.unwrap();
+        assert_eq!(held.header.flag, FlagState::HOLD);
+
+        // A held client that stays silent is dropped too.
+        let closed = timeout(Duration::from_secs(5), deserialize_packet(&mut second)).await;
+        assert!(matches!(closed, Ok(Err(_))));
+        assert!(!sessions.lock().await.is_live(6));
+    }
+
+    #[tokio::test]
+    async fn test_run_ends_sessions_on_protocol_errors() {
+        let manager = Manager::new(Config::default(), Vec::new(), None);
+        let coordinates = manager.coordinates.clone();
+        let (mut stream, _stop, _server) = serve(manager).await;
+
+        let hello = Manager::flag_packet(FlagState::COORDINATE, 7);
+        serialize_packet(hello, &mut stream).await.unwrap();
+        let released = deserialize_packet(&mut stream).await.unwrap();
+        assert_eq!(released.header.flag, FlagState::RELEASE);
+
+        // Alerts only go from the server to clients.
+        let alert = Manager::flag_packet(FlagState::COLLISION, 7);
+        serialize_packet(alert, &mut stream).await.unwrap();
+
+        let closed = timeout(Duration::from_secs(5), deserialize_packet(&mut stream)).await;
+        assert!(matches!(closed, Ok(Err(_))));
+        assert!(coordinates.lock().await.get(&7).is_none());
+    }
+
+    /// Connect another client to a served manager.
+    async fn connect(first: &TcpStream) -> TcpStream {
+        TcpStream::connect(first.peer_addr().unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// First packet of a client, with a session token.
+    fn hello(plane_id: PlaneId, token: u64) -> Packet {
+        let mut body = vec![0; Report::size()];
+        body.push(WakeCategory::MEDIUM as u8);
+        body.extend_from_slice(&token.to_be_bytes());
+        Packet {
+            header: PacketHeader {
+                flag: FlagState::COORDINATE,
+                plane_id,
+                body_size: body.len() as u16,
+                seq_len: 0,
+            },
+            body,
         }
     }
+
+    #[tokio::test]
+    async fn test_run_rejects_duplicate_identities() {
+        let manager = Manager::new(Config::default(), Vec::new(), None);
+        let sessions = manager.sessions.clone();
+        let (mut first, _stop, _server) = serve(manager).await;
+        serialize_packet(hello(8, 1), &mut first).await.unwrap();
+        let released = deserialize_packet(&mut first).await.unwrap();
+        assert_eq!(released.header.flag, FlagState::RELEASE);
+
+        let mut second = connect(&first).await;
+        serialize_packet(hello(8, 2), &mut second).await.unwrap();
+
+        let rejected = deserialize_packet(&mut second).await.unwrap();
+        assert_eq!(rejected.header.flag, FlagState::REJECTED);
+        assert!(deserialize_packet(&mut second).await.is_err());
+        let registry = sessions.lock().await;
+        assert!(registry.is_live(8));
+        assert_eq!(registry.audit().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_sends_alerts_without_waiting_for_the_client() {
+        let manager = Manager::new(Config::default(), Vec::new(), None);
+        let sessions = manager.sessions.clone();
+        let (mut stream, _stop, _server) = serve(manager).await;
+        serialize_packet(hello(6, 1), &mut stream).await.unwrap();
+        let released = deserialize_packet(&mut stream).await.unwrap();
+        assert_eq!(released.header.flag, FlagState::RELEASE);
+
+        // The client stays silent, the alert still goes out as soon as it is queued.
+        let alert = Manager::flag_packet(FlagState::COLLISION, 6);
+        assert!(sessions.lock().await.deliver(6, alert.clone()));
+
+        let received = timeout(Duration::from_millis(500), deserialize_packet(&mut stream))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, alert);
+    }
+
+    #[tokio::test]
+    async fn test_run_disconnects_on_operator_request() {
+        let manager = Manager::new(Config::default(), Vec::new(), None);
+        let console = manager.console();
+        let (mut stream, _stop, _server) = serve(manager).await;
+        serialize_packet(hello(9, 1), &mut stream).await.unwrap();
+        let released = deserialize_packet(&mut stream).await.unwrap();
+        assert_eq!(released.header.flag, FlagState::RELEASE);
+
+        assert_eq!(console.reply("disconnect 9").await, "Disconnecting 9");
+
+        let disconnected = deserialize_packet(&mut stream).await.unwrap();
+        assert_eq!(disconnected.header.flag, FlagState::DISCONNECTED);
+        assert!(deserialize_packet(&mut stream).await.is_err());
+        assert_eq!(console.reply("aircraft").await, "No aircraft connected");
+    }
+
+    #[tokio::test]
+    async fn test_run_lets_the_same_aircraft_take_over() {
+        let manager = Manager::new(Config::default(), Vec::new(), None);
+        let sessions = manager.sessions.clone();
+        let (mut first, _stop, _server) = serve(manager).await;
+        serialize_packet(hello(8, 1), &mut first).await.unwrap();
+        let released = deserialize_packet(&mut first).await.unwrap();
+        assert_eq!(released.header.flag, FlagState::RELEASE);
+
+        let mut second = connect(&first).await;
+        serialize_packet(hello(8, 1), &mut second).await.unwrap();
+
+        let released = deserialize_packet(&mut second).await.unwrap();
+        assert_eq!(released.header.flag, FlagState::RELEASE);
+        // The replaced session is closed without forgetting the aircraft.
+        assert!(deserialize_packet(&mut first).await.is_err());
+        assert!(sessions.lock().await.is_live(8));
+    }
+
+    #[tokio::test]
+    async fn test_run_keeps_holding_departures_that_are_taken_over() {
+        let mut config = Config::default();
+        config.timing.tick_ms = 10;
+        let manager = Manager::new(config, Vec::new(), None);
+        let state_machine = manager.state_machine.clone();
+        let mut state = state_machine.subscribe();
+        let (mut first, _stop, _server) = serve(manager).await;
+        state.wait_for(|s| *s == State::OPEN).await.unwrap();
+        state_machine.degrade().unwrap();
+        serialize_packet(hello(8, 1), &mut first).await.unwrap();
+        let held = deserialize_packet(&mut first).await.unwrap();
+        assert_eq!(held.header.flag, FlagState::HOLD);
+
+        let mut second = connect(&first).await;
+        serialize_packet(hello(8, 1), &mut second).await.unwrap();
+
+        let held = deserialize_packet(&mut second).await.unwrap();
+        assert_eq!(held.header.flag, FlagState::HOLD);
+        assert!(deserialize_packet(&mut first).await.is_err());
+
+        // Released once the server opens, like any other held departure.
+        state_machine.open().unwrap();
+        let released = deserialize_packet(&mut second).await.unwrap();
+        assert_eq!(released.header.flag, FlagState::RELEASE);
+    }
 }
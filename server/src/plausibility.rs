@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utils::vector::Vector3;
+
+/// Physical limits an aircraft cannot exceed between two consecutive reports.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlausibilityLimits {
+    /// Maximum horizontal distance covered in one tick.
+    pub max_speed: f32,
+    /// Maximum change in altitude in one tick.
+    pub max_climb_rate: f32,
+}
+
+/// A report that implies motion the aircraft cannot perform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Implausible {
+    /// Implied horizontal speed per tick.
+    Speed(f32),
+    /// Implied climb or descent rate per tick.
+    ClimbRate(f32),
+}
+
+impl fmt::Display for Implausible {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Implausible::Speed(s) => write!(f, "implied speed of {} per tick", s),
+            Implausible::ClimbRate(r) => write!(f, "implied climb rate of {} per tick", r),
+        }
+    }
+}
+
+impl PlausibilityLimits {
+    /// Check the motion implied between the previous and the current report, dt ticks apart.
+    pub fn check(&self, prev: Vector3, curr: Vector3, dt: f32) -> Result<(), Implausible> {
+        let speed = ((curr.x - prev.x).powi(2) + (curr.y - prev.y).powi(2)).sqrt() / dt;
+        if speed > self.max_speed {
+            return Err(Implausible::Speed(speed));
+        }
+
+        let climb_rate = (curr.z - prev.z).abs() / dt;
+        if climb_rate > self.max_climb_rate {
+            return Err(Implausible::ClimbRate(climb_rate));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: PlausibilityLimits = PlausibilityLimits {
+        max_speed: 10.0,
+        max_climb_rate: 1000.0,
+    };
+
+    #[test]
+    fn test_check_plausible() {
+        let prev = Vector3::new(0.0, 0.0, 0.0);
+        let curr = Vector3::new(3.0, 4.0, 500.0);
+
+        assert_eq!(Ok(()), LIMITS.check(prev, curr, 1.0));
+    }
+
+    #[test]
+    fn test_check_speed() {
+        let prev = Vector3::new(0.0, 0.0, 0.0);
+        let curr = Vector3::new(12.0, 16.0, 0.0);
+
+        assert_eq!(Err(Implausible::Speed(20.0)), LIMITS.check(prev, curr, 1.0));
+    }
+
+    #[test]
+    fn test_check_climb_rate() {
+        let prev = Vector3::new(0.0, 0.0, 30000.0);
+        let curr = Vector3::new(1.0, 1.0, 0.0);
+
+        assert_eq!(
+            Err(Implausible::ClimbRate(30000.0)),
+            LIMITS.check(prev, curr, 1.0)
+        );
+    }
+
+    #[test]
+    fn test_check_scales_with_elapsed_ticks() {
+        let prev = Vector3::new(0.0, 0.0, 0.0);
+        let curr = Vector3::new(12.0, 16.0, 0.0);
+
+        assert_eq!(Ok(()), LIMITS.check(prev, curr, 2.0));
+        assert_eq!(Err(Implausible::Speed(40.0)), LIMITS.check(prev, curr, 0.5));
+    }
+}
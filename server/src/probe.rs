@@ -0,0 +1,286 @@
+use crate::detector::PlaneId;
+use serde::{Deserialize, Serialize};
+use utils::flight_plan::FlightPlan;
+use utils::vector::Vector3;
+
+/// Parameters of the medium term conflict probe.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProbeConfig {
+    /// Ticks each flight plan is followed ahead.
+    pub horizon: u32,
+    /// Time between two runs of the probe.
+    pub interval_ms: u64,
+    /// Distance under which two planned routes are in conflict.
+    /// Wider than the detector tolerance since aircraft drift from their plans.
+    pub separation: f32,
+}
+
+/// Planned path of an aircraft from where it is now, as positions at increasing times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trajectory {
+    pub id: PlaneId,
+    /// Ticks from now and position at each turn of the route. The aircraft lands at the last one.
+    pub points: Vec<(f32, Vector3)>,
+}
+
+/// A loss of separation predicted along two flight plans.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeConflict {
+    /// Aircraft with the lower id.
+    pub a: PlaneId,
+    /// Aircraft with the higher id.
+    pub b: PlaneId,
+    /// Ticks until the aircraft are first closer than the separation.
+    pub time: f32,
+    /// Smallest distance between the aircraft within the horizon.
+    pub distance: f32,
+}
+
+/// Fraction of the segment from a to b closest to p, clamped to the segment.
+fn projection(p: Vector3, a: Vector3, b: Vector3) -> f32 {
+    let ab = b.sub(a);
+    let length = ab.x * ab.x + ab.y * ab.y + ab.z * ab.z;
+    if length > 0.0 {
+        let ap = p.sub(a);
+        ((ap.x * ab.x + ap.y * ab.y + ap.z * ab.z) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Point a fraction of the way from a to b.
+fn lerp(a: Vector3, b: Vector3, t: f32) -> Vector3 {
+    let ab = b.sub(a);
+    Vector3::new(a.x + ab.x * t, a.y + ab.y * t, a.z + ab.z * t)
+}
+
+/// Distance between two points over the ground, altitude left out since the planned speed is
+/// flown horizontally.
+fn ground_distance(a: Vector3, b: Vector3) -> f32 {
+    ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt()
+}
+
+impl Trajectory {
+    /// Follow a flight plan from the current position of the aircraft.
+    /// The aircraft is assumed to be on the leg of the route closest to it, and to fly the rest
+    /// of the route at the planned speed.
+    pub fn new(id: PlaneId, plan: &FlightPlan, position: Vector3) -> Trajectory {
+        let route = plan.route();
+        let leg = route
+            .windows(2)
+            .enumerate()
+            .map(|(i, leg)| {
+                let closest = lerp(leg[0], leg[1], projection(position, leg[0], leg[1]));
+                (i, Vector3::distance(position, closest))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(0, |(i, _)| i);
+
+        let mut points = vec![(0.0, position)];
+        let mut time = 0.0;
+        let mut previous = position;
+        for next in route.iter().skip(leg + 1) {
+            time += ground_distance(previous, *next) / plan.speed;
+            points.push((time, *next));
+            previous = *next;
+        }
+        Trajectory { id, points }
+    }
+
+    /// Time the aircraft lands.
+    pub fn end(&self) -> f32 {
+        self.points.last().map_or(0.0, |(time, _)| *time)
+    }
+
+    /// Planned position at a time, if the aircraft has not landed yet.
+    pub fn position_at(&self, time: f32) -> Option<Vector3> {
+        if time < 0.0 || time > self.end() {
+            return None;
+        }
+        let next = self.points.iter().position(|(t, _)| *t >= time)?;
+        let (t1, p1) = self.points[next];
+        match next.checked_sub(1).map(|i| self.points[i]) {
+            Some((t0, p0)) if t1 > t0 => Some(lerp(p0, p1, (time - t0) / (t1 - t0))),
+            _ => Some(p1),
+        }
+    }
+}
+
+/// First loss of separation and closest approach between two trajectories within the horizon.
+/// Both aircraft fly straight between turns, so each interval between turns is solved exactly.
+fn compare(a: &Trajectory, b: &Trajectory, config: &ProbeConfig) -> Option<ProbeConflict> {
+    let end = a.end().min(b.end()).min(config.horizon as f32);
+    let mut times: Vec<f32> = a
+        .points
+        .iter()
+        .chain(&b.points)
+        .map(|(t, _)| *t)
+        .filter(|t| *t < end)
+        .chain([end])
+        .collect();
+    times.sort_by(f32::total_cmp);
+    times.dedup();
+
+    let mut first_loss: Option<f32> = None;
+    let mut closest = f32::INFINITY;
+    for window in times.windows(2) {
+        let (t0, t1) = (window[0], window[1]);
+        let dt = t1 - t0;
+        let (Some(a0), Some(a1), Some(b0), Some(b1)) = (
+            a.position_at(t0),
+            a.position_at(t1),
+            b.position_at(t0),
+            b.position_at(t1),
+        ) else {
+            continue;
+        };
+
+        // Relative position and velocity of b seen from a over the interval.
+        let p = b0.sub(a0);
+        let v = b1.sub(a1).sub(p);
+        let pp = p.x * p.x + p.y * p.y + p.z * p.z;
+        let pv = p.x * v.x + p.y * v.y + p.z * v.z;
+        let vv = v.x * v.x + v.y * v.y + v.z * v.z;
+
+        let s = if vv > 0.0 {
+            (-pv / vv).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        closest = closest.min((pp + 2.0 * pv * s + vv * s * s).sqrt());
+
+        if first_loss.is_none() {
+            let c = pp - config.separation * config.separation;
+            let discriminant = pv * pv - vv * c;
+            if c < 0.0 {
+                first_loss = Some(t0);
+            } else if vv > 0.0 && discriminant >= 0.0 {
+                let entry = (-pv - discriminant.sqrt()) / vv;
+                if (0.0..=1.0).contains(&entry) {
+                    first_loss = Some(t0 + entry * dt);
+                }
+            }
+        }
+    }
+
+    first_loss.map(|time| ProbeConflict {
+        a: a.id.min(b.id),
+        b: a.id.max(b.id),
+        time,
+        distance: closest,
+    })
+}
+
+/// Compare every pair of trajectories, ordered by the time separation is first lost.
+pub fn probe(trajectories: &[Trajectory], config: &ProbeConfig) -> Vec<ProbeConflict> {
+    let mut conflicts = Vec::new();
+    for (i, a) in trajectories.iter().enumerate() {
+        for b in &trajectories[i + 1..] {
+            if let Some(conflict) = compare(a, b, config) {
+                conflicts.push(conflict);
+            }
+        }
+    }
+    conflicts.sort_by(|x, y| x.time.total_cmp(&y.time).then((x.a, x.b).cmp(&(y.a, y.b))));
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: ProbeConfig = ProbeConfig {
+        horizon: 300,
+        interval_ms: 10000,
+        separation: 3.0,
+    };
+
+    fn plan(origin: (f32, f32), waypoints: &[(f32, f32)], destination: (f32, f32)) -> FlightPlan {
+        let point = |(x, y): (f32, f32)| Vector3::new(x, y, 0.0);
+        FlightPlan::new(
+            point(origin),
+            point(destination),
+            waypoints.iter().copied().map(point).collect(),
+            1.0,
+        )
+    }
+
+    #[test]
+    fn test_trajectory_starts_on_closest_leg() {
+        let plan = plan((0.0, 0.0), &[(10.0, 0.0)], (10.0, 10.0));
+
+        let trajectory = Trajectory::new(1, &plan, Vector3::new(10.0, 2.0, 0.0));
+
+        assert_eq!(
+            trajectory.points,
+            vec![
+                (0.0, Vector3::new(10.0, 2.0, 0.0)),
+                (8.0, Vector3::new(10.0, 10.0, 0.0))
+            ]
+        );
+        assert_eq!(
+            trajectory.position_at(4.0),
+            Some(Vector3::new(10.0, 6.0, 0.0))
+        );
+        assert_eq!(trajectory.position_at(9.0), None);
+    }
+
+    #[test]
+    fn test_trajectory_times_legs_over_the_ground() {
+        let plan = plan((0.0, 0.0), &[], (10.0, 0.0));
+
+        let trajectory = Trajectory::new(1, &plan, Vector3::new(4.0, 0.0, 1000.0));
+
+        assert_eq!(trajectory.end(), 6.0);
+    }
+
+    #[test]
+    fn test_probe_finds_crossing_routes_early() {
+        // Both aircraft turn onto crossing legs and meet about 96 ticks from now,
+        // far beyond the look-ahead of the short term detector.
+        let a = plan((0.0, 0.0), &[(50.0, 0.0)], (100.0, 50.0));
+        let b = plan((100.0, -55.0), &[(100.0, 20.0)], (20.0, 80.0));
+        let trajectories = [
+            Trajectory::new(4, &a, a.origin),
+            Trajectory::new(2, &b, b.origin),
+        ];
+
+        let conflicts = probe(&trajectories, &CONFIG);
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!((conflicts[0].a, conflicts[0].b), (2, 4));
+        assert!(conflicts[0].time > 90.0 && conflicts[0].time < 110.0);
+        assert!(conflicts[0].distance < 1.0);
+    }
+
+    #[test]
+    fn test_probe_ignores_separated_and_distant_conflicts() {
+        // Parallel routes 5 apart, and a crossing beyond the horizon.
+        let a = plan((0.0, 0.0), &[], (400.0, 0.0));
+        let b = plan((0.0, 5.0), &[], (400.0, 5.0));
+        let c = plan((350.0, -50.0), &[], (350.0, 50.0));
+        let trajectories = [
+            Trajectory::new(1, &a, a.origin),
+            Trajectory::new(2, &b, b.origin),
+            Trajectory::new(3, &c, Vector3::new(350.0, -45.0, 0.0)),
+        ];
+
+        assert!(probe(&trajectories, &CONFIG).is_empty());
+    }
+
+    #[test]
+    fn test_probe_stops_at_landing() {
+        // The first aircraft lands before the second reaches its destination.
+        let a = plan((0.0, 0.0), &[], (10.0, 0.0));
+        let b = plan((0.0, 10.0), &[], (10.0, 0.0));
+        let trajectories = [
+            Trajectory::new(1, &a, a.origin),
+            Trajectory::new(2, &b, Vector3::new(0.0, 10.0, 0.0)),
+        ];
+
+        let conflicts = probe(&trajectories, &CONFIG);
+
+        assert!(conflicts.is_empty(), "{:?}", conflicts);
+    }
+}
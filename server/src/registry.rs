@@ -0,0 +1,494 @@
+use crate::config::NetworkConfig;
+use crate::detector::PlaneId;
+use core::fmt;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::SystemTime;
+use tokio::sync::{mpsc, oneshot};
+use utils::packet::{FlagState, Packet};
+
+/// Audit entries kept, oldest dropped first.
+const AUDIT_LENGTH: usize = 256;
+
+/// Token a client gives after its report and wake category in its first packet, if any.
+/// A client that reconnects with the token of the live session of its aircraft takes it over.
+pub fn hello_token(body: &[u8], report_size: usize) -> Option<u64> {
+    let start = report_size + 1;
+    let bytes = body.get(start..start + 8)?;
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// What was done about a client connecting with the identity of a live session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    /// The client could not prove it is the same aircraft and was turned away.
+    Rejected,
+    /// The client proved it is the same aircraft and replaced the live session.
+    TookOver,
+}
+
+/// A client that connected with the identity of a live session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AuditEntry {
+    pub time: SystemTime,
+    pub plane_id: PlaneId,
+    pub action: AuditAction,
+    /// Address of the client that connected.
+    pub peer: SocketAddr,
+    /// Address of the live session.
+    pub live: SocketAddr,
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self.action {
+            AuditAction::Rejected => "rejected",
+            AuditAction::TookOver => "took over",
+        };
+        write!(
+            f,
+            "Client {} from {} {}, live session from {}",
+            self.plane_id, self.peer, action, self.live
+        )
+    }
+}
+
+/// A session admitted into the registry.
+#[derive(Debug)]
+pub struct Registration {
+    /// Number of the session, unique for the lifetime of the registry.
+    pub generation: u64,
+    /// Whether the session replaced a live one of the same aircraft.
+    pub took_over: bool,
+    /// Whether the replaced session had been released, so this one carries on airborne.
+    pub airborne: bool,
+    /// Completes when another session takes this one over.
+    pub taken_over: oneshot::Receiver<()>,
+    /// Completes when the operator disconnects the aircraft.
+    pub disconnected: oneshot::Receiver<()>,
+    /// Packets addressed to the aircraft, to be sent to the client.
+    pub outbox: mpsc::Receiver<Packet>,
+    /// Resolution advisories and clearances addressed to the aircraft, sent before the outbox.
+    pub urgent: mpsc::Receiver<Packet>,
+}
+
+/// Delivery queue of a live session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStatus {
+    pub plane_id: PlaneId,
+    /// Packets waiting to be sent.
+    pub queued: usize,
+    /// Packets dropped since the session started because the queue was full.
+    pub dropped: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    generation: u64,
+    token: Option<u64>,
+    peer: SocketAddr,
+    airborne: bool,
+    take_over: oneshot::Sender<()>,
+    disconnect: Option<oneshot::Sender<()>>,
+    outbox: mpsc::Sender<Packet>,
+    urgent: mpsc::Sender<Packet>,
+    dropped: u64,
+}
+
+/// Whether a packet commands the aircraft, so it must not wait behind or be lost to awareness
+/// alerts.
+fn urgent(packet: &Packet) -> bool {
+    matches!(
+        packet.header.flag,
+        FlagState::COLLISION | FlagState::CLEAR_OF_CONFLICT
+    )
+}
+
+/// Live client sessions by aircraft identity, so two connections never feed the same track.
+/// Each session has its own bounded queues of packets to send, so packets are only addressed to
+/// the aircraft concerned and a slow client only ever loses its own. Resolution advisories and
+/// clearances have a queue of their own, so a backlog of traffic and wake advisories never
+/// crowds them out.
+#[derive(Debug)]
+pub struct SessionRegistry {
+    live: HashMap<PlaneId, Entry>,
+    generations: u64,
+    audit: VecDeque<AuditEntry>,
+    queue_length: usize,
+}
+
+impl SessionRegistry {
+    /// Empty registry giving each session a queue of the given length.
+    pub fn new(queue_length: usize) -> SessionRegistry {
+        SessionRegistry {
+            live: HashMap::new(),
+            generations: 0,
+            audit: VecDeque::new(),
+            queue_length,
+        }
+    }
+
+    /// Admit a session for an aircraft.
+    /// A second session with the identity of a live one takes it over if both gave the same
+    /// token, and is rejected otherwise. Either way an audit entry is kept. A session taking
+    /// over an airborne one is airborne too, any other starts on the ground.
+    pub fn register(
+        &mut self,
+        plane_id: PlaneId,
+        token: Option<u64>,
+        peer: SocketAddr,
+    ) -> Option<Registration> {
+        let mut took_over = false;
+        let mut airborne = false;
+        if let Some(live) = self.live.get(&plane_id) {
+            let same = token.is_some() && token == live.token;
+            let entry = AuditEntry {
+                time: SystemTime::now(),
+                plane_id,
+                action: if same {
+                    AuditAction::TookOver
+                } else {
+                    AuditAction::Rejected
+                },
+                peer,
+                live: live.peer,
+            };
+            tracing::warn!("{}", entry);
+            if self.audit.len() == AUDIT_LENGTH {
+                self.audit.pop_front();
+            }
+            self.audit.push_back(entry);
+            if !same {
+                return None;
+            }
+            if let Some(live) = self.live.remove(&plane_id) {
+                airborne = live.airborne;
+                let _ = live.take_over.send(());
+            }
+            took_over = true;
+        }
+
+        self.generations += 1;
+        let (take_over, taken_over) = oneshot::channel();
+        let (disconnect, disconnected) = oneshot::channel();
+        let (sender, outbox) = mpsc::channel(self.queue_length);
+        let (urgent_sender, urgent) = mpsc::channel(self.queue_length);
+        self.live.insert(
+            plane_id,
+            Entry {
+                generation: self.generations,
+                token,
+                peer,
+                airborne,
+                take_over,
+                disconnect: Some(disconnect),
+                outbox: sender,
+                urgent: urgent_sender,
+                dropped: 0,
+            },
+        );
+        Some(Registration {
+            generation: self.generations,
+            took_over,
+            airborne,
+            taken_over,
+            disconnected,
+            outbox,
+            urgent,
+        })
+    }
+
+    /// Queue a packet for the session of an aircraft, on the urgent queue if it commands the
+    /// aircraft.
+    /// Returns false if the aircraft has no live session or its queue is full, in which case the
+    /// packet is dropped.
+    pub fn deliver(&mut self, plane_id: PlaneId, packet: Packet) -> bool {
+        let Some(entry) = self.live.get_mut(&plane_id) else {
+            tracing::debug!("No session for {}, dropping {}", plane_id, packet);
+            return false;
+        };
+        let queue = if urgent(&packet) {
+            &entry.urgent
+        } else {
+            &entry.outbox
+        };
+        match queue.try_send(packet) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(packet)) => {
+                entry.dropped += 1;
+                tracing::warn!("Queue of {} is full, dropping {}", plane_id, packet);
+                false
+            }
+            // The session is closing and will not send anything more.
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Queue a packet for every live session but the one of an aircraft.
+    pub fn deliver_others(&mut self, plane_id: PlaneId, packet: &Packet) {
+        let others: Vec<PlaneId> = self
+            .live
+            .keys()
+            .copied()
+            .filter(|id| *id != plane_id)
+            .collect();
+        for other in others {
+            self.deliver(other, packet.clone());
+        }
+    }
+
+    /// Queue of every live session, by aircraft.
+    pub fn queues(&self) -> Vec<QueueStatus> {
+        let mut queues: Vec<QueueStatus> = self
+            .live
+            .iter()
+            .map(|(plane_id, entry)| QueueStatus {
+                plane_id: *plane_id,
+                queued: [&entry.outbox, &entry.urgent]
+                    .iter()
+                    .map(|queue| queue.max_capacity() - queue.capacity())
+                    .sum(),
+                dropped: entry.dropped,
+            })
+            .collect();
+        queues.sort_by_key(|q| q.plane_id);
+        queues
+    }
+
+    /// Remove a session that ended.
+    /// Returns false if it had been taken over, in which case the aircraft is still live.
+    pub fn release(&mut self, plane_id: PlaneId, generation: u64) -> bool {
+        match self.live.get(&plane_id) {
+            Some(entry) if entry.generation == generation => {
+                self.live.remove(&plane_id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Note that a session was released, so that a session taking it over need not wait for a
+    /// departure clearance.
+    pub fn take_off(&mut self, plane_id: PlaneId, generation: u64) {
+        if let Some(entry) = self.live.get_mut(&plane_id)
+            && entry.generation == generation
+        {
+            entry.airborne = true;
+        }
+    }
+
+    /// Ask the live session of an aircraft to end.
+    /// Returns false if the aircraft has no live session or was already asked.
+    pub fn disconnect(&mut self, plane_id: PlaneId) -> bool {
+        let disconnect = self
+            .live
+            .get_mut(&plane_id)
+            .and_then(|entry| entry.disconnect.take());
+        match disconnect {
+            Some(disconnect) => disconnect.send(()).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Address of the client flying an aircraft, if it has a live session.
+    pub fn peer(&self, plane_id: PlaneId) -> Option<SocketAddr> {
+        self.live.get(&plane_id).map(|entry| entry.peer)
+    }
+
+    /// Whether an aircraft has a live session.
+    pub fn is_live(&self, plane_id: PlaneId) -> bool {
+        self.live.contains_key(&plane_id)
+    }
+
+    /// Audit entries, oldest first.
+    pub fn audit(&self) -> impl Iterator<Item = &AuditEntry> {
+        self.audit.iter()
+    }
+}
+
+impl Default for SessionRegistry {
+    fn default() -> Self {
+        Self::new(NetworkConfig::default().queue_length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::packet::{FlagState, PacketHeader};
+
+    fn packet(flag: FlagState, plane_id: PlaneId) -> Packet {
+        Packet {
+            header: PacketHeader {
+                flag,
+                plane_id,
+                body_size: 0,
+                seq_len: 0,
+            },
+            body: Vec::new(),
+        }
+    }
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_hello_token() {
+        let mut body = vec![0; 21];
+        assert_eq!(hello_token(&body, 20), None);
+        body.extend_from_slice(&42u64.to_be_bytes());
+        assert_eq!(hello_token(&body, 20), Some(42));
+    }
+
+    #[test]
+    fn test_rejects_second_session() {
+        let mut registry = SessionRegistry::default();
+        let first = registry.register(3, Some(7), peer(1000)).unwrap();
+        assert!(!first.took_over);
+
+        assert!(registry.register(3, Some(8), peer(2000)).is_none());
+        assert!(registry.register(3, None, peer(3000)).is_none());
+
+        let audit: Vec<(AuditAction, SocketAddr)> =
+            registry.audit().map(|e| (e.action, e.peer)).collect();
+        assert_eq!(
+            audit,
+            vec![
+                (AuditAction::Rejected, peer(2000)),
+                (AuditAction::Rejected, peer(3000))
+            ]
+        );
+        // Other aircraft are not affected.
+        assert!(registry.register(4, None, peer(4000)).is_some());
+    }
+
+    #[test]
+    fn test_same_token_takes_over() {
+        let mut registry = SessionRegistry::default();
+        let mut first = registry.register(3, Some(7), peer(1000)).unwrap();
+
+        let second = registry.register(3, Some(7), peer(2000)).unwrap();
+
+        assert!(second.took_over);
+        assert!(!second.airborne);
+        assert_eq!(first.taken_over.try_recv(), Ok(()));
+        assert_eq!(
+            registry.audit().map(|e| e.action).collect::<Vec<_>>(),
+            vec![AuditAction::TookOver]
+        );
+        // The replaced session ending leaves the aircraft live.
+        assert!(!registry.release(3, first.generation));
+        assert!(registry.is_live(3));
+        assert!(registry.release(3, second.generation));
+        assert!(!registry.is_live(3));
+    }
+
+    #[test]
+    fn test_taking_over_keeps_airborne_sessions_airborne() {
+        let mut registry = SessionRegistry::default();
+        let first = registry.register(3, Some(7), peer(1000)).unwrap();
+        let second = registry.register(3, Some(7), peer(2000)).unwrap();
+
+        // Only the live session can be marked.
+        registry.take_off(3, first.generation);
+        let third = registry.register(3, Some(7), peer(3000)).unwrap();
+        assert!(!third.airborne);
+
+        registry.take_off(3, third.generation);
+        let fourth = registry.register(3, Some(7), peer(4000)).unwrap();
+        assert!(fourth.airborne);
+        assert!(!second.airborne);
+    }
+
+    #[test]
+    fn test_sessions_without_token_never_take_over() {
+        let mut registry = SessionRegistry::default();
+        registry.register(3, None, peer(1000)).unwrap();
+
+        assert!(registry.register(3, None, peer(2000)).is_none());
+    }
+
+    #[test]
+    fn test_delivers_only_to_the_aircraft_concerned() {
+        let mut registry = SessionRegistry::default();
+        let mut first = registry.register(1, None, peer(1000)).unwrap();
+        let mut second = registry.register(2, None, peer(2000)).unwrap();
+
+        assert!(registry.deliver(2, packet(FlagState::COLLISION, 2)));
+        assert!(!registry.deliver(3, packet(FlagState::COLLISION, 3)));
+        registry.deliver_others(2, &packet(FlagState::WARNING, 2));
+
+        assert_eq!(
+            first.outbox.try_recv().map(|p| p.header.flag),
+            Ok(FlagState::WARNING)
+        );
+        assert!(first.outbox.try_recv().is_err());
+        assert_eq!(
+            second.urgent.try_recv().map(|p| p.header.flag),
+            Ok(FlagState::COLLISION)
+        );
+        assert!(second.outbox.try_recv().is_err());
+        assert!(first.urgent.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_disconnect_signals_the_session_once() {
+        let mut registry = SessionRegistry::default();
+        let mut session = registry.register(5, None, peer(1000)).unwrap();
+
+        assert!(registry.disconnect(5));
+        assert!(!registry.disconnect(5));
+        assert!(!registry.disconnect(6));
+
+        assert_eq!(session.disconnected.try_recv(), Ok(()));
+        // The session stays live until it ends.
+        assert_eq!(registry.peer(5), Some(peer(1000)));
+        assert!(registry.release(5, session.generation));
+        assert_eq!(registry.peer(5), None);
+    }
+
+    #[test]
+    fn test_full_queue_drops_and_counts() {
+        let mut registry = SessionRegistry::new(2);
+        let _session = registry.register(1, None, peer(1000)).unwrap();
+
+        for _ in 0..3 {
+            registry.deliver(1, packet(FlagState::TRAFFIC, 1));
+        }
+
+        assert_eq!(
+            registry.queues(),
+            vec![QueueStatus {
+                plane_id: 1,
+                queued: 2,
+                dropped: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn test_advisories_survive_a_full_queue() {
+        let mut registry = SessionRegistry::new(2);
+        let mut session = registry.register(1, None, peer(1000)).unwrap();
+        for _ in 0..3 {
+            registry.deliver(1, packet(FlagState::TRAFFIC, 1));
+        }
+
+        assert!(registry.deliver(1, packet(FlagState::COLLISION, 1)));
+        assert!(registry.deliver(1, packet(FlagState::CLEAR_OF_CONFLICT, 1)));
+
+        assert_eq!(
+            session.urgent.try_recv().map(|p| p.header.flag),
+            Ok(FlagState::COLLISION)
+        );
+        assert_eq!(
+            session.urgent.try_recv().map(|p| p.header.flag),
+            Ok(FlagState::CLEAR_OF_CONFLICT)
+        );
+        assert_eq!(
+            session.outbox.try_recv().map(|p| p.header.flag),
+            Ok(FlagState::TRAFFIC)
+        );
+    }
+}
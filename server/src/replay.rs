@@ -0,0 +1,482 @@
+use crate::alerting::{Alert, AlertFilter};
+use crate::config::Config;
+use crate::detector::PlaneId;
+use crate::encounter::Encounters;
+use crate::manager::{Categories, Coordinates, Manager, SectorStatus};
+use crate::zone::Zone;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use utils::report::Report;
+use utils::vector::Vector3;
+use utils::wake::WakeCategory;
+
+/// Where the server records the telemetry it receives, for later replay.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    /// Telemetry file, overwritten on start. Empty to not record.
+    pub file: String,
+}
+
+/// Something that happened to an aircraft during a recording.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// A report was accepted from the aircraft.
+    Report(Report),
+    /// The aircraft left and its track was dropped.
+    Exit,
+    /// The aircraft declared its wake category when it connected.
+    Category(WakeCategory),
+}
+
+/// One line of a telemetry file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Record {
+    /// Receive time, from the start of the recording.
+    pub time: Duration,
+    pub plane_id: PlaneId,
+    pub event: Event,
+}
+
+/// A line of a telemetry file that could not be read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Line number, from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+/// An alert the detector raised during a replay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlertRecord {
+    /// Simulated time of the tick that raised the alert, from the start of the recording.
+    pub time: Duration,
+    pub plane_id: PlaneId,
+    pub alert: Alert,
+}
+
+/// Alert lines found in only one of two replays.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AlertDiff {
+    /// Raised by the baseline only.
+    pub removed: Vec<String>,
+    /// Raised by the new run only.
+    pub added: Vec<String>,
+}
+
+/// Appends every declared category, accepted report and exit to a telemetry file.
+#[derive(Debug)]
+pub struct Recorder {
+    file: File,
+    start: Instant,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} ", self.time.as_millis(), self.plane_id)?;
+        match self.event {
+            Event::Report(report) => {
+                let p = report.position;
+                write!(f, "report {} {} {} ", p.x, p.y, p.z)?;
+                match report.sent {
+                    Some(sent) => write!(f, "{}", sent.as_millis()),
+                    None => write!(f, "-"),
+                }
+            }
+            Event::Exit => write!(f, "exit"),
+            Event::Category(category) => write!(f, "category {}", category),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Telemetry line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for AlertRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} ", self.time.as_millis(), self.plane_id)?;
+        match self.alert {
+            Alert::Traffic(traffic) => write!(
+                f,
+                "traffic {} {:.2} {:.0}",
+                traffic.intruder, traffic.cpa, traffic.time
+            ),
+            Alert::Resolution(advisory) => write!(
+                f,
+                "resolution {} {:.2} {:.2}",
+                advisory.sense, advisory.target_altitude, advisory.vertical_rate
+            ),
+            Alert::Clear => write!(f, "clear"),
+            Alert::Wake(wake) => write!(
+                f,
+                "wake {} {:.2} {:.2}",
+                wake.leader, wake.required, wake.distance
+            ),
+        }
+    }
+}
+
+impl Recorder {
+    /// Open the configured telemetry file, if recording is enabled.
+    pub fn create(config: &RecordingConfig) -> Result<Option<Recorder>, std::io::Error> {
+        if config.file.is_empty() {
+            return Ok(None);
+        }
+        let file = File::create(&config.file)?;
+        Ok(Some(Recorder {
+            file,
+            start: Instant::now(),
+        }))
+    }
+
+    /// Append an event received at an instant.
+    pub fn record(&mut self, received: Instant, plane_id: PlaneId, event: Event) {
+        let record = Record {
+            time: received.saturating_duration_since(self.start),
+            plane_id,
+            event,
+        };
+        if let Err(e) = writeln!(self.file, "{}", record) {
+            tracing::error!("Unable to record telemetry: {}", e);
+        }
+    }
+}
+
+/// Read a telemetry file, one `TIME_MS PLANE_ID report X Y Z SENT_MS`, `TIME_MS PLANE_ID exit` or
+/// `TIME_MS PLANE_ID category CATEGORY` per line. SENT_MS is `-` when the client did not stamp the
+/// report.
+/// Blank lines and lines starting with `#` are skipped. Records are ordered by time.
+pub fn parse(text: &str) -> Result<Vec<Record>, ParseError> {
+    let mut records = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| ParseError {
+            line: i + 1,
+            message: String::from(message),
+        };
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let time = fields[0]
+            .parse::<u64>()
+            .map_err(|_| error("time is not a number of milliseconds"))?;
+        let plane_id = fields
+            .get(1)
+            .and_then(|id| id.parse::<PlaneId>().ok())
+            .ok_or_else(|| error("plane id is not a number from 0 to 255"))?;
+        let event = match fields.get(2..) {
+            Some(["exit"]) => Event::Exit,
+            Some(["category", name]) => Event::Category(
+                WakeCategory::from_name(name)
+                    .ok_or_else(|| error("category is not light, medium, heavy or super"))?,
+            ),
+            Some(["report", x, y, z, sent]) => {
+                let coordinate = |v: &str| {
+                    v.parse::<f32>()
+                        .ok()
+                        .filter(|v| v.is_finite())
+                        .ok_or_else(|| error("position is not three finite numbers"))
+                };
+                let position = Vector3::new(coordinate(x)?, coordinate(y)?, coordinate(z)?);
+                let sent = match *sent {
+                    "-" => None,
+                    sent => Some(Duration::from_millis(
+                        sent.parse::<u64>()
+                            .map_err(|_| error("sent time is not a number of milliseconds"))?,
+                    )),
+                };
+                Event::Report(Report::new(position, sent))
+            }
+            _ => {
+                return Err(error(
+                    "expected `report X Y Z SENT_MS`, `exit` or `category CATEGORY`",
+                ));
+            }
+        };
+        records.push(Record {
+            time: Duration::from_millis(time),
+            plane_id,
+            event,
+        });
+    }
+    records.sort_by_key(|r| r.time);
+    Ok(records)
+}
+
+/// Feed recorded telemetry through the same code path as live reports, and run the detector
+/// once per tick on simulated time until the last record. Records are replayed in time order.
+/// Returns every alert that would have been sent, in order.
+pub async fn replay(records: &[Record], config: &Config, zones: &[Zone]) -> Vec<AlertRecord> {
+    let coordinates: Coordinates = Arc::new(Mutex::new(HashMap::new()));
+    let sectors: SectorStatus = Arc::new(Mutex::new(Default::default()));
+    let categories: Categories = Arc::new(Mutex::new(HashMap::new()));
+    let start = Instant::now();
+    let tick = config.timing.tick();
+    let mut records = records.to_vec();
+    records.sort_by_key(|r| r.time);
+    let end = records.last().map_or(Duration::ZERO, |r| r.time);
+
+    let mut encounters = Encounters::default();
+    let mut filter = AlertFilter::default();
+    let mut pending = records.iter().peekable();
+    let mut alerts = Vec::new();
+    let mut time = Duration::ZERO;
+    while time < end {
+        time += tick;
+        while let Some(record) = pending.next_if(|r| r.time <= time) {
+            match record.event {
+                Event::Report(report) => {
+                    // Rejected reports are logged and dropped, as they would be live.
+                    let _ = Manager::store_coordinate(
+                        &coordinates,
+                        record.plane_id,
+                        &report.to_bytes(),
+                        start + record.time,
+                        config,
+                        &None,
+                    )
+                    .await;
+                }
+                Event::Exit => {
+                    coordinates.lock().await.remove(&record.plane_id);
+                    categories.lock().await.remove(&record.plane_id);
+                    filter.forget(record.plane_id);
+                }
+                Event::Category(category) => {
+                    categories.lock().await.insert(record.plane_id, category);
+                }
+            }
+        }
+
+        let sent = Manager::process_data(
+            &coordinates,
+            config,
+            zones,
+            &sectors,
+            &categories,
+            &mut encounters,
+            &mut filter,
+            start + time,
+        )
+        .await;
+        alerts.extend(sent.into_iter().map(|(plane_id, alert)| AlertRecord {
+            time,
+            plane_id,
+            alert,
+        }));
+    }
+    alerts
+}
+
+/// Compare the alert lines of a baseline run with those of a new run.
+/// Lines are matched as a multiset, so reordering within a tick is not a difference.
+pub fn diff(baseline: &[String], current: &[String]) -> AlertDiff {
+    let mut counts: HashMap<&str, isize> = HashMap::new();
+    for line in baseline {
+        *counts.entry(line.as_str()).or_default() += 1;
+    }
+    for line in current {
+        *counts.entry(line.as_str()).or_default() -= 1;
+    }
+
+    let mut result = AlertDiff::default();
+    for line in baseline {
+        if let Some(count) = counts.get_mut(line.as_str())
+            && *count > 0
+        {
+            *count -= 1;
+            result.removed.push(line.clone());
+        }
+    }
+    for line in current {
+        if let Some(count) = counts.get_mut(line.as_str())
+            && *count < 0
+        {
+            *count += 1;
+            result.added.push(line.clone());
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two aircraft flying head on at the same altitude, one report per second each.
+    fn head_on() -> Vec<Record> {
+        let mut records = Vec::new();
+        for i in 0..8u64 {
+            for (plane_id, x, vx) in [(1, 0.0, 1.0), (2, 20.0, -1.0)] {
+                let position = Vector3::new(x + vx * i as f32, 10.0, 10.0);
+                records.push(Record {
+                    time: Duration::from_millis(1000 * i + 10),
+                    plane_id,
+                    event: Event::Report(Report::new(
+                        position,
+                        Some(Duration::from_millis(1000 * i)),
+                    )),
+                });
+            }
+        }
+        records
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let mut records = head_on();
+        records.insert(
+            0,
+            Record {
+                time: Duration::ZERO,
+                plane_id: 2,
+                event: Event::Category(WakeCategory::HEAVY),
+            },
+        );
+        records.push(Record {
+            time: Duration::from_millis(9000),
+            plane_id: 1,
+            event: Event::Exit,
+        });
+        let text: String = records.iter().map(|r| format!("{}\n", r)).collect();
+
+        assert_eq!(parse(&format!("# recorded\n\n{}", text)), Ok(records));
+        assert_eq!(
+            parse("0 1 report 1.5 2 3 -\n").map(|r| r[0].event),
+            Ok(Event::Report(Report::new(
+                Vector3::new(1.5, 2.0, 3.0),
+                None
+            )))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (text, line) in [
+            ("x 1 exit", 1),
+            ("0 1 exit\n0 300 exit", 2),
+            ("0 1 report 1 2", 1),
+            ("0 1 report 1 2 NaN -", 1),
+            ("0 1 land", 1),
+            ("0 1 category jumbo", 1),
+        ] {
+            assert_eq!(parse(text).map_err(|e| e.line), Err(line), "{}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_alerts_converging_planes() {
+        let config = Config::default();
+
+        let alerts = replay(&head_on(), &config, &[]).await;
+
+        assert!(alerts.iter().any(|a| a.plane_id == 1));
+        assert!(alerts.iter().any(|a| a.plane_id == 2));
+        assert!(
+            alerts
+                .iter()
+                .any(|a| matches!(a.alert, Alert::Resolution(_)))
+        );
+        assert_eq!(replay(&head_on(), &config, &[]).await, alerts);
+    }
+
+    #[tokio::test]
+    async fn test_replay_drops_exited_planes() {
+        let config = Config::default();
+        let exit = Duration::from_millis(3500);
+        let mut records: Vec<Record> = head_on()
+            .into_iter()
+            .filter(|r| r.plane_id == 1 || r.time < exit)
+            .collect();
+        records.push(Record {
+            time: exit,
+            plane_id: 2,
+            event: Event::Exit,
+        });
+
+        let full = replay(&head_on(), &config, &[]).await;
+        let alerts = replay(&records, &config, &[]).await;
+
+        assert!(full.iter().any(|a| a.time > exit));
+        assert!(alerts.iter().all(|a| a.time < exit));
+    }
+
+    #[tokio::test]
+    async fn test_replay_alerts_planes_that_rejoin() {
+        // Plane 2 leaves right after both were told of the other, and reports again at once.
+        let config = Config::default();
+        let exit = Duration::from_millis(2500);
+        let mut records = head_on();
+        records.push(Record {
+            time: exit,
+            plane_id: 2,
+            event: Event::Exit,
+        });
+
+        let alerts = replay(&records, &config, &[]).await;
+
+        // Both are told about each other again, as they would be live.
+        for (plane_id, intruder) in [(1, 2), (2, 1)] {
+            assert!(
+                alerts.iter().any(|a| a.time > exit
+                    && a.plane_id == plane_id
+                    && matches!(a.alert, Alert::Traffic(traffic) if traffic.intruder == intruder)),
+                "{} was not told about {}",
+                plane_id,
+                intruder
+            );
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let lines = |lines: &[&str]| lines.iter().map(|l| String::from(*l)).collect::<Vec<_>>();
+        let baseline = lines(&["1000 1 a", "1000 2 b", "2000 1 a", "2000 1 a"]);
+        let current = lines(&["1000 2 b", "1000 1 a", "2000 1 a", "3000 1 c"]);
+
+        let result = diff(&baseline, &current);
+
+        assert_eq!(result.removed, lines(&["2000 1 a"]));
+        assert_eq!(result.added, lines(&["3000 1 c"]));
+        assert_eq!(diff(&current, &current), AlertDiff::default());
+    }
+
+    #[test]
+    fn test_recorder_writes_parsable_telemetry() {
+        let path = std::env::temp_dir().join(format!("telemetry-{}.txt", std::process::id()));
+        let config = RecordingConfig {
+            file: path.to_string_lossy().into_owned(),
+        };
+        let mut recorder = Recorder::create(&config).unwrap().unwrap();
+        let report = Report::new(Vector3::new(1.0, 2.0, 3.0), Some(Duration::from_millis(7)));
+
+        recorder.record(
+            recorder.start + Duration::from_millis(250),
+            4,
+            Event::Report(report),
+        );
+        recorder.record(recorder.start + Duration::from_millis(500), 4, Event::Exit);
+        drop(recorder);
+        let text = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(text, "250 4 report 1 2 3 7\n500 4 exit\n");
+        assert!(
+            Recorder::create(&RecordingConfig::default())
+                .unwrap()
+                .is_none()
+        );
+    }
+}
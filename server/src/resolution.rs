@@ -0,0 +1,554 @@
+use crate::cluster::Cluster;
+use crate::detector::{Conflict, DetectorConfig, PlaneId, TrackState};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use utils::advisory::{Advisory, Sense};
+use utils::vector::Vector3;
+
+/// Parameters of the resolution engine.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResolutionConfig {
+    /// Altitude gained or lost by a manoeuvring aircraft.
+    pub vertical_offset: f32,
+    /// Altitude change per tick while manoeuvring.
+    pub vertical_rate: f32,
+    /// Lowest altitude an aircraft can be sent to.
+    pub floor: f32,
+    /// Highest altitude an aircraft can be sent to.
+    pub ceiling: f32,
+}
+
+/// Position of a track after some cycles, following an advisory if it has one.
+/// A manoeuvring track keeps its horizontal velocity and replaces its vertical one.
+pub fn predict(state: &TrackState, advisory: Option<&Advisory>, cycles: u32) -> Vector3 {
+    let mut position = state.position;
+    for _ in 0..cycles {
+        position.x += state.velocity.x;
+        position.y += state.velocity.y;
+        position.z = match advisory {
+            Some(advisory) => advisory.step(position.z),
+            None => position.z + state.velocity.z,
+        };
+    }
+    position
+}
+
+/// Check whether two tracks, each optionally manoeuvring, come within tolerance.
+/// The advisory changes the vertical speed from one cycle to the next, so each cycle is solved
+/// exactly as a straight segment, like the detector does, instead of only checking its ends.
+fn in_conflict(
+    a: &TrackState,
+    a_advisory: Option<&Advisory>,
+    b: &TrackState,
+    b_advisory: Option<&Advisory>,
+    detector: &DetectorConfig,
+) -> bool {
+    let separation = detector.tolerance + a.uncertainty + b.uncertainty;
+    (0..detector.max_cycles).any(|cycle| {
+        let a_start = predict(a, a_advisory, cycle);
+        let b_start = predict(b, b_advisory, cycle);
+        let a_vel = predict(a, a_advisory, cycle + 1).sub(a_start);
+        let b_vel = predict(b, b_advisory, cycle + 1).sub(b_start);
+        Vector3::closest_approach_within(a_start, a_vel, b_start, b_vel, 1.0).1 < separation
+    })
+}
+
+/// Sense for the first aircraft of a pair, from their relative geometry.
+/// The aircraft that will be higher at the closest approach climbs. Ties go to the one
+/// climbing faster, then to the lower id.
+fn preferred_sense(a: &TrackState, b: &TrackState, time: u32) -> Sense {
+    let a_cpa = predict(a, None, time);
+    let b_cpa = predict(b, None, time);
+    if a_cpa.z != b_cpa.z {
+        return if a_cpa.z > b_cpa.z {
+            Sense::CLIMB
+        } else {
+            Sense::DESCEND
+        };
+    }
+    if a.velocity.z != b.velocity.z {
+        return if a.velocity.z > b.velocity.z {
+            Sense::CLIMB
+        } else {
+            Sense::DESCEND
+        };
+    }
+    if a.id < b.id {
+        Sense::CLIMB
+    } else {
+        Sense::DESCEND
+    }
+}
+
+/// Advisory moving a track by the configured offset in the given sense,
+/// without leaving the floor and ceiling.
+fn advisory_for(state: &TrackState, sense: Sense, config: &ResolutionConfig) -> Advisory {
+    let target_altitude = match sense {
+        Sense::CLIMB => (state.position.z + config.vertical_offset).min(config.ceiling),
+        Sense::DESCEND => (state.position.z - config.vertical_offset).max(config.floor),
+    };
+    Advisory::new(sense, target_altitude, config.vertical_rate)
+}
+
+/// Pick complementary advisories for the two aircraft of a conflict.
+/// The geometric preference is tried first, then the reversed senses. A candidate is
+/// rejected if the pair stays in conflict or if either manoeuvre runs into a third aircraft.
+/// If both candidates fail the one with the fewest conflicts is returned.
+pub fn resolve(
+    conflict: &Conflict,
+    states: &[TrackState],
+    detector: &DetectorConfig,
+    config: &ResolutionConfig,
+) -> Option<[(PlaneId, Advisory); 2]> {
+    let a = states.iter().find(|s| s.id == conflict.a)?;
+    let b = states.iter().find(|s| s.id == conflict.b)?;
+
+    let preferred = preferred_sense(a, b, conflict.time);
+    let mut best: Option<([(PlaneId, Advisory); 2], usize)> = None;
+    for a_sense in [preferred, preferred.reverse()] {
+        let a_advisory = advisory_for(a, a_sense, config);
+        let b_advisory = advisory_for(b, a_sense.reverse(), config);
+
+        let mut remaining = 0;
+        if in_conflict(a, Some(&a_advisory), b, Some(&b_advisory), detector) {
+            remaining += 1;
+        }
+        for third in states.iter().filter(|s| s.id != a.id && s.id != b.id) {
+            if in_conflict(a, Some(&a_advisory), third, None, detector) {
+                remaining += 1;
+            }
+            if in_conflict(b, Some(&b_advisory), third, None, detector) {
+                remaining += 1;
+            }
+        }
+
+        let candidate = [(a.id, a_advisory), (b.id, b_advisory)];
+        if remaining == 0 {
+            return Some(candidate);
+        }
+        if best.is_none_or(|(_, fewest)| remaining < fewest) {
+            best = Some((candidate, remaining));
+        }
+    }
+
+    best.map(|(candidate, remaining)| {
+        tracing::warn!(
+            "No clean resolution between {} and {}, {} conflicts remain",
+            conflict.a,
+            conflict.b,
+            remaining
+        );
+        candidate
+    })
+}
+
+/// Altitude order of two tracks at the closest approach, lowest first.
+/// Ties go the same way as for pairs: the one climbing faster, then the lower id, is higher.
+fn altitude_order(a: &TrackState, b: &TrackState, time: u32) -> Ordering {
+    let a_cpa = predict(a, None, time);
+    let b_cpa = predict(b, None, time);
+    a_cpa
+        .z
+        .total_cmp(&b_cpa.z)
+        .then(a.velocity.z.total_cmp(&b.velocity.z))
+        .then(b.id.cmp(&a.id))
+}
+
+/// Count the conflicts left when each member follows its advisory and everyone else flies on.
+fn remaining_conflicts(
+    advisories: &[(&TrackState, Advisory)],
+    states: &[TrackState],
+    detector: &DetectorConfig,
+) -> usize {
+    let mut remaining = 0;
+    for (i, (a, a_advisory)) in advisories.iter().enumerate() {
+        for (b, b_advisory) in &advisories[i + 1..] {
+            if in_conflict(a, Some(a_advisory), b, Some(b_advisory), detector) {
+                remaining += 1;
+            }
+        }
+        for third in states
+            .iter()
+            .filter(|s| advisories.iter().all(|(member, _)| member.id != s.id))
+        {
+            if in_conflict(a, Some(a_advisory), third, None, detector) {
+                remaining += 1;
+            }
+        }
+    }
+    remaining
+}
+
+/// Stack the members of a cluster vertically, `vertical_offset` apart, keeping their order at the
+/// closest approach so that nobody crosses another member's altitude.
+/// The stack is centred on the mean altitude plus `shift`, then moved to fit between the floor
+/// and ceiling. Rates are scaled so that every member levels off at the same time, which keeps
+/// members that start at the same altitude from moving in lockstep.
+fn stack(
+    members: &[&TrackState],
+    time: u32,
+    shift: f32,
+    config: &ResolutionConfig,
+) -> Vec<(PlaneId, Advisory)> {
+    let mut ordered = members.to_vec();
+    ordered.sort_by(|a, b| altitude_order(a, b, time));
+
+    let count = ordered.len() as f32;
+    let mean = ordered.iter().map(|s| s.position.z).sum::<f32>() / count;
+    let mut base = mean - (count - 1.0) / 2.0 * config.vertical_offset + shift;
+    let top = base + (count - 1.0) * config.vertical_offset;
+    if base < config.floor {
+        base = config.floor;
+    } else if top > config.ceiling {
+        base -= top - config.ceiling;
+    }
+
+    let targets: Vec<f32> = (0..ordered.len())
+        .map(|i| {
+            (base + i as f32 * config.vertical_offset)
+                .max(config.floor)
+                .min(config.ceiling)
+        })
+        .collect();
+    let furthest = ordered
+        .iter()
+        .zip(&targets)
+        .map(|(s, target)| (target - s.position.z).abs())
+        .fold(0.0, f32::max);
+
+    ordered
+        .iter()
+        .zip(targets)
+        .map(|(s, target)| {
+            let sense = if target >= s.position.z {
+                Sense::CLIMB
+            } else {
+                Sense::DESCEND
+            };
+            let distance = (target - s.position.z).abs();
+            let rate = if distance > 0.0 {
+                config.vertical_rate * distance / furthest
+            } else {
+                config.vertical_rate
+            };
+            (s.id, Advisory::new(sense, target, rate))
+        })
+        .collect()
+}
+
+/// Pick one consistent set of advisories for every member of a cluster.
+/// Pairs are resolved with `resolve`. Larger clusters are stacked vertically, centred first and
+/// then shifted up and down by half an offset. A candidate is rejected if any members stay in
+/// conflict or if a manoeuvre runs into an aircraft outside the cluster. If every candidate
+/// fails the one with the fewest conflicts is returned.
+pub fn resolve_cluster(
+    cluster: &Cluster,
+    states: &[TrackState],
+    detector: &DetectorConfig,
+    config: &ResolutionConfig,
+) -> Option<Vec<(PlaneId, Advisory)>> {
+    if let [conflict] = cluster.conflicts.as_slice()
+        && cluster.members.len() == 2
+    {
+        return resolve(conflict, states, detector, config).map(|pair| pair.to_vec());
+    }
+
+    let members: Vec<&TrackState> = cluster
+        .members
+        .iter()
+        .map(|id| states.iter().find(|s| s.id == *id))
+        .collect::<Option<_>>()?;
+
+    let mut best: Option<(Vec<(PlaneId, Advisory)>, usize)> = None;
+    for shift in [0.0, 0.5, -0.5] {
+        let candidate = stack(
+            &members,
+            cluster.time(),
+            shift * config.vertical_offset,
+            config,
+        );
+        let advisories: Vec<(&TrackState, Advisory)> = members
+            .iter()
+            .filter_map(|member| {
+                candidate
+                    .iter()
+                    .find(|(id, _)| *id == member.id)
+                    .map(|(_, advisory)| (*member, *advisory))
+            })
+            .collect();
+
+        let remaining = remaining_conflicts(&advisories, states, detector);
+        if remaining == 0 {
+            return Some(candidate);
+        }
+        if best.as_ref().is_none_or(|(_, fewest)| remaining < *fewest) {
+            best = Some((candidate, remaining));
+        }
+    }
+
+    best.map(|(candidate, remaining)| {
+        tracing::warn!(
+            "No clean resolution for cluster {:?}, {} conflicts remain",
+            cluster.members,
+            remaining
+        );
+        candidate
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DETECTOR: DetectorConfig = DetectorConfig {
+        max_cycles: 3,
+        tolerance: 2.0,
+    };
+    const CONFIG: ResolutionConfig = ResolutionConfig {
+        vertical_offset: 1000.0,
+        vertical_rate: 500.0,
+        floor: -5000.0,
+        ceiling: 60000.0,
+    };
+
+    fn state(id: PlaneId, position: Vector3, velocity: Vector3) -> TrackState {
+        TrackState {
+            id,
+            position,
+            velocity,
+            uncertainty: 0.0,
+        }
+    }
+
+    fn conflict(a: PlaneId, b: PlaneId) -> Conflict {
+        Conflict {
+            a,
+            b,
+            cpa: 0.0,
+            time: 2,
+        }
+    }
+
+    #[test]
+    fn test_resolve_higher_aircraft_climbs() {
+        let states = vec![
+            state(
+                1,
+                Vector3::new(0.0, 0.0, 100.0),
+                Vector3::new(1.0, 0.0, 0.0),
+            ),
+            state(
+                2,
+                Vector3::new(4.0, 0.0, 101.0),
+                Vector3::new(-1.0, 0.0, 0.0),
+            ),
+        ];
+
+        let [(a, a_adv), (b, b_adv)] =
+            resolve(&conflict(1, 2), &states, &DETECTOR, &CONFIG).expect("Expected a resolution");
+
+        assert_eq!(
+            (a, a_adv.sense, a_adv.target_altitude),
+            (1, Sense::DESCEND, -900.0)
+        );
+        assert_eq!(
+            (b, b_adv.sense, b_adv.target_altitude),
+            (2, Sense::CLIMB, 1101.0)
+        );
+        assert_eq!(a_adv.vertical_rate, 500.0);
+    }
+
+    #[test]
+    fn test_resolve_avoids_third_aircraft() {
+        // Plane 1 would climb into plane 3, which flies towards it above the encounter.
+        let states = vec![
+            state(
+                1,
+                Vector3::new(0.0, 0.0, 101.0),
+                Vector3::new(3.0, 0.0, 0.0),
+            ),
+            state(
+                2,
+                Vector3::new(6.0, -6.0, 100.0),
+                Vector3::new(0.0, 3.0, 0.0),
+            ),
+            state(
+                3,
+                Vector3::new(12.0, 0.0, 1101.0),
+                Vector3::new(-1.0, 0.0, 0.0),
+            ),
+        ];
+
+        let [(a, a_adv), (b, b_adv)] =
+            resolve(&conflict(1, 2), &states, &DETECTOR, &CONFIG).expect("Expected a resolution");
+
+        assert_eq!((a, a_adv.sense), (1, Sense::DESCEND));
+        assert_eq!((b, b_adv.sense), (2, Sense::CLIMB));
+    }
+
+    #[test]
+    fn test_in_conflict_between_cycles() {
+        // Fast aircraft passing each other three quarters into the first cycle.
+        let a = state(1, Vector3::new(0.0, 0.0, 0.0), Vector3::new(10.0, 0.0, 0.0));
+        let b = state(
+            2,
+            Vector3::new(15.0, 0.0, 0.0),
+            Vector3::new(-10.0, 0.0, 0.0),
+        );
+        assert!(in_conflict(&a, None, &b, None, &DETECTOR));
+
+        // Climbing out of the way in time follows the advisory, not the current vertical speed.
+        let climb = Advisory::new(Sense::CLIMB, 10.0, 10.0);
+        assert!(!in_conflict(&a, Some(&climb), &b, None, &DETECTOR));
+        let slow = Advisory::new(Sense::CLIMB, 10.0, 1.0);
+        assert!(in_conflict(&a, Some(&slow), &b, None, &DETECTOR));
+    }
+
+    #[test]
+    fn test_resolve_respects_floor() {
+        let states = vec![
+            state(1, Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0)),
+            state(2, Vector3::new(4.0, 0.0, 0.0), Vector3::new(-1.0, 0.0, 0.0)),
+        ];
+        let config = ResolutionConfig {
+            floor: 0.0,
+            ..CONFIG
+        };
+
+        let [(_, a_adv), (_, b_adv)] =
+            resolve(&conflict(1, 2), &states, &DETECTOR, &config).expect("Expected a resolution");
+
+        assert_eq!((a_adv.sense, a_adv.target_altitude), (Sense::CLIMB, 1000.0));
+        assert_eq!((b_adv.sense, b_adv.target_altitude), (Sense::DESCEND, 0.0));
+    }
+
+    #[test]
+    fn test_resolve_missing_track() {
+        let states = vec![state(
+            1,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+        )];
+
+        assert_eq!(resolve(&conflict(1, 2), &states, &DETECTOR, &CONFIG), None);
+    }
+
+    /// Aircraft evenly spread on a circle, all flying to its centre and meeting at the same time.
+    /// Altitudes differ slightly so that the expected stacking order is known.
+    fn convergence(count: usize) -> Vec<TrackState> {
+        (0..count)
+            .map(|i| {
+                let angle = std::f32::consts::TAU * i as f32 / count as f32;
+                let (sin, cos) = angle.sin_cos();
+                state(
+                    i as PlaneId + 1,
+                    Vector3::new(6.0 * cos, 6.0 * sin, 10000.0 + i as f32),
+                    Vector3::new(-1.5 * cos, -1.5 * sin, 0.0),
+                )
+            })
+            .collect()
+    }
+
+    fn assert_cluster_resolved(count: usize) {
+        let detector = DetectorConfig {
+            max_cycles: 6,
+            tolerance: 2.0,
+        };
+        let states = convergence(count);
+        let clusters =
+            crate::cluster::clusters(&crate::detector::detect_conflicts(&states, &detector));
+        assert_eq!(clusters.len(), 1, "{}-way convergence", count);
+        assert_eq!(clusters[0].members.len(), count);
+
+        let advisories = resolve_cluster(&clusters[0], &states, &detector, &CONFIG)
+            .expect("Expected a resolution");
+
+        // One advisory per member, each to its own level, in the original altitude order.
+        assert_eq!(advisories.len(), count);
+        let mut ids: Vec<PlaneId> = advisories.iter().map(|(id, _)| *id).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), count);
+        for pair in advisories.windows(2) {
+            let (low, high) = (&pair[0], &pair[1]);
+            assert_eq!(
+                high.1.target_altitude - low.1.target_altitude,
+                CONFIG.vertical_offset
+            );
+            assert!(low.0 < high.0, "{:?} crosses {:?}", low, high);
+        }
+
+        // Following the advisories clears every conflict in the cluster.
+        let following: Vec<(&TrackState, Advisory)> = advisories
+            .iter()
+            .filter_map(|(id, advisory)| {
+                states.iter().find(|s| s.id == *id).map(|s| (s, *advisory))
+            })
+            .collect();
+        assert_eq!(remaining_conflicts(&following, &states, &detector), 0);
+    }
+
+    #[test]
+    fn test_resolve_cluster_three_way() {
+        assert_cluster_resolved(3);
+    }
+
+    #[test]
+    fn test_resolve_cluster_four_way() {
+        assert_cluster_resolved(4);
+    }
+
+    #[test]
+    fn test_resolve_cluster_six_way() {
+        assert_cluster_resolved(6);
+    }
+
+    #[test]
+    fn test_resolve_cluster_fits_under_ceiling() {
+        let detector = DetectorConfig {
+            max_cycles: 6,
+            tolerance: 2.0,
+        };
+        let states = convergence(3);
+        let cluster = Cluster {
+            members: vec![1, 2, 3],
+            conflicts: crate::detector::detect_conflicts(&states, &detector),
+        };
+        let config = ResolutionConfig {
+            ceiling: 10500.0,
+            ..CONFIG
+        };
+
+        let advisories =
+            resolve_cluster(&cluster, &states, &detector, &config).expect("Expected a resolution");
+
+        let targets: Vec<f32> = advisories.iter().map(|(_, a)| a.target_altitude).collect();
+        assert_eq!(targets, vec![8500.0, 9500.0, 10500.0]);
+    }
+
+    #[test]
+    fn test_resolve_cluster_pair_matches_resolve() {
+        let states = vec![
+            state(
+                1,
+                Vector3::new(0.0, 0.0, 100.0),
+                Vector3::new(1.0, 0.0, 0.0),
+            ),
+            state(
+                2,
+                Vector3::new(4.0, 0.0, 101.0),
+                Vector3::new(-1.0, 0.0, 0.0),
+            ),
+        ];
+        let cluster = Cluster {
+            members: vec![1, 2],
+            conflicts: vec![conflict(1, 2)],
+        };
+
+        assert_eq!(
+            resolve_cluster(&cluster, &states, &DETECTOR, &CONFIG),
+            resolve(&conflict(1, 2), &states, &DETECTOR, &CONFIG).map(|pair| pair.to_vec())
+        );
+    }
+}
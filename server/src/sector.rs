@@ -0,0 +1,338 @@
+use crate::config::ConfigError;
+use crate::detector::{PlaneId, TrackState};
+use crate::resolution::predict;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use utils::vector::Vector3;
+
+/// Sectors of the airspace and how far ahead their occupancy is predicted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SectorConfig {
+    /// Cycles over which the occupancy of each sector is predicted.
+    pub look_ahead: u32,
+    /// Sectors, checked in order. A position belongs to the first sector containing it.
+    pub sector: Vec<Sector>,
+}
+
+/// A rectangular area of the airspace with a limit on simultaneous aircraft.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sector {
+    pub name: String,
+    /// Lowest x and y of the sector.
+    pub min: [f32; 2],
+    /// Highest x and y of the sector.
+    pub max: [f32; 2],
+    /// Most aircraft the sector may hold at once.
+    pub capacity: usize,
+}
+
+/// Current, predicted and reserved number of aircraft in each sector, in config order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Occupancy {
+    /// Aircraft in each sector now.
+    pub current: Vec<usize>,
+    /// Most aircraft predicted in each sector at once within the look-ahead.
+    pub predicted: Vec<usize>,
+    /// Sector each released departure was cleared into, kept until the departure is tracked.
+    pub reservations: HashMap<PlaneId, usize>,
+}
+
+/// A sector predicted to go over capacity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Overload {
+    /// Index of the sector in the config.
+    pub sector: usize,
+    /// Cycles until the sector goes over capacity.
+    pub time: u32,
+    /// Aircraft predicted in the sector at that time.
+    pub count: usize,
+}
+
+impl Sector {
+    /// Check whether a position lies inside the sector, edges included.
+    pub fn contains(&self, position: Vector3) -> bool {
+        position.x >= self.min[0]
+            && position.x <= self.max[0]
+            && position.y >= self.min[1]
+            && position.y <= self.max[1]
+    }
+}
+
+impl SectorConfig {
+    /// Index of the sector a position belongs to, if any.
+    pub fn locate(&self, position: Vector3) -> Option<usize> {
+        self.sector.iter().position(|s| s.contains(position))
+    }
+
+    /// Check the look-ahead and every sector definition.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.look_ahead == 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "sectors.look_ahead must be above 0",
+            )));
+        }
+        let mut names = HashSet::new();
+        for sector in &self.sector {
+            let invalid = |message: &str| {
+                Err(ConfigError::Invalid(format!(
+                    "sector {}: {}",
+                    sector.name, message
+                )))
+            };
+            if sector.name.is_empty() {
+                return Err(ConfigError::Invalid(String::from(
+                    "sector names must not be empty",
+                )));
+            }
+            if !names.insert(sector.name.as_str()) {
+                return invalid("is defined twice");
+            }
+            let finite = sector.min.iter().chain(&sector.max).all(|v| v.is_finite());
+            if !finite || sector.min[0] >= sector.max[0] || sector.min[1] >= sector.max[1] {
+                return invalid("min must be below max on both axes");
+            }
+            if sector.capacity == 0 {
+                return invalid("capacity must be above 0");
+            }
+        }
+        Ok(())
+    }
+
+    /// Count the aircraft in each sector at a number of cycles from now.
+    fn count_at(&self, states: &[TrackState], cycle: u32) -> Vec<usize> {
+        let mut counts = vec![0; self.sector.len()];
+        for state in states {
+            if let Some(i) = self.locate(predict(state, None, cycle))
+                && let Some(count) = counts.get_mut(i)
+            {
+                *count += 1;
+            }
+        }
+        counts
+    }
+}
+
+/// Compute the current and predicted occupancy of every sector, with no reservations.
+pub fn occupancy(states: &[TrackState], config: &SectorConfig) -> Occupancy {
+    let current = config.count_at(states, 0);
+    let mut predicted = current.clone();
+    for cycle in 1..=config.look_ahead {
+        for (peak, count) in predicted.iter_mut().zip(config.count_at(states, cycle)) {
+            *peak = (*peak).max(count);
+        }
+    }
+    Occupancy {
+        current,
+        predicted,
+        reservations: HashMap::new(),
+    }
+}
+
+/// First time each sector is predicted to go over capacity within the look-ahead.
+pub fn predict_overloads(states: &[TrackState], config: &SectorConfig) -> Vec<Overload> {
+    let mut overloads: Vec<Overload> = Vec::new();
+    for cycle in 0..=config.look_ahead {
+        for (i, count) in config.count_at(states, cycle).into_iter().enumerate() {
+            let over = config.sector.get(i).is_some_and(|s| count > s.capacity);
+            if over && overloads.iter().all(|o| o.sector != i) {
+                overloads.push(Overload {
+                    sector: i,
+                    time: cycle,
+                    count,
+                });
+            }
+        }
+    }
+    overloads.sort_by_key(|o| o.sector);
+    overloads
+}
+
+impl Occupancy {
+    /// Recompute the occupancy from the tracks of this tick.
+    /// Reservations are kept until their departure is tracked, which takes a few reports, so a
+    /// released departure is always counted either as a reservation or as a track.
+    pub fn refresh(&mut self, states: &[TrackState], config: &SectorConfig) {
+        let fresh = occupancy(states, config);
+        self.current = fresh.current;
+        self.predicted = fresh.predicted;
+        self.reservations
+            .retain(|plane_id, _| states.iter().all(|s| s.id != *plane_id));
+    }
+
+    /// Departures released into a sector that are not tracked yet.
+    pub fn pending(&self, sector: usize) -> usize {
+        self.reservations.values().filter(|i| **i == sector).count()
+    }
+
+    /// Aircraft a sector is expected to hold at its busiest, released departures included.
+    pub fn expected(&self, sector: usize) -> usize {
+        let current = self.current.get(sector).copied().unwrap_or(0);
+        let predicted = self.predicted.get(sector).copied().unwrap_or(0);
+        current.max(predicted) + self.pending(sector)
+    }
+
+    /// Reserve room for the departure of a plane from a position.
+    /// Returns false if its sector is expected to be full, true if it may depart.
+    /// Departures outside every sector are always cleared.
+    pub fn reserve(&mut self, plane_id: PlaneId, position: Vector3, config: &SectorConfig) -> bool {
+        let Some(i) = config.locate(position) else {
+            return true;
+        };
+        if self.reservations.get(&plane_id) == Some(&i) {
+            return true;
+        }
+        let capacity = config.sector.get(i).map_or(0, |s| s.capacity);
+        if self.expected(i) >= capacity {
+            return false;
+        }
+        self.reservations.insert(plane_id, i);
+        true
+    }
+
+    /// Give up the reservation of a plane whose session ended before it was tracked.
+    pub fn cancel(&mut self, plane_id: PlaneId) {
+        self.reservations.remove(&plane_id);
+    }
+
+    /// One line per sector, for the status log.
+    pub fn describe(&self, config: &SectorConfig) -> Vec<String> {
+        config
+            .sector
+            .iter()
+            .enumerate()
+            .map(|(i, sector)| {
+                format!(
+                    "Sector {}: {} of {} aircraft, {} predicted, {} departing",
+                    sector.name,
+                    self.current.get(i).copied().unwrap_or(0),
+                    sector.capacity,
+                    self.predicted.get(i).copied().unwrap_or(0),
+                    self.pending(i)
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SectorConfig {
+        SectorConfig {
+            look_ahead: 4,
+            sector: vec![
+                Sector {
+                    name: String::from("West"),
+                    min: [0.0, 0.0],
+                    max: [10.0, 10.0],
+                    capacity: 2,
+                },
+                Sector {
+                    name: String::from("East"),
+                    min: [10.0, 0.0],
+                    max: [20.0, 10.0],
+                    capacity: 1,
+                },
+            ],
+        }
+    }
+
+    fn state(id: u8, x: f32, vx: f32) -> TrackState {
+        TrackState {
+            id,
+            position: Vector3::new(x, 5.0, 0.0),
+            velocity: Vector3::new(vx, 0.0, 0.0),
+            uncertainty: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_locate() {
+        let config = config();
+
+        assert_eq!(config.locate(Vector3::new(5.0, 5.0, 0.0)), Some(0));
+        assert_eq!(config.locate(Vector3::new(10.0, 5.0, 0.0)), Some(0));
+        assert_eq!(config.locate(Vector3::new(15.0, 5.0, 0.0)), Some(1));
+        assert_eq!(config.locate(Vector3::new(25.0, 5.0, 0.0)), None);
+    }
+
+    #[test]
+    fn test_occupancy() {
+        let states = [state(1, 2.0, 0.0), state(2, 8.0, 1.0), state(3, 30.0, 0.0)];
+
+        let occupancy = occupancy(&states, &config());
+
+        assert_eq!(occupancy.current, vec![2, 0]);
+        assert_eq!(occupancy.predicted, vec![2, 1]);
+        assert!(occupancy.reservations.is_empty());
+    }
+
+    #[test]
+    fn test_predict_overloads() {
+        // Plane 2 fills the west sector on the edge at cycle 1, then crowds the east one.
+        let states = [state(1, 15.0, 0.0), state(2, 8.0, 2.0), state(3, 2.0, 0.0)];
+
+        let overloads = predict_overloads(&states, &config());
+
+        assert_eq!(
+            overloads,
+            vec![Overload {
+                sector: 1,
+                time: 2,
+                count: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn test_reserve_holds_departures_into_full_sectors() {
+        let config = config();
+        let mut occupancy = occupancy(&[state(1, 2.0, 0.0)], &config);
+        let west = Vector3::new(5.0, 5.0, 0.0);
+
+        assert!(occupancy.reserve(4, west, &config));
+        assert!(occupancy.reserve(4, west, &config));
+        assert!(!occupancy.reserve(5, west, &config));
+        assert!(occupancy.reserve(6, Vector3::new(15.0, 5.0, 0.0), &config));
+        assert!(occupancy.reserve(7, Vector3::new(25.0, 5.0, 0.0), &config));
+        assert_eq!((occupancy.pending(0), occupancy.pending(1)), (1, 1));
+
+        occupancy.cancel(4);
+        assert!(occupancy.reserve(5, west, &config));
+    }
+
+    #[test]
+    fn test_refresh_keeps_reservations_until_tracked() {
+        let config = config();
+        let mut occupancy = Occupancy::default();
+        let east = Vector3::new(15.0, 5.0, 0.0);
+        assert!(occupancy.reserve(4, east, &config));
+
+        // Not tracked yet, the sector stays full.
+        occupancy.refresh(&[], &config);
+        assert!(!occupancy.reserve(5, east, &config));
+
+        occupancy.refresh(&[state(4, 15.0, 0.0)], &config);
+        assert!(occupancy.reservations.is_empty());
+        assert_eq!(occupancy.current, vec![0, 1]);
+        assert!(!occupancy.reserve(5, east, &config));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(config().validate().is_ok());
+
+        let mut duplicate = config();
+        duplicate.sector[1].name = String::from("West");
+        let mut empty = config();
+        empty.sector[0].max = [0.0, 10.0];
+        let mut closed = config();
+        closed.sector[0].capacity = 0;
+        for invalid in [duplicate, empty, closed] {
+            assert!(matches!(invalid.validate(), Err(ConfigError::Invalid(_))));
+        }
+    }
+}
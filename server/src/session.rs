@@ -0,0 +1,301 @@
+use crate::detector::PlaneId;
+use core::fmt;
+use utils::packet::{FlagState, PacketHeader};
+
+/// Where a client connection is in the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum SessionState {
+    /// Connected, waiting for the first packet naming the aircraft.
+    AWAITING_HELLO,
+    /// Aircraft known, waiting for its departure to be released.
+    IDENTIFIED,
+    /// Released and reporting its position.
+    AIRBORNE,
+    /// Uploading its flight log after landing.
+    TRANSFERRING,
+    /// Done with the client, the manager is yet to forget the aircraft.
+    CLOSING,
+    /// Finished.
+    CLOSED,
+}
+
+impl fmt::Display for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionState::AWAITING_HELLO => write!(f, "AWAITING_HELLO"),
+            SessionState::IDENTIFIED => write!(f, "IDENTIFIED"),
+            SessionState::AIRBORNE => write!(f, "AIRBORNE"),
+            SessionState::TRANSFERRING => write!(f, "TRANSFERRING"),
+            SessionState::CLOSING => write!(f, "CLOSING"),
+            SessionState::CLOSED => write!(f, "CLOSED"),
+        }
+    }
+}
+
+/// A message the session does not allow, which ends it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProtocolError {
+    /// The flag is not legal in the state of the session.
+    Unexpected {
+        state: SessionState,
+        flag: FlagState,
+    },
+    /// The packet names another aircraft than the one the session was opened for.
+    WrongPlane {
+        expected: PlaneId,
+        received: PlaneId,
+    },
+    /// An upload chunk does not follow the previous one.
+    OutOfOrder { expected: u8, received: u8 },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::Unexpected { state, flag } => {
+                write!(f, "Unexpected {} while {}", flag, state)
+            }
+            ProtocolError::WrongPlane { expected, received } => {
+                write!(
+                    f,
+                    "Packet from plane {} on the session of {}",
+                    received, expected
+                )
+            }
+            ProtocolError::OutOfOrder { expected, received } => write!(
+                f,
+                "Upload chunk with {} left, expected {}",
+                received, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Protocol state of one client connection.
+/// Every packet received is checked against the state before it is acted on:
+/// - AWAITING_HELLO: only the COORDINATE hello, which names the aircraft.
+/// - IDENTIFIED: FLIGHT_PLAN, until the server releases the departure.
+/// - AIRBORNE: COORDINATE, FLIGHT_PLAN, and EXIT to start the upload.
+/// - TRANSFERRING: EXIT chunks, each with one fewer left than the one before.
+/// - CLOSING and CLOSED: nothing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Session {
+    state: SessionState,
+    plane_id: Option<PlaneId>,
+    /// Upload chunks left after the last one received.
+    remaining: u8,
+}
+
+impl Session {
+    /// Session of a client that just connected.
+    pub fn new() -> Session {
+        Session {
+            state: SessionState::AWAITING_HELLO,
+            plane_id: None,
+            remaining: 0,
+        }
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    /// Aircraft the session is for, once identified.
+    pub fn plane_id(&self) -> Option<PlaneId> {
+        self.plane_id
+    }
+
+    /// Check a received packet is legal now, and move to the state it leads to.
+    /// Illegal packets leave the state as it is.
+    pub fn receive(&mut self, header: &PacketHeader) -> Result<SessionState, ProtocolError> {
+        if let Some(expected) = self.plane_id
+            && header.plane_id != expected
+        {
+            return Err(ProtocolError::WrongPlane {
+                expected,
+                received: header.plane_id,
+            });
+        }
+        let unexpected = ProtocolError::Unexpected {
+            state: self.state,
+            flag: header.flag,
+        };
+        self.state = match (self.state, header.flag) {
+            (SessionState::AWAITING_HELLO, FlagState::COORDINATE) => {
+                self.plane_id = Some(header.plane_id);
+                SessionState::IDENTIFIED
+            }
+            (SessionState::IDENTIFIED, FlagState::FLIGHT_PLAN) => SessionState::IDENTIFIED,
+            (SessionState::AIRBORNE, FlagState::COORDINATE | FlagState::FLIGHT_PLAN) => {
+                SessionState::AIRBORNE
+            }
+            (SessionState::AIRBORNE, FlagState::EXIT) => self.chunk(header.seq_len),
+            (SessionState::TRANSFERRING, FlagState::EXIT) => {
+                let expected = self.remaining - 1;
+                if header.seq_len != expected {
+                    return Err(ProtocolError::OutOfOrder {
+                        expected,
+                        received: header.seq_len,
+                    });
+                }
+                self.chunk(header.seq_len)
+            }
+            _ => return Err(unexpected),
+        };
+        Ok(self.state)
+    }
+
+    /// State after an upload chunk with a number of chunks left.
+    fn chunk(&mut self, remaining: u8) -> SessionState {
+        self.remaining = remaining;
+        if remaining == 0 {
+            SessionState::CLOSING
+        } else {
+            SessionState::TRANSFERRING
+        }
+    }
+
+    /// The departure was released.
+    pub fn release(&mut self) -> Result<(), ProtocolError> {
+        if self.state != SessionState::IDENTIFIED {
+            return Err(ProtocolError::Unexpected {
+                state: self.state,
+                flag: FlagState::RELEASE,
+            });
+        }
+        self.state = SessionState::AIRBORNE;
+        Ok(())
+    }
+
+    /// Stop serving the client, whatever it was doing.
+    pub fn close(&mut self) {
+        if self.state != SessionState::CLOSED {
+            self.state = SessionState::CLOSING;
+        }
+    }
+
+    /// The manager has forgotten the aircraft.
+    pub fn closed(&mut self) {
+        self.state = SessionState::CLOSED;
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(flag: FlagState, plane_id: PlaneId, seq_len: u8) -> PacketHeader {
+        PacketHeader {
+            flag,
+            plane_id,
+            body_size: 0,
+            seq_len,
+        }
+    }
+
+    #[test]
+    fn test_session_follows_a_flight() {
+        let mut session = Session::new();
+
+        let hello = session.receive(&header(FlagState::COORDINATE, 4, 0));
+        assert_eq!(hello, Ok(SessionState::IDENTIFIED));
+        assert_eq!(session.plane_id(), Some(4));
+        let plan = session.receive(&header(FlagState::FLIGHT_PLAN, 4, 0));
+        assert_eq!(plan, Ok(SessionState::IDENTIFIED));
+
+        assert_eq!(session.release(), Ok(()));
+        let report = session.receive(&header(FlagState::COORDINATE, 4, 0));
+        assert_eq!(report, Ok(SessionState::AIRBORNE));
+
+        for (left, state) in [
+            (2, SessionState::TRANSFERRING),
+            (1, SessionState::TRANSFERRING),
+            (0, SessionState::CLOSING),
+        ] {
+            assert_eq!(
+                session.receive(&header(FlagState::EXIT, 4, left)),
+                Ok(state)
+            );
+        }
+        session.closed();
+        assert_eq!(session.state(), SessionState::CLOSED);
+    }
+
+    #[test]
+    fn test_single_chunk_upload_closes() {
+        let mut session = Session::new();
+        session
+            .receive(&header(FlagState::COORDINATE, 4, 0))
+            .unwrap();
+        session.release().unwrap();
+
+        let exit = session.receive(&header(FlagState::EXIT, 4, 0));
+
+        assert_eq!(exit, Ok(SessionState::CLOSING));
+    }
+
+    #[test]
+    fn test_rejects_illegal_messages() {
+        let mut session = Session::new();
+        assert_eq!(
+            session.receive(&header(FlagState::EXIT, 4, 0)),
+            Err(ProtocolError::Unexpected {
+                state: SessionState::AWAITING_HELLO,
+                flag: FlagState::EXIT
+            })
+        );
+
+        session
+            .receive(&header(FlagState::COORDINATE, 4, 0))
+            .unwrap();
+        // Reports only count once the departure is released.
+        assert!(
+            session
+                .receive(&header(FlagState::COORDINATE, 4, 0))
+                .is_err()
+        );
+        session.release().unwrap();
+        assert!(session.release().is_err());
+        assert_eq!(
+            session.receive(&header(FlagState::COORDINATE, 9, 0)),
+            Err(ProtocolError::WrongPlane {
+                expected: 4,
+                received: 9
+            })
+        );
+        // Alerts only ever go to clients.
+        assert!(
+            session
+                .receive(&header(FlagState::COLLISION, 4, 0))
+                .is_err()
+        );
+        assert_eq!(session.state(), SessionState::AIRBORNE);
+
+        session.receive(&header(FlagState::EXIT, 4, 2)).unwrap();
+        assert!(
+            session
+                .receive(&header(FlagState::COORDINATE, 4, 0))
+                .is_err()
+        );
+        assert_eq!(
+            session.receive(&header(FlagState::EXIT, 4, 0)),
+            Err(ProtocolError::OutOfOrder {
+                expected: 1,
+                received: 0
+            })
+        );
+        assert_eq!(session.state(), SessionState::TRANSFERRING);
+
+        session.close();
+        assert!(session.receive(&header(FlagState::EXIT, 4, 1)).is_err());
+    }
+}
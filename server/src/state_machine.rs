@@ -1,37 +1,254 @@
-#[derive(Debug)]
+use core::fmt;
+use tokio::sync::watch;
+
+/// Lifecycle of the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum State {
+    /// Setting up, not serving clients yet.
+    STARTING = 0,
+    /// Serving clients and releasing departures.
     OPEN = 1,
-    CLOSED = 0,
+    /// Serving the aircraft already connected but falling behind, so departures are held.
+    DEGRADED = 2,
+    /// Shutting down: no new clients, connected ones are told and their uploads finished.
+    DRAINING = 3,
+    /// Stopped for good.
+    CLOSED = 4,
+}
+
+impl State {
+    /// Every state, in lifecycle order.
+    pub const ALL: [State; 5] = [
+        State::STARTING,
+        State::OPEN,
+        State::DEGRADED,
+        State::DRAINING,
+        State::CLOSED,
+    ];
+
+    /// Whether the lifecycle may go from this state to another.
+    /// A failed start closes straight away, a degraded server recovers once it keeps up again,
+    /// and everything else only moves forward.
+    pub fn can_become(self, to: State) -> bool {
+        matches!(
+            (self, to),
+            (State::STARTING, State::OPEN)
+                | (State::STARTING, State::CLOSED)
+                | (State::OPEN, State::DEGRADED)
+                | (State::OPEN, State::DRAINING)
+                | (State::DEGRADED, State::OPEN)
+                | (State::DEGRADED, State::DRAINING)
+                | (State::DRAINING, State::CLOSED)
+        )
+    }
+
+    /// Whether new clients are accepted.
+    pub fn accepts(self) -> bool {
+        matches!(self, State::OPEN | State::DEGRADED)
+    }
+
+    /// Whether departures are released.
+    pub fn releases(self) -> bool {
+        self == State::OPEN
+    }
+
+    /// Whether conflicts are detected and alerts sent.
+    pub fn detects(self) -> bool {
+        matches!(self, State::OPEN | State::DEGRADED | State::DRAINING)
+    }
+
+    /// Whether connected clients should be told to leave.
+    pub fn stopping(self) -> bool {
+        matches!(self, State::DRAINING | State::CLOSED)
+    }
 }
 
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            State::STARTING => write!(f, "STARTING"),
+            State::OPEN => write!(f, "OPEN"),
+            State::DEGRADED => write!(f, "DEGRADED"),
+            State::DRAINING => write!(f, "DRAINING"),
+            State::CLOSED => write!(f, "CLOSED"),
+        }
+    }
+}
+
+/// A transition the lifecycle does not allow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransitionError {
+    pub from: State,
+    pub to: State,
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Server cannot go from {} to {}", self.from, self.to)
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+/// Current state of the server, shared by every task that depends on it.
+/// Tasks subscribe to be woken on every transition.
 #[derive(Debug)]
 pub struct StateMachine {
-    state: State,
+    state: watch::Sender<State>,
 }
 
 impl StateMachine {
     /// Initialize state machine.
-    /// Default state is State::OPEN.
+    /// Default state is State::STARTING.
     pub fn new() -> StateMachine {
-        StateMachine { state: State::OPEN }
+        StateMachine {
+            state: watch::Sender::new(State::STARTING),
+        }
     }
 
-    /// Set state to State::OPEN.
-    pub fn open(&mut self) {
-        self.state = State::OPEN;
+    /// Move to another state, if the lifecycle allows it.
+    /// Returns the state left.
+    pub fn transition(&self, to: State) -> Result<State, TransitionError> {
+        let mut from = to;
+        let moved = self.state.send_if_modified(|state| {
+            from = *state;
+            if from.can_become(to) {
+                *state = to;
+                true
+            } else {
+                false
+            }
+        });
+        if !moved {
+            return Err(TransitionError { from, to });
+        }
+        tracing::info!("Server state {} -> {}", from, to);
+        Ok(from)
     }
 
-    /// Set state to State::CLOSED.
-    pub fn close(&mut self) {
-        self.state = State::CLOSED;
+    /// Start serving clients.
+    pub fn open(&self) -> Result<State, TransitionError> {
+        self.transition(State::OPEN)
     }
 
-    /// Get state from state machine
-    /// Does not copy or borrow state.
+    /// Hold departures until the server keeps up again.
+    pub fn degrade(&self) -> Result<State, TransitionError> {
+        self.transition(State::DEGRADED)
+    }
+
+    /// Stop accepting clients and tell the connected ones to leave.
+    pub fn drain(&self) -> Result<State, TransitionError> {
+        self.transition(State::DRAINING)
+    }
+
+    /// Stop for good.
+    pub fn close(&self) -> Result<State, TransitionError> {
+        self.transition(State::CLOSED)
+    }
+
+    /// Get state from state machine.
     pub fn get_state(&self) -> State {
-        match self.state {
-            State::OPEN => State::OPEN,
-            State::CLOSED => State::CLOSED,
+        *self.state.borrow()
+    }
+
+    /// Receiver that sees the current state and is woken on every transition.
+    pub fn subscribe(&self) -> watch::Receiver<State> {
+        self.state.subscribe()
+    }
+}
+
+impl Default for StateMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transition_rules() {
+        let allowed = [
+            (State::STARTING, State::OPEN),
+            (State::STARTING, State::CLOSED),
+            (State::OPEN, State::DEGRADED),
+            (State::OPEN, State::DRAINING),
+            (State::DEGRADED, State::OPEN),
+            (State::DEGRADED, State::DRAINING),
+            (State::DRAINING, State::CLOSED),
+        ];
+        for from in State::ALL {
+            for to in State::ALL {
+                assert_eq!(
+                    from.can_become(to),
+                    allowed.contains(&(from, to)),
+                    "{} -> {}",
+                    from,
+                    to
+                );
+            }
         }
     }
+
+    #[test]
+    fn test_lifecycle() {
+        let machine = StateMachine::new();
+        assert_eq!(machine.get_state(), State::STARTING);
+
+        assert_eq!(machine.open(), Ok(State::STARTING));
+        assert_eq!(machine.degrade(), Ok(State::OPEN));
+        assert_eq!(machine.open(), Ok(State::DEGRADED));
+        assert_eq!(machine.drain(), Ok(State::OPEN));
+        assert_eq!(machine.close(), Ok(State::DRAINING));
+        assert_eq!(machine.get_state(), State::CLOSED);
+    }
+
+    #[test]
+    fn test_refuses_illegal_transitions() {
+        let machine = StateMachine::new();
+        assert_eq!(
+            machine.degrade(),
+            Err(TransitionError {
+                from: State::STARTING,
+                to: State::DEGRADED
+            })
+        );
+
+        machine.open().unwrap();
+        machine.drain().unwrap();
+        assert_eq!(
+            machine.open(),
+            Err(TransitionError {
+                from: State::DRAINING,
+                to: State::OPEN
+            })
+        );
+        assert!(machine.drain().is_err());
+        assert_eq!(machine.get_state(), State::DRAINING);
+    }
+
+    #[test]
+    fn test_subscribers_see_transitions() {
+        let machine = StateMachine::new();
+        let mut state = machine.subscribe();
+        assert!(!state.has_changed().unwrap());
+
+        machine.open().unwrap();
+        assert!(state.has_changed().unwrap());
+        assert_eq!(*state.borrow_and_update(), State::OPEN);
+
+        // Refused transitions do not wake subscribers.
+        machine.close().unwrap_err();
+        assert!(!state.has_changed().unwrap());
+    }
+
+    #[test]
+    fn test_state_capabilities() {
+        assert!(State::OPEN.releases() && State::OPEN.accepts());
+        assert!(State::DEGRADED.accepts() && !State::DEGRADED.releases());
+        assert!(State::DRAINING.detects() && !State::DRAINING.accepts());
+        assert!(State::DRAINING.stopping() && State::CLOSED.stopping());
+        assert!(!State::STARTING.detects() && !State::CLOSED.detects());
+    }
 }
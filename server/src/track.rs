@@ -0,0 +1,194 @@
+use crate::history::{History, HistoryConfig, Sample};
+use crate::kalman::{KalmanFilter, TrackingConfig};
+use std::time::{Duration, Instant};
+
+/// Everything the server knows about one aircraft: its reports and its filtered state.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Track {
+    /// Bounded history of reported positions.
+    pub samples: History,
+    /// Filtered state and covariance, started on the first report.
+    pub filter: Option<KalmanFilter>,
+    /// Set when no report arrived for too long, cleared by the next report.
+    pub stale: bool,
+}
+
+/// Express a duration in ticks.
+pub fn ticks(duration: Duration, tick: Duration) -> f32 {
+    duration.as_secs_f32() / tick.as_secs_f32()
+}
+
+impl Track {
+    /// Create an empty track with the given history limits.
+    pub fn new(config: &HistoryConfig) -> Track {
+        Track {
+            samples: History::new(config),
+            filter: None,
+            stale: false,
+        }
+    }
+
+    /// Record a new report and fold it into the filter, advanced by the real time elapsed.
+    /// Reports sent at the same time as the last one are duplicates and are dropped.
+    /// Returns whether the report was recorded.
+    pub fn push(&mut self, sample: Sample, tick: Duration, config: &TrackingConfig) -> bool {
+        let elapsed = self.elapsed(&sample, config);
+        if elapsed.is_some_and(|e| e.is_zero()) {
+            return false;
+        }
+        match (self.filter.as_mut(), elapsed) {
+            (Some(filter), Some(elapsed)) => {
+                filter.step(sample.position, ticks(elapsed, tick), config)
+            }
+            _ => self.filter = Some(KalmanFilter::new(sample.position, config)),
+        }
+        self.samples.push(sample);
+        self.stale = false;
+        true
+    }
+
+    /// Most recent report.
+    pub fn last(&self) -> Option<&Sample> {
+        self.samples.last()
+    }
+
+    /// Time between the most recent report and a new one.
+    pub fn elapsed(&self, sample: &Sample, config: &TrackingConfig) -> Option<Duration> {
+        let tolerance = Duration::from_millis(config.clock_tolerance_ms);
+        self.last()
+            .map(|last| sample.elapsed_since(last, tolerance))
+    }
+
+    /// Time since the most recent report was received.
+    pub fn age(&self, now: Instant) -> Option<Duration> {
+        self.last()
+            .map(|last| now.saturating_duration_since(last.received))
+    }
+
+    /// Mark the track stale if its most recent report is too old.
+    /// Returns true only when the track has just become stale.
+    pub fn mark_stale(&mut self, now: Instant, config: &TrackingConfig) -> bool {
+        let max_age = Duration::from_millis(config.stale_after_ms);
+        let stale = self.age(now).is_some_and(|age| age > max_age);
+        let became_stale = stale && !self.stale;
+        self.stale = stale;
+        became_stale
+    }
+
+    /// Bytes used by this track.
+    pub fn memory_bytes(&self) -> usize {
+        std::mem::size_of::<Track>() - std::mem::size_of::<History>() + self.samples.memory_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::vector::Vector3;
+
+    const CONFIG: TrackingConfig = TrackingConfig {
+        horizontal_process_noise: 0.01,
+        vertical_process_noise: 0.01,
+        measurement_noise: 0.01,
+        stale_after_ms: 3000,
+        clock_tolerance_ms: 5000,
+    };
+    const TICK: Duration = Duration::from_millis(1000);
+
+    fn sample(x: f32, received: Instant, sent_ms: Option<u64>) -> Sample {
+        Sample::new(
+            Vector3::new(x, 0.0, 0.0),
+            received,
+            sent_ms.map(Duration::from_millis),
+        )
+    }
+
+    #[test]
+    fn test_push() {
+        let start = Instant::now();
+        let mut track = Track::default();
+
+        assert!(track.push(sample(0.0, start, None), TICK, &CONFIG));
+        assert!(track.push(sample(1.0, start + TICK, None), TICK, &CONFIG));
+
+        assert_eq!(track.samples.live().len(), 2);
+        assert_eq!(
+            track.last().map(|s| s.position),
+            Some(Vector3::new(1.0, 0.0, 0.0))
+        );
+        assert_eq!(track.filter.map(|f| f.updates), Some(2));
+    }
+
+    #[test]
+    fn test_push_uses_elapsed_time() {
+        // Reports two ticks apart, received in a burst after a network stall.
+        let start = Instant::now();
+        let mut track = Track::default();
+        for t in 0..10u64 {
+            let received = start + Duration::from_millis(2000 * t + if t == 9 { 3000 } else { 0 });
+            track.push(
+                sample(4.0 * t as f32, received, Some(2000 * t)),
+                TICK,
+                &CONFIG,
+            );
+        }
+
+        let velocity = track.filter.map(|f| f.velocity().x).unwrap_or(0.0);
+        assert!((velocity - 2.0).abs() < 0.05, "velocity {}", velocity);
+    }
+
+    #[test]
+    fn test_push_drops_duplicates() {
+        let start = Instant::now();
+        let mut track = Track::default();
+
+        assert!(track.push(sample(0.0, start, Some(1000)), TICK, &CONFIG));
+        assert!(!track.push(
+            sample(0.0, start + Duration::from_millis(5), Some(1000)),
+            TICK,
+            &CONFIG
+        ));
+
+        assert_eq!(track.samples.live().len(), 1);
+        assert_eq!(track.filter.map(|f| f.updates), Some(1));
+    }
+
+    #[test]
+    fn test_push_ignores_bogus_client_times() {
+        // A stamp far in the future must not blow up the filter.
+        let start = Instant::now();
+        let mut track = Track::default();
+        track.push(sample(0.0, start, Some(1000)), TICK, &CONFIG);
+        track.push(sample(1.0, start + TICK, Some(2000)), TICK, &CONFIG);
+
+        assert!(track.push(
+            sample(2.0, start + 2 * TICK, Some(u64::MAX / 2)),
+            TICK,
+            &CONFIG
+        ));
+
+        let filter = track.filter.expect("Expected a filter");
+        assert!(filter.position().x.is_finite() && filter.velocity().x.is_finite());
+        assert!(filter.position_uncertainty().is_finite());
+        assert!((filter.velocity().x - 1.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_mark_stale() {
+        let start = Instant::now();
+        let mut track = Track::default();
+        track.push(sample(0.0, start, None), TICK, &CONFIG);
+
+        assert!(!track.mark_stale(start + Duration::from_millis(3000), &CONFIG));
+        assert!(track.mark_stale(start + Duration::from_millis(3001), &CONFIG));
+        assert!(!track.mark_stale(start + Duration::from_millis(4000), &CONFIG));
+        assert!(track.stale);
+
+        track.push(
+            sample(1.0, start + Duration::from_millis(4000), None),
+            TICK,
+            &CONFIG,
+        );
+        assert!(!track.stale);
+    }
+}
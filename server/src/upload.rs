@@ -0,0 +1,123 @@
+use crate::detector::PlaneId;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A flight log being received from a client.
+/// Chunks are written to a `.part` file that only takes the final name once the last chunk is in,
+/// so an interrupted upload never leaves a truncated log behind. Dropping an unfinished upload,
+/// including when its task is cancelled, removes the partial file.
+#[derive(Debug)]
+pub struct Upload {
+    file: Option<File>,
+    part: PathBuf,
+    path: PathBuf,
+}
+
+impl Upload {
+    /// Final path of the flight log of a plane in a directory.
+    pub fn path(directory: &Path, plane_id: PlaneId) -> PathBuf {
+        directory.join(format!("plane_{}.txt", plane_id))
+    }
+
+    /// Start receiving the flight log of a plane into a directory.
+    pub fn create(directory: &Path, plane_id: PlaneId) -> Result<Upload, std::io::Error> {
+        let path = Upload::path(directory, plane_id);
+        let part = path.with_extension("txt.part");
+        let file = File::create(&part)?;
+        Ok(Upload {
+            file: Some(file),
+            part,
+            path,
+        })
+    }
+
+    /// Append a chunk of the log.
+    pub fn write(&mut self, chunk: &[u8]) -> Result<(), std::io::Error> {
+        match &mut self.file {
+            Some(file) => file.write_all(chunk),
+            None => Ok(()),
+        }
+    }
+
+    /// Move the complete log to its final name, replacing any earlier log of the plane.
+    /// The upload stays unfinished until the move succeeds, so a failure removes the partial file.
+    pub fn finish(mut self) -> Result<PathBuf, std::io::Error> {
+        if let Some(file) = &self.file {
+            file.sync_all()?;
+        }
+        std::fs::rename(&self.part, &self.path)?;
+        self.file = None;
+        Ok(self.path.clone())
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            match std::fs::remove_file(&self.part) {
+                Ok(()) => tracing::warn!("Discarded partial upload {}", self.part.display()),
+                Err(e) => tracing::error!("Unable to remove {}: {}", self.part.display(), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("upload-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn test_finish_renames_complete_upload() {
+        let directory = directory("finish");
+        let mut upload = Upload::create(&directory, 3).unwrap();
+        upload.write(b"first ").unwrap();
+        upload.write(b"second").unwrap();
+        assert!(directory.join("plane_3.txt.part").exists());
+
+        let path = upload.finish().unwrap();
+
+        assert_eq!(path, Upload::path(&directory, 3));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first second");
+        assert!(!directory.join("plane_3.txt.part").exists());
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_failed_finish_discards_partial_upload() {
+        // A directory in the way of the final name makes the rename fail.
+        let directory = directory("blocked");
+        std::fs::create_dir_all(Upload::path(&directory, 5)).unwrap();
+        let mut upload = Upload::create(&directory, 5).unwrap();
+        upload.write(b"whole log").unwrap();
+
+        assert!(upload.finish().is_err());
+
+        assert!(!directory.join("plane_5.txt.part").exists());
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_drop_discards_partial_upload() {
+        let directory = directory("drop");
+        std::fs::write(Upload::path(&directory, 4), "earlier flight").unwrap();
+
+        let mut upload = Upload::create(&directory, 4).unwrap();
+        upload.write(b"half a log").unwrap();
+        drop(upload);
+
+        assert!(!directory.join("plane_4.txt.part").exists());
+        assert_eq!(
+            std::fs::read_to_string(Upload::path(&directory, 4)).unwrap(),
+            "earlier flight"
+        );
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
@@ -0,0 +1,299 @@
+use crate::broad_phase::candidate_pairs;
+use crate::config::ConfigError;
+use crate::detector::{PlaneId, TrackState};
+use crate::resolution::predict;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utils::vector::Vector3;
+use utils::wake::WakeCategory;
+
+/// Wake turbulence separation behind each leader category, and the shape of the wake.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WakeConfig {
+    /// Distance a light, medium, heavy and super follower keeps behind a light leader.
+    /// Zero where only the detection tolerance applies.
+    pub light: [f32; 4],
+    /// Distance each follower category, lightest first, keeps behind a medium leader.
+    pub medium: [f32; 4],
+    /// Distance each follower category, lightest first, keeps behind a heavy leader.
+    pub heavy: [f32; 4],
+    /// Distance each follower category, lightest first, keeps behind a super leader.
+    #[serde(rename = "super")]
+    pub super_: [f32; 4],
+    /// Half width of the wake on either side of the leader's track.
+    pub lateral: f32,
+    /// Altitude below the leader the wake sinks to.
+    pub below: f32,
+    /// Altitude above the leader the wake is still felt at.
+    pub above: f32,
+}
+
+/// A follower predicted inside the wake of a leader, closer than their categories allow.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WakeViolation {
+    pub leader: PlaneId,
+    pub follower: PlaneId,
+    /// Distance the category pair requires behind the leader.
+    pub required: f32,
+    /// Distance behind the leader when the follower enters the wake.
+    pub distance: f32,
+    /// Cycles until the follower enters the wake.
+    pub time: u32,
+}
+
+impl WakeConfig {
+    /// Distance a follower keeps behind a leader, zero if their categories need no wake minimum.
+    pub fn minimum(&self, leader: WakeCategory, follower: WakeCategory) -> f32 {
+        let row = match leader {
+            WakeCategory::LIGHT => &self.light,
+            WakeCategory::MEDIUM => &self.medium,
+            WakeCategory::HEAVY => &self.heavy,
+            WakeCategory::SUPER => &self.super_,
+        };
+        row[follower as usize]
+    }
+
+    /// Largest minimum of any category pair.
+    pub fn reach(&self) -> f32 {
+        [self.light, self.medium, self.heavy, self.super_]
+            .iter()
+            .flatten()
+            .fold(0.0, |reach, minimum| minimum.max(reach))
+    }
+
+    /// Config with every wake minimum moved out by a margin, pairs without one left alone.
+    pub fn widened(&self, margin: f32) -> WakeConfig {
+        let widen = |row: [f32; 4]| row.map(|m| if m > 0.0 { m + margin } else { m });
+        WakeConfig {
+            light: widen(self.light),
+            medium: widen(self.medium),
+            heavy: widen(self.heavy),
+            super_: widen(self.super_),
+            ..*self
+        }
+    }
+
+    /// Check the minima and the shape of the wake.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let minima = [self.light, self.medium, self.heavy, self.super_];
+        if !minima.iter().flatten().all(|m| m.is_finite() && *m >= 0.0) {
+            return Err(ConfigError::Invalid(String::from(
+                "wake minima must not be negative",
+            )));
+        }
+        if !self.lateral.is_finite() || self.lateral <= 0.0 {
+            return Err(ConfigError::Invalid(String::from(
+                "wake.lateral must be a positive number",
+            )));
+        }
+        let vertical = [self.below, self.above];
+        if !vertical.iter().all(|v| v.is_finite() && *v >= 0.0) {
+            return Err(ConfigError::Invalid(String::from(
+                "wake.below and wake.above must not be negative",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Distance of a follower behind a leader flying a velocity, if it is within the wake up to
+    /// the given distance: behind the leader along its track, close to it sideways, and between
+    /// the altitudes the wake spans. A leader that is not moving horizontally has no wake.
+    fn behind(
+        &self,
+        leader: Vector3,
+        velocity: Vector3,
+        follower: Vector3,
+        required: f32,
+    ) -> Option<f32> {
+        let speed = (velocity.x * velocity.x + velocity.y * velocity.y).sqrt();
+        if speed <= 0.0 {
+            return None;
+        }
+        let (hx, hy) = (velocity.x / speed, velocity.y / speed);
+        let d = follower.sub(leader);
+        let distance = -(d.x * hx + d.y * hy);
+        let lateral = (d.x * hy - d.y * hx).abs();
+        let inside = distance > 0.0
+            && distance < required
+            && lateral <= self.lateral
+            && d.z <= self.above
+            && d.z >= -self.below;
+        inside.then_some(distance)
+    }
+}
+
+/// Detect every follower inside the wake of a leader, now or within the look-ahead.
+/// Aircraft without a declared category are medium. Only the horizontal swept boxes are compared
+/// by the broad phase, since the wake spans a wide band of altitudes.
+/// Each violation is reported once, ordered by follower then leader.
+pub fn detect_wake(
+    states: &[TrackState],
+    categories: &HashMap<PlaneId, WakeCategory>,
+    config: &WakeConfig,
+    max_cycles: u32,
+) -> Vec<WakeViolation> {
+    if config.reach() <= 0.0 {
+        return Vec::new();
+    }
+    let reach = (config.reach().powi(2) + config.lateral.powi(2)).sqrt();
+    let flat: Vec<TrackState> = states
+        .iter()
+        .map(|s| TrackState {
+            position: Vector3::new(s.position.x, s.position.y, 0.0),
+            velocity: Vector3::new(s.velocity.x, s.velocity.y, 0.0),
+            ..*s
+        })
+        .collect();
+    let category = |id: PlaneId| categories.get(&id).copied().unwrap_or(WakeCategory::MEDIUM);
+
+    let mut violations = Vec::new();
+    for (i, j) in candidate_pairs(&flat, max_cycles, reach) {
+        for (leader, follower) in [(&states[i], &states[j]), (&states[j], &states[i])] {
+            let required = config.minimum(category(leader.id), category(follower.id));
+            if required <= 0.0 {
+                continue;
+            }
+            let entry = (0..=max_cycles).find_map(|cycle| {
+                config
+                    .behind(
+                        predict(leader, None, cycle),
+                        leader.velocity,
+                        predict(follower, None, cycle),
+                        required,
+                    )
+                    .map(|distance| (cycle, distance))
+            });
+            if let Some((time, distance)) = entry {
+                violations.push(WakeViolation {
+                    leader: leader.id,
+                    follower: follower.id,
+                    required,
+                    distance,
+                    time,
+                });
+            }
+        }
+    }
+    violations.sort_by_key(|v| (v.follower, v.leader));
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> WakeConfig {
+        WakeConfig {
+            light: [0.0; 4],
+            medium: [3.5, 0.0, 0.0, 0.0],
+            heavy: [4.0, 3.5, 3.0, 0.0],
+            super_: [5.5, 4.5, 4.0, 0.0],
+            lateral: 1.0,
+            below: 1000.0,
+            above: 100.0,
+        }
+    }
+
+    fn state(id: PlaneId, x: f32, z: f32, vx: f32) -> TrackState {
+        TrackState {
+            id,
+            position: Vector3::new(x, 5.0, z),
+            velocity: Vector3::new(vx, 0.0, 0.0),
+            uncertainty: 0.0,
+        }
+    }
+
+    fn categories(list: &[(PlaneId, WakeCategory)]) -> HashMap<PlaneId, WakeCategory> {
+        list.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_minimum() {
+        let config = config();
+
+        assert_eq!(
+            config.minimum(WakeCategory::HEAVY, WakeCategory::LIGHT),
+            4.0
+        );
+        assert_eq!(
+            config.minimum(WakeCategory::SUPER, WakeCategory::HEAVY),
+            4.0
+        );
+        assert_eq!(
+            config.minimum(WakeCategory::LIGHT, WakeCategory::HEAVY),
+            0.0
+        );
+        assert_eq!(config.reach(), 5.5);
+
+        let widened = config.widened(1.0);
+        assert_eq!(widened.heavy, [5.0, 4.5, 4.0, 0.0]);
+        assert_eq!(widened.light, [0.0; 4]);
+    }
+
+    #[test]
+    fn test_detect_wake_behind_and_below() {
+        // A light aircraft 3 behind a heavy one, 500 below it, both flying east.
+        let states = [state(1, 10.0, 10000.0, 1.0), state(2, 7.0, 9500.0, 1.0)];
+        let categories = categories(&[(1, WakeCategory::HEAVY), (2, WakeCategory::LIGHT)]);
+
+        let violations = detect_wake(&states, &categories, &config(), 3);
+
+        assert_eq!(
+            violations,
+            vec![WakeViolation {
+                leader: 1,
+                follower: 2,
+                required: 4.0,
+                distance: 3.0,
+                time: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_detect_wake_ignores_safe_positions() {
+        let config = config();
+        let heavy_light = categories(&[(1, WakeCategory::HEAVY), (2, WakeCategory::LIGHT)]);
+        let ahead = [state(1, 10.0, 10000.0, 1.0), state(2, 13.0, 10000.0, 1.0)];
+        let above = [state(1, 10.0, 10000.0, 1.0), state(2, 7.0, 10500.0, 1.0)];
+        let far_below = [state(1, 10.0, 10000.0, 1.0), state(2, 7.0, 8000.0, 1.0)];
+        for states in [ahead, above, far_below] {
+            assert!(detect_wake(&states, &heavy_light, &config, 3).is_empty());
+        }
+
+        // Medium behind medium, the default for undeclared aircraft, needs no wake minimum.
+        let close = [state(1, 10.0, 10000.0, 1.0), state(2, 8.0, 10000.0, 1.0)];
+        assert!(detect_wake(&close, &HashMap::new(), &config, 3).is_empty());
+    }
+
+    #[test]
+    fn test_detect_wake_within_look_ahead() {
+        // A faster light aircraft closing on a super one from 8 behind.
+        let states = [state(1, 20.0, 10000.0, 1.0), state(2, 12.0, 10000.0, 2.0)];
+        let categories = categories(&[(1, WakeCategory::SUPER), (2, WakeCategory::LIGHT)]);
+
+        let violations = detect_wake(&states, &categories, &config(), 3);
+
+        assert_eq!(violations.len(), 1);
+        assert_eq!((violations[0].leader, violations[0].follower), (1, 2));
+        assert_eq!(violations[0].time, 3);
+        assert_eq!(violations[0].distance, 5.0);
+        assert!(detect_wake(&states, &categories, &config(), 2).is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(config().validate().is_ok());
+
+        let mut negative = config();
+        negative.heavy[0] = -1.0;
+        let mut narrow = config();
+        narrow.lateral = 0.0;
+        let mut sunk = config();
+        sunk.below = f32::NAN;
+        for invalid in [negative, narrow, sunk] {
+            assert!(matches!(invalid.validate(), Err(ConfigError::Invalid(_))));
+        }
+    }
+}
@@ -0,0 +1,537 @@
+use crate::config::ConfigError;
+use crate::detector::{DetectorConfig, PlaneId, TrackState};
+use crate::resolution::{ResolutionConfig, predict};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use utils::advisory::{Advisory, Sense};
+use utils::vector::Vector3;
+
+/// Where the airspace zones are read from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ZoneConfig {
+    /// TOML file listing the zones. Leave empty to run without zones.
+    pub file: String,
+}
+
+/// Why aircraft must stay out of a zone.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum ZoneKind {
+    /// Permanently restricted area, avoided over or under.
+    Restricted,
+    /// Ground or obstacles, only ever avoided by climbing.
+    Terrain,
+    /// Area closed for a limited time, avoided over or under while closed.
+    /// The closure runs from `start` until `end`, in seconds since the UNIX epoch.
+    TemporaryClosure { start: u64, end: u64 },
+}
+
+/// Horizontal outline of a zone.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Shape {
+    /// Polygon given by its corners in order, closed implicitly.
+    Polygon { points: Vec<[f32; 2]> },
+    /// Circle given by its centre and radius.
+    Cylinder { center: [f32; 2], radius: f32 },
+}
+
+/// A named volume aircraft must stay out of, between a floor and a ceiling.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Zone {
+    pub name: String,
+    pub kind: ZoneKind,
+    pub floor: f32,
+    pub ceiling: f32,
+    pub shape: Shape,
+}
+
+/// Contents of a zones file.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ZoneFile {
+    zone: Vec<Zone>,
+}
+
+/// A predicted entry of an aircraft into a zone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Incursion {
+    pub plane_id: PlaneId,
+    /// Name of the zone entered.
+    pub zone: String,
+    /// Cycles until the aircraft enters the zone, 0 if it is already inside.
+    pub time: u32,
+}
+
+impl fmt::Display for ZoneKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZoneKind::Restricted => write!(f, "restricted area"),
+            ZoneKind::Terrain => write!(f, "terrain"),
+            ZoneKind::TemporaryClosure { .. } => write!(f, "temporary closure"),
+        }
+    }
+}
+
+/// Distance from a point to the segment between a and b, in the horizontal plane.
+fn segment_distance(p: [f32; 2], a: [f32; 2], b: [f32; 2]) -> f32 {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let length = dx * dx + dy * dy;
+    let t = if length > 0.0 {
+        (((p[0] - a[0]) * dx + (p[1] - a[1]) * dy) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    ((p[0] - a[0] - t * dx).powi(2) + (p[1] - a[1] - t * dy).powi(2)).sqrt()
+}
+
+impl Shape {
+    /// Check whether a horizontal position lies inside the outline widened by a buffer.
+    pub fn contains(&self, p: [f32; 2], buffer: f32) -> bool {
+        match self {
+            Shape::Cylinder { center, radius } => {
+                ((p[0] - center[0]).powi(2) + (p[1] - center[1]).powi(2)).sqrt() <= radius + buffer
+            }
+            Shape::Polygon { points } => {
+                // Even-odd ray casting, then the distance to each edge for the buffer.
+                let edges = points.iter().zip(points.iter().cycle().skip(1));
+                let mut inside = false;
+                for (a, b) in edges.clone() {
+                    if (a[1] > p[1]) != (b[1] > p[1])
+                        && p[0] < a[0] + (p[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0])
+                    {
+                        inside = !inside;
+                    }
+                }
+                inside
+                    || edges
+                        .into_iter()
+                        .any(|(a, b)| segment_distance(p, *a, *b) <= buffer)
+            }
+        }
+    }
+}
+
+impl Zone {
+    /// Check whether a position lies inside the zone widened by a buffer on every side.
+    /// Altitudes exactly on the widened floor or ceiling are outside, so they can be used as
+    /// targets.
+    pub fn contains(&self, position: Vector3, buffer: f32) -> bool {
+        position.z > self.floor - buffer
+            && position.z < self.ceiling + buffer
+            && self.shape.contains([position.x, position.y], buffer)
+    }
+
+    /// Check whether the zone is in force at a time, in seconds since the UNIX epoch.
+    /// Only temporary closures come and go.
+    pub fn active(&self, time: u64) -> bool {
+        match self.kind {
+            ZoneKind::TemporaryClosure { start, end } => start <= time && time < end,
+            _ => true,
+        }
+    }
+
+    /// Check the zone definition.
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: &str| {
+            Err(ConfigError::Invalid(format!(
+                "zone {}: {}",
+                self.name, message
+            )))
+        };
+        if self.name.is_empty() {
+            return Err(ConfigError::Invalid(String::from(
+                "zone names must not be empty",
+            )));
+        }
+        if !self.floor.is_finite() || !self.ceiling.is_finite() || self.floor >= self.ceiling {
+            return invalid("floor must be below ceiling");
+        }
+        if let ZoneKind::TemporaryClosure { start, end } = self.kind
+            && start >= end
+        {
+            return invalid("a temporary closure must start before it ends");
+        }
+        match &self.shape {
+            Shape::Polygon { points } => {
+                if points.len() < 3 {
+                    return invalid("a polygon needs at least 3 points");
+                }
+                if points.iter().flatten().any(|v| !v.is_finite()) {
+                    return invalid("polygon points must be finite");
+                }
+            }
+            Shape::Cylinder { center, radius } => {
+                if !center.iter().all(|v| v.is_finite()) || !radius.is_finite() || *radius <= 0.0 {
+                    return invalid("a cylinder needs a finite center and a positive radius");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse and validate a zones document.
+pub fn parse(text: &str) -> Result<Vec<Zone>, ConfigError> {
+    let file: ZoneFile = match toml::from_str(text) {
+        Ok(file) => file,
+        Err(e) => return Err(ConfigError::Parse(e.to_string())),
+    };
+    let mut names = HashSet::new();
+    for zone in &file.zone {
+        zone.validate()?;
+        if !names.insert(zone.name.as_str()) {
+            return Err(ConfigError::Invalid(format!(
+                "zone {} is defined twice",
+                zone.name
+            )));
+        }
+    }
+    Ok(file.zone)
+}
+
+/// Read the zones file named in the config, if any.
+pub fn load(config: &ZoneConfig) -> Result<Vec<Zone>, ConfigError> {
+    if config.file.is_empty() {
+        return Ok(Vec::new());
+    }
+    match std::fs::read_to_string(&config.file) {
+        Ok(text) => parse(&text),
+        Err(e) => Err(ConfigError::Io(config.file.clone(), e)),
+    }
+}
+
+/// Buffer kept around zones for a track: the conflict tolerance widened by its uncertainty.
+fn buffer(state: &TrackState, detector: &DetectorConfig) -> f32 {
+    detector.tolerance + state.uncertainty
+}
+
+/// First zone in force at `now` a track enters within the look-ahead, following an advisory if
+/// it has one.
+fn first_incursion<'a>(
+    state: &TrackState,
+    advisory: Option<&Advisory>,
+    zones: &'a [Zone],
+    now: u64,
+    detector: &DetectorConfig,
+) -> Option<(&'a Zone, u32)> {
+    let buffer = buffer(state, detector);
+    (0..=detector.max_cycles).find_map(|cycle| {
+        let position = predict(state, advisory, cycle);
+        zones
+            .iter()
+            .find(|zone| zone.active(now) && zone.contains(position, buffer))
+            .map(|zone| (zone, cycle))
+    })
+}
+
+/// Check whether a track and its path over the look-ahead stay clear of a zone's outline at any
+/// altitude, so that it may leave the altitude it avoided the zone at.
+pub fn clear_of(state: &TrackState, zone: &Zone, detector: &DetectorConfig) -> bool {
+    let buffer = buffer(state, detector);
+    (0..=detector.max_cycles).all(|cycle| {
+        let position = predict(state, None, cycle);
+        !zone.shape.contains([position.x, position.y], buffer)
+    })
+}
+
+/// Predict the first zone incursion of every track within the look-ahead, ordered by id.
+/// Only zones in force at `now`, in seconds since the UNIX epoch, are considered.
+pub fn predict_incursions(
+    states: &[TrackState],
+    zones: &[Zone],
+    now: u64,
+    detector: &DetectorConfig,
+) -> Vec<Incursion> {
+    states
+        .iter()
+        .filter_map(|state| {
+            first_incursion(state, None, zones, now, detector).map(|(zone, time)| Incursion {
+                plane_id: state.id,
+                zone: zone.name.clone(),
+                time,
+            })
+        })
+        .collect()
+}
+
+/// Advisory taking a track over or under the zone it is about to enter.
+/// Terrain is only ever cleared by climbing. Of the manoeuvres that stay within the resolution
+/// floor and ceiling, the ones clearing every zone in force at `now` are preferred, then the
+/// smallest change.
+pub fn avoid(
+    incursion: &Incursion,
+    states: &[TrackState],
+    zones: &[Zone],
+    now: u64,
+    detector: &DetectorConfig,
+    config: &ResolutionConfig,
+) -> Option<Advisory> {
+    let state = states.iter().find(|s| s.id == incursion.plane_id)?;
+    let zone = zones
+        .iter()
+        .find(|z| z.name == incursion.zone && z.active(now))?;
+    let buffer = buffer(state, detector);
+
+    let mut candidates = vec![Advisory::new(
+        Sense::CLIMB,
+        zone.ceiling + buffer,
+        config.vertical_rate,
+    )];
+    if zone.kind != ZoneKind::Terrain {
+        candidates.push(Advisory::new(
+            Sense::DESCEND,
+            zone.floor - buffer,
+            config.vertical_rate,
+        ));
+    }
+    candidates.retain(|a| a.target_altitude >= config.floor && a.target_altitude <= config.ceiling);
+    candidates.sort_by(|a, b| {
+        let change = |adv: &Advisory| (adv.target_altitude - state.position.z).abs();
+        change(a).total_cmp(&change(b))
+    });
+
+    let clear = candidates
+        .iter()
+        .find(|a| first_incursion(state, Some(a), zones, now, detector).is_none());
+    match clear {
+        Some(advisory) => Some(*advisory),
+        None => {
+            tracing::warn!(
+                "No clean avoidance of {} for {}, {} cycles away",
+                incursion.zone,
+                incursion.plane_id,
+                incursion.time
+            );
+            candidates.first().copied()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DETECTOR: DetectorConfig = DetectorConfig {
+        max_cycles: 6,
+        tolerance: 2.0,
+    };
+    const CONFIG: ResolutionConfig = ResolutionConfig {
+        vertical_offset: 1000.0,
+        vertical_rate: 500.0,
+        floor: 0.0,
+        ceiling: 50000.0,
+    };
+
+    /// Test time, within the closure of the airshow.
+    const NOW: u64 = 1_800_000_000;
+
+    const ZONES: &str = r#"
+[[zone]]
+name = "R-1"
+kind = "restricted"
+floor = 0.0
+ceiling = 2000.0
+shape = { type = "cylinder", center = [20.0, 0.0], radius = 3.0 }
+
+[[zone]]
+name = "Ridge"
+kind = "terrain"
+floor = 0.0
+ceiling = 3000.0
+shape = { type = "polygon", points = [[0.0, 20.0], [10.0, 20.0], [10.0, 30.0], [0.0, 30.0]] }
+
+[[zone]]
+name = "Airshow"
+kind = { temporary_closure = { start = 1799996400, end = 1800003600 } }
+floor = 5000.0
+ceiling = 8000.0
+shape = { type = "cylinder", center = [40.0, 40.0], radius = 5.0 }
+"#;
+
+    fn state(id: PlaneId, position: Vector3, velocity: Vector3) -> TrackState {
+        TrackState {
+            id,
+            position,
+            velocity,
+            uncertainty: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let zones = parse(ZONES).expect("Expected valid zones");
+
+        assert_eq!(zones.len(), 3);
+        assert_eq!(zones[1].kind, ZoneKind::Terrain);
+        assert_eq!(
+            zones[2].kind,
+            ZoneKind::TemporaryClosure {
+                start: 1_799_996_400,
+                end: 1_800_003_600
+            }
+        );
+        assert_eq!(
+            zones[2].shape,
+            Shape::Cylinder {
+                center: [40.0, 40.0],
+                radius: 5.0
+            }
+        );
+    }
+
+    #[test]
+    fn test_shipped_zones_file_parses() {
+        assert_eq!(
+            parse(include_str!("../zones.toml")).map(|z| z.len()).ok(),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_zones() {
+        let cases = [
+            "[[zone]]\nname = \"A\"\nkind = \"restricted\"\nfloor = 10.0\nceiling = 0.0\nshape = { type = \"cylinder\", center = [0.0, 0.0], radius = 1.0 }\n",
+            "[[zone]]\nname = \"A\"\nkind = \"restricted\"\nfloor = 0.0\nceiling = 10.0\nshape = { type = \"cylinder\", center = [0.0, 0.0], radius = 0.0 }\n",
+            "[[zone]]\nname = \"A\"\nkind = \"terrain\"\nfloor = 0.0\nceiling = 10.0\nshape = { type = \"polygon\", points = [[0.0, 0.0], [1.0, 1.0]] }\n",
+            "[[zone]]\nname = \"A\"\nkind = { temporary_closure = { start = 20, end = 10 } }\nfloor = 0.0\nceiling = 10.0\nshape = { type = \"cylinder\", center = [0.0, 0.0], radius = 1.0 }\n",
+        ];
+        for case in cases {
+            assert!(
+                matches!(parse(case), Err(ConfigError::Invalid(_))),
+                "{} was accepted",
+                case
+            );
+        }
+
+        let duplicate = format!("{}{}", ZONES, ZONES);
+        assert!(matches!(parse(&duplicate), Err(ConfigError::Invalid(_))));
+        assert!(matches!(
+            parse("[[zone]]\nname = \"A\"\nkind = \"forbidden\"\n"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_contains() {
+        let zones = parse(ZONES).expect("Expected valid zones");
+
+        assert!(zones[0].contains(Vector3::new(22.0, 0.0, 1000.0), 0.0));
+        assert!(!zones[0].contains(Vector3::new(24.0, 0.0, 1000.0), 0.0));
+        assert!(zones[0].contains(Vector3::new(24.0, 0.0, 1000.0), 2.0));
+        assert!(!zones[0].contains(Vector3::new(20.0, 0.0, 2500.0), 2.0));
+
+        assert!(zones[1].contains(Vector3::new(5.0, 25.0, 100.0), 0.0));
+        assert!(!zones[1].contains(Vector3::new(11.0, 25.0, 100.0), 0.0));
+        assert!(zones[1].contains(Vector3::new(11.0, 25.0, 100.0), 2.0));
+        assert!(!zones[1].contains(Vector3::new(11.5, 31.5, 100.0), 2.0));
+    }
+
+    #[test]
+    fn test_predict_incursions() {
+        let zones = parse(ZONES).expect("Expected valid zones");
+        let states = vec![
+            state(
+                1,
+                Vector3::new(10.0, 0.0, 1000.0),
+                Vector3::new(2.0, 0.0, 0.0),
+            ),
+            state(
+                2,
+                Vector3::new(10.0, 0.0, 4000.0),
+                Vector3::new(2.0, 0.0, 0.0),
+            ),
+            state(
+                3,
+                Vector3::new(5.0, 25.0, 500.0),
+                Vector3::new(0.0, 0.0, 0.0),
+            ),
+        ];
+
+        let incursions = predict_incursions(&states, &zones, NOW, &DETECTOR);
+
+        assert_eq!(
+            incursions,
+            vec![
+                Incursion {
+                    plane_id: 1,
+                    zone: String::from("R-1"),
+                    time: 3
+                },
+                Incursion {
+                    plane_id: 3,
+                    zone: String::from("Ridge"),
+                    time: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_avoid_picks_smallest_clear_manoeuvre() {
+        let zones = parse(ZONES).expect("Expected valid zones");
+        let states = vec![state(
+            1,
+            Vector3::new(30.0, 40.0, 5500.0),
+            Vector3::new(1.0, 0.0, 0.0),
+        )];
+        let incursions = predict_incursions(&states, &zones, NOW, &DETECTOR);
+
+        let advisory = avoid(&incursions[0], &states, &zones, NOW, &DETECTOR, &CONFIG)
+            .expect("Expected an advisory");
+
+        assert_eq!(advisory.sense, Sense::DESCEND);
+        assert_eq!(advisory.target_altitude, 4998.0);
+    }
+
+    #[test]
+    fn test_avoid_climbs_over_terrain() {
+        let zones = parse(ZONES).expect("Expected valid zones");
+        let states = vec![state(
+            1,
+            Vector3::new(-6.0, 25.0, 200.0),
+            Vector3::new(2.0, 0.0, 0.0),
+        )];
+        let incursions = predict_incursions(&states, &zones, NOW, &DETECTOR);
+
+        let advisory = avoid(&incursions[0], &states, &zones, NOW, &DETECTOR, &CONFIG)
+            .expect("Expected an advisory");
+
+        assert_eq!(advisory.sense, Sense::CLIMB);
+        assert_eq!(advisory.target_altitude, 3002.0);
+    }
+
+    #[test]
+    fn test_closures_only_apply_while_closed() {
+        let zones = parse(ZONES).expect("Expected valid zones");
+        let states = vec![state(
+            1,
+            Vector3::new(30.0, 40.0, 5500.0),
+            Vector3::new(1.0, 0.0, 0.0),
+        )];
+        let incursion = Incursion {
+            plane_id: 1,
+            zone: String::from("Airshow"),
+            time: 3,
+        };
+
+        assert_eq!(
+            predict_incursions(&states, &zones, NOW, &DETECTOR),
+            vec![incursion.clone()]
+        );
+        for time in [1_799_996_399, 1_800_003_600] {
+            assert!(predict_incursions(&states, &zones, time, &DETECTOR).is_empty());
+            assert_eq!(
+                avoid(&incursion, &states, &zones, time, &DETECTOR, &CONFIG),
+                None
+            );
+        }
+        assert_eq!(
+            predict_incursions(&states, &zones, 1_799_996_400, &DETECTOR),
+            vec![incursion]
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! Check the conflict detector against a high resolution reference on random encounters.
+//! Run with `cargo test -p server --test detector_oracle -- --nocapture` to see the report.
+//! Fails if the detector misses a single loss of separation the reference finds.
+use server::detector::{DetectorConfig, PlaneId, TrackState, detect_conflicts};
+use std::collections::HashSet;
+use utils::vector::Vector3;
+
+const CONFIG: DetectorConfig = DetectorConfig {
+    max_cycles: 3,
+    tolerance: 2.0,
+};
+const SCENES: usize = 2_000;
+const AIRCRAFT: usize = 6;
+/// Reference samples per cycle of look-ahead.
+const RESOLUTION: u32 = 1_000;
+/// Highest speed per cycle, fast enough for aircraft to pass each other between two cycles.
+const MAX_SPEED: f32 = 6.0;
+
+/// Small linear congruential generator so runs are reproducible without extra crates.
+struct Lcg(u64);
+
+impl Lcg {
+    /// Uniform value in [0, 1).
+    fn next(&mut self) -> f32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniform value in [low, high).
+    fn range(&mut self, low: f32, high: f32) -> f32 {
+        low + self.next() * (high - low)
+    }
+
+    /// Uniformly distributed unit vector.
+    fn direction(&mut self) -> Vector3 {
+        let z = self.range(-1.0, 1.0);
+        let heading = self.range(0.0, std::f32::consts::TAU);
+        let r = (1.0 - z * z).sqrt();
+        Vector3::new(r * heading.cos(), r * heading.sin(), z)
+    }
+
+    fn velocity(&mut self) -> Vector3 {
+        let direction = self.direction();
+        let speed = self.range(0.0, MAX_SPEED);
+        Vector3::new(
+            direction.x * speed,
+            direction.y * speed,
+            direction.z * speed,
+        )
+    }
+}
+
+/// A scene of aircraft, each but the first set up to pass an earlier one at a random time,
+/// around the look-ahead window, and at a random distance, around the separation.
+/// Most pairs are near misses or narrow conflicts, where detectors go wrong.
+fn scene(rng: &mut Lcg) -> Vec<TrackState> {
+    let mut states: Vec<TrackState> = Vec::new();
+    for i in 0..AIRCRAFT {
+        let velocity = rng.velocity();
+        let uncertainty = rng.range(0.0, 0.6);
+        let position = match states.len() {
+            0 => Vector3::new(0.0, 0.0, 0.0),
+            n => {
+                let other = states[(rng.next() * n as f32) as usize % n];
+                let time = rng.range(-1.0, CONFIG.max_cycles as f32 + 1.0);
+                let separation = CONFIG.tolerance + other.uncertainty + uncertainty;
+                let miss = rng.range(0.0, 2.0 * separation);
+                let offset = rng.direction();
+                // Where the other aircraft is at that time, plus the miss distance,
+                // flown back to now at this aircraft's velocity.
+                Vector3::new(
+                    other.position.x + (other.velocity.x - velocity.x) * time + offset.x * miss,
+                    other.position.y + (other.velocity.y - velocity.y) * time + offset.y * miss,
+                    other.position.z + (other.velocity.z - velocity.z) * time + offset.z * miss,
+                )
+            }
+        };
+        states.push(TrackState {
+            id: i as PlaneId,
+            position,
+            velocity,
+            uncertainty,
+        });
+    }
+    states
+}
+
+/// Ground truth: whether two tracks come within the widened separation at any time from now to
+/// the end of the look-ahead, sampled finely in double precision.
+fn reference(a: &TrackState, b: &TrackState, config: &DetectorConfig) -> bool {
+    let separation = (config.tolerance + a.uncertainty + b.uncertainty) as f64;
+    let samples = config.max_cycles * RESOLUTION;
+    (0..=samples).any(|k| {
+        let t = k as f64 / RESOLUTION as f64;
+        let axis =
+            |p: f32, v: f32, q: f32, w: f32| (q as f64 + w as f64 * t) - (p as f64 + v as f64 * t);
+        let dx = axis(a.position.x, a.velocity.x, b.position.x, b.velocity.x);
+        let dy = axis(a.position.y, a.velocity.y, b.position.y, b.velocity.y);
+        let dz = axis(a.position.z, a.velocity.z, b.position.z, b.velocity.z);
+        (dx * dx + dy * dy + dz * dz).sqrt() < separation
+    })
+}
+
+#[test]
+fn test_detector_never_misses_a_loss_of_separation() {
+    let mut rng = Lcg(42000);
+    let mut pairs = 0;
+    let mut losses = 0;
+    let mut missed = Vec::new();
+    let mut false_alarms = 0;
+
+    for _ in 0..SCENES {
+        let states = scene(&mut rng);
+        let detected: HashSet<(PlaneId, PlaneId)> = detect_conflicts(&states, &CONFIG)
+            .iter()
+            .map(|c| (c.a, c.b))
+            .collect();
+
+        for (i, a) in states.iter().enumerate() {
+            for b in &states[i + 1..] {
+                pairs += 1;
+                let truth = reference(a, b, &CONFIG);
+                let alarm = detected.contains(&(a.id, b.id));
+                if truth {
+                    losses += 1;
+                }
+                match (truth, alarm) {
+                    (true, false) => missed.push((*a, *b)),
+                    (false, true) => false_alarms += 1,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    println!(
+        "{} pairs, {} losses of separation, {} missed ({:.3}%), {} false alarms ({:.3}%)",
+        pairs,
+        losses,
+        missed.len(),
+        100.0 * missed.len() as f32 / losses.max(1) as f32,
+        false_alarms,
+        100.0 * false_alarms as f32 / (pairs - losses).max(1) as f32
+    );
+    assert!(losses > pairs / 10, "too few conflicts to be meaningful");
+    assert!(missed.is_empty(), "missed detections: {:?}", missed);
+}
@@ -0,0 +1,224 @@
+use core::fmt;
+
+/// Vertical direction of a resolution advisory.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sense {
+    CLIMB = 0,
+    DESCEND = 1,
+}
+
+impl Sense {
+    /// init takes a u8 and gives back a Sense, or None if it doesn't match any of the values.
+    pub fn init(in_sense: u8) -> Option<Sense> {
+        match in_sense {
+            0 => Some(Sense::CLIMB),
+            1 => Some(Sense::DESCEND),
+            _ => None,
+        }
+    }
+
+    /// The opposite sense.
+    pub fn reverse(&self) -> Sense {
+        match self {
+            Sense::CLIMB => Sense::DESCEND,
+            Sense::DESCEND => Sense::CLIMB,
+        }
+    }
+}
+
+impl fmt::Display for Sense {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Sense::CLIMB => write!(f, "CLIMB"),
+            Sense::DESCEND => write!(f, "DESCEND"),
+        }
+    }
+}
+
+/// Body of a COLLISION packet: which way to move, where to level off, and how fast to get there.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Advisory {
+    pub sense: Sense,
+    pub target_altitude: f32,
+    /// Altitude change per tick, always positive.
+    pub vertical_rate: f32,
+}
+
+impl Advisory {
+    ///Create a new Advisory
+    pub fn new(sense: Sense, target_altitude: f32, vertical_rate: f32) -> Advisory {
+        Advisory {
+            sense,
+            target_altitude,
+            vertical_rate,
+        }
+    }
+
+    ///Altitude after one tick of following the advisory from the given altitude.
+    pub fn step(&self, altitude: f32) -> f32 {
+        match self.sense {
+            Sense::CLIMB => (altitude + self.vertical_rate).min(self.target_altitude),
+            Sense::DESCEND => (altitude - self.vertical_rate).max(self.target_altitude),
+        }
+    }
+
+    ///Size of a serialized Advisory.
+    pub fn size() -> usize {
+        9
+    }
+
+    ///Convert Advisory to a vector of u8.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.sense as u8];
+        bytes.extend_from_slice(&self.target_altitude.to_be_bytes());
+        bytes.extend_from_slice(&self.vertical_rate.to_be_bytes());
+        bytes
+    }
+
+    ///Create Advisory from a slice of u8.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Advisory> {
+        if bytes.len() < Advisory::size() {
+            return None;
+        }
+        let sense = Sense::init(bytes[0])?;
+        let target_altitude = f32::from_be_bytes(bytes[1..5].try_into().ok()?);
+        let vertical_rate = f32::from_be_bytes(bytes[5..9].try_into().ok()?);
+        if !target_altitude.is_finite() || !vertical_rate.is_finite() || vertical_rate <= 0.0 {
+            return None;
+        }
+        Some(Advisory::new(sense, target_altitude, vertical_rate))
+    }
+}
+
+impl fmt::Display for Advisory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} to {} at {} per tick",
+            self.sense, self.target_altitude, self.vertical_rate
+        )
+    }
+}
+
+/// Body of a TRAFFIC packet: early warning about an intruder, no manoeuvre required.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TrafficAdvisory {
+    pub intruder: u8,
+    /// Predicted distance to the intruder at the closest point of approach.
+    pub cpa: f32,
+    /// Ticks until the closest point of approach.
+    pub time: f32,
+}
+
+impl TrafficAdvisory {
+    ///Create a new TrafficAdvisory
+    pub fn new(intruder: u8, cpa: f32, time: f32) -> TrafficAdvisory {
+        TrafficAdvisory {
+            intruder,
+            cpa,
+            time,
+        }
+    }
+
+    ///Size of a serialized TrafficAdvisory.
+    pub fn size() -> usize {
+        9
+    }
+
+    ///Convert TrafficAdvisory to a vector of u8.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.intruder];
+        bytes.extend_from_slice(&self.cpa.to_be_bytes());
+        bytes.extend_from_slice(&self.time.to_be_bytes());
+        bytes
+    }
+
+    ///Create TrafficAdvisory from a slice of u8.
+    pub fn from_bytes(bytes: &[u8]) -> Option<TrafficAdvisory> {
+        if bytes.len() < TrafficAdvisory::size() {
+            return None;
+        }
+        let cpa = f32::from_be_bytes(bytes[1..5].try_into().ok()?);
+        let time = f32::from_be_bytes(bytes[5..9].try_into().ok()?);
+        if !cpa.is_finite() || !time.is_finite() {
+            return None;
+        }
+        Some(TrafficAdvisory::new(bytes[0], cpa, time))
+    }
+}
+
+impl fmt::Display for TrafficAdvisory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "traffic {} within {} in {} ticks",
+            self.intruder, self.cpa, self.time
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sense_init() {
+        assert_eq!(Sense::init(0), Some(Sense::CLIMB));
+        assert_eq!(Sense::init(1), Some(Sense::DESCEND));
+        assert_eq!(Sense::init(2), None);
+    }
+
+    #[test]
+    fn test_step() {
+        let climb = Advisory::new(Sense::CLIMB, 1000.0, 400.0);
+        let descend = Advisory::new(Sense::DESCEND, -1000.0, 400.0);
+
+        assert_eq!(climb.step(0.0), 400.0);
+        assert_eq!(climb.step(800.0), 1000.0);
+        assert_eq!(descend.step(0.0), -400.0);
+        assert_eq!(descend.step(-800.0), -1000.0);
+    }
+
+    #[test]
+    fn test_byte_conversion() {
+        let expected = Advisory::new(Sense::DESCEND, 29000.0, 500.0);
+
+        let bytes = expected.to_bytes();
+
+        assert_eq!(bytes.len(), Advisory::size());
+        assert_eq!(Advisory::from_bytes(&bytes), Some(expected));
+    }
+
+    #[test]
+    fn test_from_bytes_fail() {
+        let mut short = Advisory::new(Sense::CLIMB, 1000.0, 500.0).to_bytes();
+        short.pop();
+        let mut bad_sense = Advisory::new(Sense::CLIMB, 1000.0, 500.0).to_bytes();
+        bad_sense[0] = 7;
+        let bad_rate = Advisory::new(Sense::CLIMB, 1000.0, f32::NAN).to_bytes();
+
+        assert_eq!(Advisory::from_bytes(&short), None);
+        assert_eq!(Advisory::from_bytes(&bad_sense), None);
+        assert_eq!(Advisory::from_bytes(&bad_rate), None);
+    }
+
+    #[test]
+    fn test_traffic_byte_conversion() {
+        let expected = TrafficAdvisory::new(12, 1.5, 5.0);
+
+        let bytes = expected.to_bytes();
+
+        assert_eq!(bytes.len(), TrafficAdvisory::size());
+        assert_eq!(TrafficAdvisory::from_bytes(&bytes), Some(expected));
+    }
+
+    #[test]
+    fn test_traffic_from_bytes_fail() {
+        let mut short = TrafficAdvisory::new(12, 1.5, 5.0).to_bytes();
+        short.pop();
+        let bad_cpa = TrafficAdvisory::new(12, f32::INFINITY, 5.0).to_bytes();
+
+        assert_eq!(TrafficAdvisory::from_bytes(&short), None);
+        assert_eq!(TrafficAdvisory::from_bytes(&bad_cpa), None);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::vector::Vector3;
+use core::fmt;
+
+/// Body of a FLIGHT_PLAN packet: the route an aircraft intends to fly and its speed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlightPlan {
+    pub origin: Vector3,
+    pub destination: Vector3,
+    /// Points flown through in order between the origin and the destination.
+    pub waypoints: Vec<Vector3>,
+    /// Distance flown per tick.
+    pub speed: f32,
+}
+
+impl FlightPlan {
+    ///Create a new FlightPlan.
+    pub fn new(
+        origin: Vector3,
+        destination: Vector3,
+        waypoints: Vec<Vector3>,
+        speed: f32,
+    ) -> FlightPlan {
+        FlightPlan {
+            origin,
+            destination,
+            waypoints,
+            speed,
+        }
+    }
+
+    ///Most waypoints a serialized FlightPlan can carry.
+    pub fn max_waypoints() -> usize {
+        u8::MAX as usize
+    }
+
+    ///Every point of the route in order, origin and destination included.
+    pub fn route(&self) -> Vec<Vector3> {
+        let mut route = vec![self.origin];
+        route.extend_from_slice(&self.waypoints);
+        route.push(self.destination);
+        route
+    }
+
+    ///Convert FlightPlan to a vector of u8: speed, origin, destination, waypoint count, waypoints.
+    ///Waypoints past the maximum are dropped.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let count = self.waypoints.len().min(FlightPlan::max_waypoints());
+        let mut bytes = self.speed.to_be_bytes().to_vec();
+        bytes.extend(self.origin.to_bytes());
+        bytes.extend(self.destination.to_bytes());
+        bytes.push(count as u8);
+        for waypoint in &self.waypoints[..count] {
+            bytes.extend(waypoint.to_bytes());
+        }
+        bytes
+    }
+
+    ///Create FlightPlan from a slice of u8, rejecting non-finite values and a speed that is not
+    ///positive.
+    pub fn from_bytes(bytes: &[u8]) -> Option<FlightPlan> {
+        let speed = f32::from_be_bytes(bytes.get(0..4)?.try_into().ok()?);
+        let origin = Vector3::from_bytes(bytes.get(4..16)?)?;
+        let destination = Vector3::from_bytes(bytes.get(16..28)?)?;
+        let count = *bytes.get(28)? as usize;
+        let waypoints = (0..count)
+            .map(|i| Vector3::from_bytes(bytes.get(29 + 12 * i..41 + 12 * i)?))
+            .collect::<Option<Vec<Vector3>>>()?;
+
+        let plan = FlightPlan::new(origin, destination, waypoints, speed);
+        if !speed.is_finite() || speed <= 0.0 || !plan.route().iter().all(|p| p.is_finite()) {
+            return None;
+        }
+        Some(plan)
+    }
+}
+
+impl fmt::Display for FlightPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.origin)?;
+        for waypoint in &self.waypoints {
+            write!(f, " -> {}", waypoint)?;
+        }
+        write!(f, " -> {} at {}", self.destination, self.speed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan() -> FlightPlan {
+        FlightPlan::new(
+            Vector3::new(7.0, 29.0, 0.0),
+            Vector3::new(30.0, 16.0, 0.0),
+            vec![Vector3::new(10.0, 20.0, 0.0), Vector3::new(20.0, 18.0, 0.0)],
+            5.0,
+        )
+    }
+
+    #[test]
+    fn test_route() {
+        let route = plan().route();
+
+        assert_eq!(route.len(), 4);
+        assert_eq!(route[0], Vector3::new(7.0, 29.0, 0.0));
+        assert_eq!(route[3], Vector3::new(30.0, 16.0, 0.0));
+    }
+
+    #[test]
+    fn test_byte_conversion() {
+        let bytes = plan().to_bytes();
+
+        assert_eq!(bytes.len(), 29 + 2 * 12);
+        assert_eq!(FlightPlan::from_bytes(&bytes), Some(plan()));
+    }
+
+    #[test]
+    fn test_from_bytes_fail() {
+        let bytes = plan().to_bytes();
+        assert_eq!(FlightPlan::from_bytes(&bytes[..bytes.len() - 1]), None);
+
+        let mut stopped = plan();
+        stopped.speed = 0.0;
+        assert_eq!(FlightPlan::from_bytes(&stopped.to_bytes()), None);
+
+        let mut lost = plan();
+        lost.waypoints[0].x = f32::NAN;
+        assert_eq!(FlightPlan::from_bytes(&lost.to_bytes()), None);
+    }
+
+    #[test]
+    fn test_print() {
+        let plan = FlightPlan::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vec::new(),
+            2.0,
+        );
+
+        assert_eq!(
+            format!("{}", plan),
+            format!("{} -> {} at 2", plan.origin, plan.destination)
+        );
+    }
+}
@@ -1,2 +1,6 @@
+pub mod advisory;
+pub mod flight_plan;
 pub mod packet;
+pub mod report;
 pub mod vector;
+pub mod wake;
@@ -1,14 +1,24 @@
 use std::fmt;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
 //use tokio_stream::Stream;
 // This is an enum that designates what flags we have
 #[derive(Clone, Copy, PartialEq, Debug)]
+#[allow(non_camel_case_types)]
 pub enum FlagState {
     WARNING = 0,
     COLLISION = 1,
     COORDINATE = 2,
     EXIT = 3,
+    TRAFFIC = 4,
+    HOLD = 5,
+    RELEASE = 6,
+    FLIGHT_PLAN = 7,
+    CLEAR_OF_CONFLICT = 8,
+    WAKE = 9,
+    SERVER_SHUTDOWN = 10,
+    REJECTED = 11,
+    DISCONNECTED = 12,
 }
 
 impl FlagState {
@@ -20,9 +30,18 @@ impl FlagState {
             1 => FlagState::COLLISION,
             2 => FlagState::COORDINATE,
             3 => FlagState::EXIT,
+            4 => FlagState::TRAFFIC,
+            5 => FlagState::HOLD,
+            6 => FlagState::RELEASE,
+            7 => FlagState::FLIGHT_PLAN,
+            8 => FlagState::CLEAR_OF_CONFLICT,
+            9 => FlagState::WAKE,
+            10 => FlagState::SERVER_SHUTDOWN,
+            11 => FlagState::REJECTED,
+            12 => FlagState::DISCONNECTED,
             _ => {
                 eprintln!("Invalid integer called for FlagState: {}", in_state);
-                return FlagState::WARNING;
+                FlagState::WARNING
             }
         }
     }
@@ -41,12 +60,12 @@ impl PacketHeader {
     /// Returns an "empty" PacketHeader, which is defined as flag: 0 (WARNING), plane_id: 0,
     /// and body_size: 0.
     pub fn init() -> PacketHeader {
-        return PacketHeader {
+        PacketHeader {
             flag: FlagState::WARNING,
             plane_id: 0,
             body_size: 0,
             seq_len: 0,
-        };
+        }
     }
     /// Deseralize_packet_header() takes in a u8 slice and returns an unpacked PacketHeader. The
     /// function deseralizes in the same way the serialize_packet_header works.
@@ -91,10 +110,10 @@ impl PacketHeader {
 }
 
 pub fn get_packet_header_size() -> usize {
-    return 5;
+    5
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Packet {
     pub header: PacketHeader,
     pub body: Vec<u8>,
@@ -102,16 +121,16 @@ pub struct Packet {
 
 impl Packet {
     pub fn init() -> Packet {
-        return Packet {
+        Packet {
             header: PacketHeader::init(),
             body: Vec::new(),
-        };
+        }
     }
     pub fn seralize_packet_buf(&self) -> Vec<u8> {
         let mut seralized_bytes: Vec<u8> = Vec::new();
         seralized_bytes.extend(self.header.seralize_packet_header());
         seralized_bytes.extend_from_slice(&self.body);
-        return seralized_bytes;
+        seralized_bytes
     }
 }
 
@@ -119,13 +138,21 @@ impl Packet {
 impl fmt::Display for FlagState {
     // This trait requires `fmt` with this exact signature.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let ret: &str;
-        match self {
-            FlagState::COORDINATE => ret = "COORDINATE",
-            FlagState::EXIT => ret = "EXIT",
-            FlagState::WARNING => ret = "WARNING",
-            FlagState::COLLISION => ret = "COLLISION",
-        }
+        let ret: &str = match self {
+            FlagState::COORDINATE => "COORDINATE",
+            FlagState::EXIT => "EXIT",
+            FlagState::WARNING => "WARNING",
+            FlagState::COLLISION => "COLLISION",
+            FlagState::TRAFFIC => "TRAFFIC",
+            FlagState::HOLD => "HOLD",
+            FlagState::RELEASE => "RELEASE",
+            FlagState::FLIGHT_PLAN => "FLIGHT_PLAN",
+            FlagState::CLEAR_OF_CONFLICT => "CLEAR_OF_CONFLICT",
+            FlagState::WAKE => "WAKE",
+            FlagState::SERVER_SHUTDOWN => "SERVER_SHUTDOWN",
+            FlagState::REJECTED => "REJECTED",
+            FlagState::DISCONNECTED => "DISCONNECTED",
+        };
         // Write strictly the first element into the supplied output
         // stream: `f`. Returns `fmt::Result` which indicates whether the
         // operation succeeded or failed. Note that `write!` uses syntax which
@@ -158,13 +185,20 @@ impl fmt::Display for Packet {
 }
 
 /// To use the vector return, just use &[variablename]
-pub async fn serialize_packet(pkt: Packet, stream: &mut TcpStream) -> Result<(), std::io::Error> {
+/// Writes to a TcpStream, or to the write half of one.
+pub async fn serialize_packet(
+    pkt: Packet,
+    stream: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), std::io::Error> {
     stream.write_all(&pkt.seralize_packet_buf()).await
 }
 
-/// Takes in the TcpStream, reads values from it, and returns a Packet deseralized.
-/// The logic works, except for the TcpStream which is still untested.
-pub async fn deserialize_packet(stream: &mut TcpStream) -> Result<Packet, std::io::Error> {
+/// Takes in the TcpStream, or the read half of one, reads values from it, and returns a Packet
+/// deseralized.
+/// Not cancel safe: a read dropped halfway loses the start of the packet.
+pub async fn deserialize_packet(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<Packet, std::io::Error> {
     let mut rcv_buf_header: Vec<u8> = vec![0; get_packet_header_size()];
     let mut pkt: Packet = Packet::init();
     stream.read_exact(&mut rcv_buf_header).await?;
@@ -183,6 +217,23 @@ pub async fn deserialize_packet(stream: &mut TcpStream) -> Result<Packet, std::i
     Ok(pkt)
 }
 
+/// Reads packets from the read half of a TcpStream and sends each on, until the stream fails or
+/// nothing listens anymore. The last packet sent on is the error that stopped it.
+/// Spawned on a task of its own, it lets the other end wait on packets without ever dropping a
+/// read halfway.
+pub async fn forward_packets(
+    mut stream: impl AsyncRead + Unpin,
+    sender: mpsc::Sender<Result<Packet, std::io::Error>>,
+) {
+    loop {
+        let read = deserialize_packet(&mut stream).await;
+        let failed = read.is_err();
+        if sender.send(read).await.is_err() || failed {
+            break;
+        }
+    }
+}
+
 #[cfg(test)]
 #[allow(non_snake_case)]
 mod tests {
@@ -194,11 +245,29 @@ mod tests {
         let collision = FlagState::init(1);
         let coordinate = FlagState::init(2);
         let exit = FlagState::init(3);
-        let error = FlagState::init(4);
+        let traffic = FlagState::init(4);
+        let hold = FlagState::init(5);
+        let release = FlagState::init(6);
+        let flight_plan = FlagState::init(7);
+        let clear_of_conflict = FlagState::init(8);
+        let wake = FlagState::init(9);
+        let server_shutdown = FlagState::init(10);
+        let rejected = FlagState::init(11);
+        let disconnected = FlagState::init(12);
+        let error = FlagState::init(255);
         assert_eq!(warning, FlagState::WARNING);
         assert_eq!(collision, FlagState::COLLISION);
         assert_eq!(coordinate, FlagState::COORDINATE);
         assert_eq!(exit, FlagState::EXIT);
+        assert_eq!(traffic, FlagState::TRAFFIC);
+        assert_eq!(hold, FlagState::HOLD);
+        assert_eq!(release, FlagState::RELEASE);
+        assert_eq!(flight_plan, FlagState::FLIGHT_PLAN);
+        assert_eq!(clear_of_conflict, FlagState::CLEAR_OF_CONFLICT);
+        assert_eq!(wake, FlagState::WAKE);
+        assert_eq!(server_shutdown, FlagState::SERVER_SHUTDOWN);
+        assert_eq!(rejected, FlagState::REJECTED);
+        assert_eq!(disconnected, FlagState::DISCONNECTED);
         assert_eq!(error, FlagState::WARNING);
     }
 
@@ -332,6 +401,18 @@ mod tests {
         assert_eq!(format!("{}", FlagState::COLLISION), "COLLISION");
         assert_eq!(format!("{}", FlagState::COORDINATE), "COORDINATE");
         assert_eq!(format!("{}", FlagState::WARNING), "WARNING");
+        assert_eq!(format!("{}", FlagState::TRAFFIC), "TRAFFIC");
+        assert_eq!(format!("{}", FlagState::HOLD), "HOLD");
+        assert_eq!(format!("{}", FlagState::RELEASE), "RELEASE");
+        assert_eq!(format!("{}", FlagState::FLIGHT_PLAN), "FLIGHT_PLAN");
+        assert_eq!(
+            format!("{}", FlagState::CLEAR_OF_CONFLICT),
+            "CLEAR_OF_CONFLICT"
+        );
+        assert_eq!(format!("{}", FlagState::WAKE), "WAKE");
+        assert_eq!(format!("{}", FlagState::SERVER_SHUTDOWN), "SERVER_SHUTDOWN");
+        assert_eq!(format!("{}", FlagState::REJECTED), "REJECTED");
+        assert_eq!(format!("{}", FlagState::DISCONNECTED), "DISCONNECTED");
     }
 
     #[test]
@@ -354,6 +435,54 @@ mod tests {
         );
         assert_eq!(expected, actual)
     }
+
+    #[tokio::test]
+    async fn test_packet_stream_round_trip() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let expected = Packet {
+            header: PacketHeader {
+                flag: FlagState::COORDINATE,
+                plane_id: 3,
+                body_size: 4,
+                seq_len: 0,
+            },
+            body: vec![1, 2, 3, 4],
+        };
+
+        serialize_packet(expected.clone(), &mut client)
+            .await
+            .unwrap();
+        drop(client);
+
+        assert_eq!(deserialize_packet(&mut server).await.unwrap(), expected);
+        assert!(deserialize_packet(&mut server).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_forward_packets_until_stream_ends() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let (sender, mut packets) = mpsc::channel(4);
+        let forwarding = tokio::spawn(forward_packets(server, sender));
+        let pkt = Packet {
+            header: PacketHeader {
+                flag: FlagState::COLLISION,
+                plane_id: 2,
+                body_size: 1,
+                seq_len: 0,
+            },
+            body: vec![7],
+        };
+
+        serialize_packet(pkt.clone(), &mut client).await.unwrap();
+        serialize_packet(pkt.clone(), &mut client).await.unwrap();
+        drop(client);
+
+        assert_eq!(packets.recv().await.unwrap().unwrap(), pkt);
+        assert_eq!(packets.recv().await.unwrap().unwrap(), pkt);
+        assert!(packets.recv().await.unwrap().is_err());
+        assert!(packets.recv().await.is_none());
+        forwarding.await.unwrap();
+    }
 }
 
 //#[test]
@@ -0,0 +1,108 @@
+use crate::vector::{Bounds, DecodeError, Vector3};
+use core::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Body of a COORDINATE packet: a position, and the client time it was measured at.
+/// The time is optional so that clients which only send 12 bytes are still understood.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Report {
+    pub position: Vector3,
+    /// Client time since the UNIX epoch, with millisecond precision.
+    pub sent: Option<Duration>,
+}
+
+impl Report {
+    /// Create a new Report.
+    pub fn new(position: Vector3, sent: Option<Duration>) -> Report {
+        Report { position, sent }
+    }
+
+    /// Create a Report stamped with the current system time.
+    pub fn now(position: Vector3) -> Report {
+        Report::new(position, SystemTime::now().duration_since(UNIX_EPOCH).ok())
+    }
+
+    /// Number of bytes of a report carrying a client time.
+    pub fn size() -> usize {
+        20
+    }
+
+    /// Convert Report to a vector of u8, the position followed by the time in milliseconds.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.position.to_bytes();
+        if let Some(sent) = self.sent {
+            bytes.extend_from_slice(&(sent.as_millis() as u64).to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Create Report from a slice of u8, rejecting positions that are non-finite or outside bounds.
+    /// The client time is read when the slice is long enough to hold it.
+    pub fn from_bytes_checked(bytes: &[u8], bounds: &Bounds) -> Result<Report, DecodeError> {
+        let position = Vector3::from_bytes_checked(bytes, bounds)?;
+        let sent = bytes
+            .get(12..20)
+            .and_then(|b| b.try_into().ok())
+            .map(|b| Duration::from_millis(u64::from_be_bytes(b)));
+        Ok(Report { position, sent })
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.sent {
+            Some(sent) => write!(f, "{} at {}ms", self.position, sent.as_millis()),
+            None => write!(f, "{}", self.position),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds() -> Bounds {
+        Bounds::new(
+            Vector3::new(-10.0, -10.0, -10.0),
+            Vector3::new(10.0, 10.0, 10.0),
+        )
+    }
+
+    #[test]
+    fn test_byte_conversion() {
+        let report = Report::new(
+            Vector3::new(1.0, 2.0, 3.0),
+            Some(Duration::from_millis(1234567)),
+        );
+        let bytes = report.to_bytes();
+
+        assert_eq!(bytes.len(), Report::size());
+        assert_eq!(Report::from_bytes_checked(&bytes, &bounds()), Ok(report));
+    }
+
+    #[test]
+    fn test_from_bytes_without_time() {
+        let position = Vector3::new(1.0, 2.0, 3.0);
+        let bytes = position.to_bytes();
+
+        assert_eq!(
+            Report::from_bytes_checked(&bytes, &bounds()),
+            Ok(Report::new(position, None))
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_fail() {
+        let position = Vector3::new(20.0, 0.0, 0.0);
+        let bytes = Report::new(position, Some(Duration::from_millis(1))).to_bytes();
+
+        assert_eq!(
+            Report::from_bytes_checked(&bytes, &bounds()),
+            Err(DecodeError::OutOfBounds(position))
+        );
+        assert_eq!(
+            Report::from_bytes_checked(&bytes[..8], &bounds()),
+            Err(DecodeError::TooShort(8))
+        );
+    }
+}
@@ -1,5 +1,56 @@
 use core::fmt;
 
+/// Reasons a Vector3 can be rejected when decoding it from untrusted bytes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DecodeError {
+    /// Fewer than 12 bytes were supplied.
+    TooShort(usize),
+    /// At least one component is NaN or infinite.
+    NonFinite,
+    /// The vector is finite but lies outside the airspace bounds.
+    OutOfBounds(Vector3),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::TooShort(len) => {
+                write!(f, "Expected 12 bytes for a Vector3, received {}", len)
+            }
+            DecodeError::NonFinite => write!(f, "Vector3 contains a NaN or infinite component"),
+            DecodeError::OutOfBounds(v) => {
+                write!(f, "Vector3 {} is outside the airspace bounds", v)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Axis aligned box that every reported position must fall within.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bounds {
+    pub min: Vector3,
+    pub max: Vector3,
+}
+
+impl Bounds {
+    ///Create new Bounds from two opposite corners.
+    pub fn new(min: Vector3, max: Vector3) -> Bounds {
+        Bounds { min, max }
+    }
+
+    ///Check whether a vector lies inside the bounds, edges included.
+    pub fn contains(&self, v: Vector3) -> bool {
+        v.x >= self.min.x
+            && v.x <= self.max.x
+            && v.y >= self.min.y
+            && v.y <= self.max.y
+            && v.z >= self.min.z
+            && v.z <= self.max.z
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Vector3 {
     pub x: f32,
@@ -27,6 +78,15 @@ impl Vector3 {
         }
     }
 
+    ///Subtract a vector from the existing vector
+    pub fn sub(&self, a: Vector3) -> Vector3 {
+        Vector3 {
+            x: self.x - a.x,
+            y: self.y - a.y,
+            z: self.z - a.z,
+        }
+    }
+
     ///Calculate the displacement vector towards a target location and with given speed
     pub fn displacement_vector(&self, target: Vector3, speed: f32) -> Vector3 {
         let dx = target.x - self.x;
@@ -65,6 +125,32 @@ impl Vector3 {
         false
     }
 
+    ///Find the time, between now and horizon cycles ahead, at which two points moving in straight
+    ///lines are closest, solved exactly rather than sampled once per cycle.
+    ///Returns the time in cycles and the distance between the points at that time.
+    pub fn closest_approach_within(
+        a: Vector3,
+        a_vel: Vector3,
+        b: Vector3,
+        b_vel: Vector3,
+        horizon: f32,
+    ) -> (f32, f32) {
+        let p = b.sub(a);
+        let v = b_vel.sub(a_vel);
+        let pv = p.x * v.x + p.y * v.y + p.z * v.z;
+        let vv = v.x * v.x + v.y * v.y + v.z * v.z;
+        let time = if vv > 0.0 {
+            (-pv / vv).clamp(0.0, horizon.max(0.0))
+        } else {
+            0.0
+        };
+        let closest = Vector3::new(p.x + v.x * time, p.y + v.y * time, p.z + v.z * time);
+        (
+            time,
+            Vector3::distance(Vector3::new(0.0, 0.0, 0.0), closest),
+        )
+    }
+
     ///Convert Vector3 to a vector of u8.
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
@@ -87,6 +173,26 @@ impl Vector3 {
         let z = f32::from_be_bytes(z_bytes);
         Some(Vector3::new(x, y, z))
     }
+
+    ///Check that no component is NaN or infinite.
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
+
+    ///Create Vector3 from a slice of u8, rejecting non-finite values and values outside bounds.
+    pub fn from_bytes_checked(bytes: &[u8], bounds: &Bounds) -> Result<Vector3, DecodeError> {
+        let v = match Vector3::from_bytes(bytes) {
+            Some(v) => v,
+            None => return Err(DecodeError::TooShort(bytes.len())),
+        };
+        if !v.is_finite() {
+            return Err(DecodeError::NonFinite);
+        }
+        if !bounds.contains(v) {
+            return Err(DecodeError::OutOfBounds(v));
+        }
+        Ok(v)
+    }
 }
 
 impl fmt::Display for Vector3 {
@@ -151,6 +257,16 @@ mod tests {
         assert_eq!(out.z, -4.0);
     }
 
+    #[test]
+    fn test_sub() {
+        let v1 = Vector3::new(1.0, 1.0, 1.0);
+        let v2 = Vector3::new(-5.0, 2.0, 1.0);
+
+        let out = v1.sub(v2);
+
+        assert_eq!(out, Vector3::new(6.0, -1.0, 0.0));
+    }
+
     #[test]
     fn test_displacement_vector() {
         let v1 = Vector3::new(3.0, 4.0, 0.0);
@@ -183,6 +299,58 @@ mod tests {
         assert_eq!(None, actual);
     }
 
+    #[test]
+    fn test_from_bytes_checked() {
+        let bounds = Bounds::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(40.0, 40.0, 40000.0),
+        );
+        let expected = Vector3::new(1.0, 2.0, 3.0);
+
+        let actual = Vector3::from_bytes_checked(&expected.to_bytes(), &bounds);
+
+        assert_eq!(Ok(expected), actual);
+    }
+
+    #[test]
+    fn test_from_bytes_checked_non_finite() {
+        let bounds = Bounds::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(40.0, 40.0, 40000.0),
+        );
+        let nan = Vector3::new(1.0, f32::NAN, 3.0).to_bytes();
+        let inf = Vector3::new(1.0, 2.0, f32::INFINITY).to_bytes();
+
+        assert_eq!(
+            Err(DecodeError::NonFinite),
+            Vector3::from_bytes_checked(&nan, &bounds)
+        );
+        assert_eq!(
+            Err(DecodeError::NonFinite),
+            Vector3::from_bytes_checked(&inf, &bounds)
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_checked_out_of_bounds() {
+        let bounds = Bounds::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(40.0, 40.0, 40000.0),
+        );
+        let far = Vector3::new(1.0e30, 2.0, 3.0);
+        let mut short = far.to_bytes();
+        short.pop();
+
+        assert_eq!(
+            Err(DecodeError::OutOfBounds(far)),
+            Vector3::from_bytes_checked(&far.to_bytes(), &bounds)
+        );
+        assert_eq!(
+            Err(DecodeError::TooShort(11)),
+            Vector3::from_bytes_checked(&short, &bounds)
+        );
+    }
+
     #[test]
     fn test_print() {
         let actual_vec = Vector3::new(1.0, 2.0, 3.0);
@@ -216,4 +384,35 @@ mod tests {
             position_a, a_vel, position_b, b_vel, max_cycles, tolerance
         ))
     }
+
+    #[test]
+    fn test_closest_approach_within() {
+        // Head on at 3 per cycle, passing 0.5 apart halfway between cycles 1 and 2.
+        let position_a = Vector3::new(4.5, 0.0, 0.0);
+        let a_vel = Vector3::new(-3.0, 0.0, 0.0);
+        let position_b = Vector3::new(-4.5, 0.5, 0.0);
+        let b_vel = Vector3::new(3.0, 0.0, 0.0);
+
+        let (time, distance) =
+            Vector3::closest_approach_within(position_a, a_vel, position_b, b_vel, 3.0);
+        assert_eq!(time, 1.5);
+        assert_eq!(distance, 0.5);
+        // Sampled once per cycle the aircraft never seem closer than 3.
+        assert!(!Vector3::will_intersect_in_n_cycles(
+            position_a, a_vel, position_b, b_vel, 3, 3.0
+        ));
+
+        // Clamped to the horizon, and to now once the points move apart.
+        assert_eq!(
+            Vector3::closest_approach_within(position_a, a_vel, position_b, b_vel, 1.0),
+            (
+                1.0,
+                Vector3::distance(Vector3::new(1.5, 0.0, 0.0), Vector3::new(-1.5, 0.5, 0.0))
+            )
+        );
+        assert_eq!(
+            Vector3::closest_approach_within(position_b, a_vel, position_a, b_vel, 3.0),
+            (0.0, Vector3::distance(position_a, position_b))
+        );
+    }
 }
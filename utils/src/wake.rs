@@ -0,0 +1,155 @@
+use core::fmt;
+
+/// Wake turbulence category of an aircraft, declared when it connects.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum WakeCategory {
+    LIGHT = 0,
+    MEDIUM = 1,
+    HEAVY = 2,
+    SUPER = 3,
+}
+
+impl WakeCategory {
+    /// Every category, lightest first.
+    pub const ALL: [WakeCategory; 4] = [
+        WakeCategory::LIGHT,
+        WakeCategory::MEDIUM,
+        WakeCategory::HEAVY,
+        WakeCategory::SUPER,
+    ];
+
+    /// init takes a u8 and gives back a WakeCategory, or None if it doesn't match any of the
+    /// values.
+    pub fn init(in_category: u8) -> Option<WakeCategory> {
+        match in_category {
+            0 => Some(WakeCategory::LIGHT),
+            1 => Some(WakeCategory::MEDIUM),
+            2 => Some(WakeCategory::HEAVY),
+            3 => Some(WakeCategory::SUPER),
+            _ => None,
+        }
+    }
+
+    /// Category from its name, in any case, or None if there is no such category.
+    pub fn from_name(name: &str) -> Option<WakeCategory> {
+        WakeCategory::ALL
+            .into_iter()
+            .find(|c| c.to_string().eq_ignore_ascii_case(name))
+    }
+
+    /// Category declared after the report in the body of a client's first packet.
+    /// Clients that declare none, or an unknown one, are medium.
+    pub fn from_hello(body: &[u8], report_size: usize) -> WakeCategory {
+        body.get(report_size)
+            .and_then(|b| WakeCategory::init(*b))
+            .unwrap_or(WakeCategory::MEDIUM)
+    }
+}
+
+impl fmt::Display for WakeCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WakeCategory::LIGHT => write!(f, "LIGHT"),
+            WakeCategory::MEDIUM => write!(f, "MEDIUM"),
+            WakeCategory::HEAVY => write!(f, "HEAVY"),
+            WakeCategory::SUPER => write!(f, "SUPER"),
+        }
+    }
+}
+
+/// Body of a WAKE packet: the aircraft is, or will be, too close behind and below a leader.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WakeAdvisory {
+    pub leader: u8,
+    /// Distance the category pair requires behind the leader.
+    pub required: f32,
+    /// Distance behind the leader when the wake is entered.
+    pub distance: f32,
+}
+
+impl WakeAdvisory {
+    ///Create a new WakeAdvisory
+    pub fn new(leader: u8, required: f32, distance: f32) -> WakeAdvisory {
+        WakeAdvisory {
+            leader,
+            required,
+            distance,
+        }
+    }
+
+    ///Size of a serialized WakeAdvisory.
+    pub fn size() -> usize {
+        9
+    }
+
+    ///Convert WakeAdvisory to a vector of u8.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.leader];
+        bytes.extend_from_slice(&self.required.to_be_bytes());
+        bytes.extend_from_slice(&self.distance.to_be_bytes());
+        bytes
+    }
+
+    ///Create WakeAdvisory from a slice of u8.
+    pub fn from_bytes(bytes: &[u8]) -> Option<WakeAdvisory> {
+        if bytes.len() < WakeAdvisory::size() {
+            return None;
+        }
+        let required = f32::from_be_bytes(bytes[1..5].try_into().ok()?);
+        let distance = f32::from_be_bytes(bytes[5..9].try_into().ok()?);
+        if !required.is_finite() || !distance.is_finite() {
+            return None;
+        }
+        Some(WakeAdvisory::new(bytes[0], required, distance))
+    }
+}
+
+impl fmt::Display for WakeAdvisory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "wake of {} at {}, {} required",
+            self.leader, self.distance, self.required
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_category_init() {
+        assert_eq!(WakeCategory::init(0), Some(WakeCategory::LIGHT));
+        assert_eq!(WakeCategory::init(3), Some(WakeCategory::SUPER));
+        assert_eq!(WakeCategory::init(4), None);
+    }
+
+    #[test]
+    fn test_category_from_name() {
+        assert_eq!(WakeCategory::from_name("heavy"), Some(WakeCategory::HEAVY));
+        assert_eq!(WakeCategory::from_name("Super"), Some(WakeCategory::SUPER));
+        assert_eq!(WakeCategory::from_name("jumbo"), None);
+    }
+
+    #[test]
+    fn test_category_from_hello() {
+        let mut body = vec![0; 20];
+        assert_eq!(WakeCategory::from_hello(&body, 20), WakeCategory::MEDIUM);
+        body.push(WakeCategory::HEAVY as u8);
+        assert_eq!(WakeCategory::from_hello(&body, 20), WakeCategory::HEAVY);
+        body[20] = 9;
+        assert_eq!(WakeCategory::from_hello(&body, 20), WakeCategory::MEDIUM);
+    }
+
+    #[test]
+    fn test_advisory_byte_conversion() {
+        let expected = WakeAdvisory::new(7, 4.0, 2.5);
+
+        let bytes = expected.to_bytes();
+
+        assert_eq!(bytes.len(), WakeAdvisory::size());
+        assert_eq!(WakeAdvisory::from_bytes(&bytes), Some(expected));
+        assert_eq!(WakeAdvisory::from_bytes(&bytes[..8]), None);
+    }
+}